@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Every 3 bytes encode one op, see `kesh::fuzzing`.
+fuzz_target!(|data: &[u8]| kesh::fuzzing::run(data));
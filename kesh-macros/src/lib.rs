@@ -0,0 +1,365 @@
+//! Procedural macros for kesh. Use them through the `memoize` feature of the
+//! `kesh` crate rather than depending on this crate directly.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Spacing, Span, TokenStream, TokenTree};
+
+/// Memoizes a function with a process-wide `kesh::S3FIFO` cache.
+///
+/// ```ignore
+/// #[kesh::memoize(capacity = 1000, weigher = |value: &String| value.len(), ttl = Duration::from_secs(60))]
+/// fn render(id: u64) -> String {
+///     expensive_render(id)
+/// }
+/// ```
+///
+/// All options are optional:
+///
+/// - `capacity`: capacity of the cache, 1024 by default.
+/// - `weigher`: callable taking `&Return` and returning its `usize` weight.
+///   Every result weighs 1 by default.
+/// - `ttl`: `std::time::Duration` after which a result is recomputed.
+///
+/// The arguments, cloned into a tuple, form the key, so they must be owned
+/// and implement `Clone`, `Eq` and `Hash`. The return type must implement
+/// `Clone`. Generic, `async` and `const` functions and methods are not
+/// supported. The cache is not locked while the function runs, so recursive
+/// calls are memoized too.
+#[proc_macro_attribute]
+pub fn memoize(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match expand(attr, item) {
+        Ok(tokens) => tokens,
+        Err(error) => error.into_compile_error(),
+    }
+}
+
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: &str) -> Self {
+        Error {
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    fn into_compile_error(self) -> TokenStream {
+        let mut tokens = code("::core::compile_error!");
+        tokens.extend([TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            TokenStream::from(TokenTree::Literal(Literal::string(&self.message))),
+        ))]);
+        tokens.extend(code(";"));
+        tokens
+            .into_iter()
+            .map(|mut token| {
+                token.set_span(self.span);
+                token
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    capacity: Option<TokenStream>,
+    weigher: Option<TokenStream>,
+    ttl: Option<TokenStream>,
+}
+
+struct Argument {
+    name: Ident,
+    ty: TokenStream,
+    original: TokenStream,
+}
+
+fn code(source: &str) -> TokenStream {
+    source.parse().unwrap()
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    matches!(token, TokenTree::Punct(punct) if punct.as_char() == c)
+}
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    matches!(token, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+/// Splits `tokens` at `separator`, ignoring separators nested in angle
+/// brackets. Groups are single tokens, so they never need tracking.
+fn split_top_level(tokens: Vec<TokenTree>, separator: char) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![];
+    let mut current = vec![];
+    let mut depth = 0;
+    let mut after_joint_dash = false;
+    for token in tokens {
+        if let TokenTree::Punct(punct) = &token {
+            let c = punct.as_char();
+            if c == separator && depth == 0 {
+                parts.push(std::mem::take(&mut current));
+                after_joint_dash = false;
+                continue;
+            }
+            match c {
+                '<' => depth += 1,
+                '>' if !after_joint_dash && depth > 0 => depth -= 1,
+                _ => {}
+            }
+            after_joint_dash = c == '-' && punct.spacing() == Spacing::Joint;
+        } else {
+            after_joint_dash = false;
+        }
+        current.push(token);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+fn parse_options(attr: TokenStream) -> Result<Options, Error> {
+    let mut options = Options::default();
+    for option in split_top_level(attr.into_iter().collect(), ',') {
+        let (name, value) = match option.as_slice() {
+            [TokenTree::Ident(name), eq, value @ ..] if is_punct(eq, '=') && !value.is_empty() => {
+                (name, value.iter().cloned().collect::<TokenStream>())
+            }
+            [token, ..] => return Err(Error::new(token.span(), "expected `option = value`")),
+            [] => continue,
+        };
+        let slot = match name.to_string().as_str() {
+            "capacity" => &mut options.capacity,
+            "weigher" => &mut options.weigher,
+            "ttl" => &mut options.ttl,
+            _ => {
+                return Err(Error::new(
+                    name.span(),
+                    "unknown option, expected `capacity`, `weigher` or `ttl`",
+                ))
+            }
+        };
+        if slot.replace(value).is_some() {
+            return Err(Error::new(name.span(), "option given more than once"));
+        }
+    }
+    Ok(options)
+}
+
+fn parse_argument(tokens: Vec<TokenTree>) -> Result<Argument, Error> {
+    let original: TokenStream = tokens.iter().cloned().collect();
+    let colon = tokens
+        .iter()
+        .position(|token| is_punct(token, ':'))
+        .ok_or_else(|| Error::new(tokens[0].span(), "methods are not supported"))?;
+    let name = match &tokens[..colon] {
+        [TokenTree::Ident(name)] => name.clone(),
+        [mutability, TokenTree::Ident(name)] if is_ident(mutability, "mut") => name.clone(),
+        _ => {
+            return Err(Error::new(
+                tokens[0].span(),
+                "only plain identifiers are supported as argument patterns",
+            ))
+        }
+    };
+    if name.to_string() == "self" {
+        return Err(Error::new(name.span(), "methods are not supported"));
+    }
+    let ty = &tokens[colon + 1..];
+    match ty.first() {
+        None => return Err(Error::new(name.span(), "expected an argument type")),
+        Some(token) if is_punct(token, '&') => {
+            return Err(Error::new(
+                token.span(),
+                "memoized arguments become the cache key and must be owned",
+            ))
+        }
+        Some(_) => {}
+    }
+
+    Ok(Argument {
+        name,
+        ty: ty.iter().cloned().collect(),
+        original,
+    })
+}
+
+fn expand(attr: TokenStream, item: TokenStream) -> Result<TokenStream, Error> {
+    let options = parse_options(attr)?;
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+
+    let fn_index = tokens
+        .iter()
+        .position(|token| is_ident(token, "fn"))
+        .ok_or_else(|| Error::new(Span::call_site(), "expected a function"))?;
+    if let Some(token) = tokens[..fn_index]
+        .iter()
+        .find(|token| is_ident(token, "async") || is_ident(token, "const"))
+    {
+        return Err(Error::new(
+            token.span(),
+            "async and const functions are not supported",
+        ));
+    }
+    let name = match tokens.get(fn_index + 1) {
+        Some(TokenTree::Ident(name)) => name.clone(),
+        _ => {
+            return Err(Error::new(
+                tokens[fn_index].span(),
+                "expected a function name",
+            ))
+        }
+    };
+    let arguments = match tokens.get(fn_index + 2) {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group,
+        Some(token) if is_punct(token, '<') => {
+            return Err(Error::new(
+                token.span(),
+                "generic functions are not supported",
+            ))
+        }
+        _ => return Err(Error::new(name.span(), "expected function arguments")),
+    };
+    let body = match tokens.last() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group,
+        _ => return Err(Error::new(name.span(), "expected a function body")),
+    };
+    let signature = &tokens[fn_index + 3..tokens.len() - 1];
+    if let Some(token) = signature.iter().find(|token| is_ident(token, "where")) {
+        return Err(Error::new(
+            token.span(),
+            "generic functions are not supported",
+        ));
+    }
+    let return_type: TokenStream = match signature {
+        [dash, arrow, return_type @ ..]
+            if is_punct(dash, '-') && is_punct(arrow, '>') && !return_type.is_empty() =>
+        {
+            return_type.iter().cloned().collect()
+        }
+        _ => {
+            return Err(Error::new(
+                name.span(),
+                "memoized functions must return a value",
+            ))
+        }
+    };
+    let arguments = split_top_level(arguments.stream().into_iter().collect(), ',')
+        .into_iter()
+        .filter(|argument| !argument.is_empty())
+        .map(parse_argument)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut outer_arguments = TokenStream::new();
+    let mut inner_arguments = TokenStream::new();
+    let mut key_type = TokenStream::new();
+    let mut key = TokenStream::new();
+    let mut names = TokenStream::new();
+    for argument in &arguments {
+        let name = TokenTree::Ident(argument.name.clone());
+        outer_arguments.extend([name.clone()]);
+        outer_arguments.extend(code(":"));
+        outer_arguments.extend(argument.ty.clone());
+        outer_arguments.extend(code(","));
+        inner_arguments.extend(argument.original.clone());
+        inner_arguments.extend(code(","));
+        key_type.extend(argument.ty.clone());
+        key_type.extend(code(","));
+        key.extend(code("::std::clone::Clone::clone"));
+        key.extend([paren(code("&").into_iter().chain([name.clone()]).collect())]);
+        key.extend(code(","));
+        names.extend([name]);
+        names.extend(code(","));
+    }
+
+    let capacity = options.capacity.unwrap_or_else(|| code("1024"));
+    let weight = match options.weigher {
+        Some(weigher) => {
+            let mut weight = TokenStream::from(paren(weigher));
+            weight.extend([paren(code("&__kesh_value"))]);
+            weight
+        }
+        None => code("1"),
+    };
+    let hit = match options.ttl {
+        Some(ttl) => {
+            let mut check = code("if __kesh_stored_at.elapsed() <");
+            check.extend([paren(ttl)]);
+            check.extend(code("{ return __kesh_value; }"));
+            let mut hit = code(
+                "if let ::std::option::Option::Some((__kesh_value, __kesh_stored_at)) = __kesh_hit",
+            );
+            hit.extend([brace(check)]);
+            hit
+        }
+        None => code(
+            "if let ::std::option::Option::Some((__kesh_value, _)) = __kesh_hit { return __kesh_value; }",
+        ),
+    };
+
+    let mut cached_type = TokenStream::from(paren(key_type));
+    cached_type.extend(code(","));
+    cached_type.extend([paren(
+        return_type
+            .clone()
+            .into_iter()
+            .chain(code(", ::std::time::Instant"))
+            .collect(),
+    )]);
+
+    let mut inner = code("fn __kesh_memoized");
+    inner.extend([paren(inner_arguments)]);
+    inner.extend(code("->"));
+    inner.extend(return_type.clone());
+    inner.extend([TokenTree::Group(body.clone())]);
+
+    let mut block = inner;
+    block.extend(code(
+        "static __KESH_CACHE: ::std::sync::OnceLock<::std::sync::Mutex<::kesh::S3FIFO<",
+    ));
+    block.extend(cached_type);
+    block.extend(code(">>> = ::std::sync::OnceLock::new();"));
+    let mut cache = code("::kesh::S3FIFO::new");
+    cache.extend([paren(capacity)]);
+    let mut init = code("|| ::std::sync::Mutex::new");
+    init.extend([paren(cache)]);
+    block.extend(code("let __kesh_cache = __KESH_CACHE.get_or_init"));
+    block.extend([paren(init)]);
+    block.extend(code(";"));
+    block.extend(code("let __kesh_key = "));
+    block.extend([paren(key)]);
+    block.extend(code(";"));
+    block.extend(code(
+        "let __kesh_hit = __kesh_cache.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).get_cloned(&__kesh_key);",
+    ));
+    block.extend(hit);
+    block.extend(code("let __kesh_value = __kesh_memoized"));
+    block.extend([paren(names)]);
+    block.extend(code(";"));
+    block.extend(code("let __kesh_weight: usize = "));
+    block.extend(weight);
+    block.extend(code(";"));
+    block.extend(code(
+        "let _ = __kesh_cache.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).put(&__kesh_key, (::std::clone::Clone::clone(&__kesh_value), ::std::time::Instant::now()), __kesh_weight);",
+    ));
+    block.extend(code("__kesh_value"));
+
+    let mut output: TokenStream = tokens[..fn_index].iter().cloned().collect();
+    output.extend(code("fn"));
+    output.extend([TokenTree::Ident(name)]);
+    output.extend([paren(outer_arguments)]);
+    output.extend(code("->"));
+    output.extend(return_type);
+    output.extend([brace(block)]);
+
+    Ok(output)
+}
+
+fn paren(tokens: TokenStream) -> TokenTree {
+    TokenTree::Group(Group::new(Delimiter::Parenthesis, tokens))
+}
+
+fn brace(tokens: TokenStream) -> TokenTree {
+    TokenTree::Group(Group::new(Delimiter::Brace, tokens))
+}
@@ -0,0 +1,350 @@
+//! Replays a trace file through cache policies and prints their hit ratios.
+//!
+//! ```text
+//! kesh-sim [--format csv|twitter|oracleGeneral] [--capacity 1000,10000]
+//!          [--policy s3fifo,s3fifo-no-ghost,lru] [--csv] TRACE
+//! ```
+
+use kesh::sim::{Op, Request, TraceFormat};
+use kesh::S3FIFO;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+const USAGE: &str = "usage: kesh-sim [--format csv|twitter|oracleGeneral] \
+[--capacity N[,N...]] [--policy s3fifo|s3fifo-no-ghost|lru[,...]] [--csv] TRACE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PolicyKind {
+    S3FIFO,
+    S3FIFONoGhost,
+    Lru,
+}
+
+impl FromStr for PolicyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s3fifo" => Ok(PolicyKind::S3FIFO),
+            "s3fifo-no-ghost" => Ok(PolicyKind::S3FIFONoGhost),
+            "lru" => Ok(PolicyKind::Lru),
+            _ => Err(format!("unknown policy {s:?}")),
+        }
+    }
+}
+
+impl PolicyKind {
+    fn name(self) -> &'static str {
+        match self {
+            PolicyKind::S3FIFO => "s3fifo",
+            PolicyKind::S3FIFONoGhost => "s3fifo-no-ghost",
+            PolicyKind::Lru => "lru",
+        }
+    }
+
+    fn build(self, capacity: usize) -> Box<dyn Policy> {
+        match self {
+            PolicyKind::S3FIFO => Box::new(S3FIFO::new(capacity)),
+            PolicyKind::S3FIFONoGhost => {
+                Box::new(S3FIFO::builder(capacity).without_ghost().build())
+            }
+            PolicyKind::Lru => Box::new(Lru::new(capacity)),
+        }
+    }
+}
+
+/// Cache under evaluation, keyed by object id and weighted by object size.
+trait Policy {
+    fn get(&mut self, key: u64) -> bool;
+
+    /// Returns the number of evicted objects.
+    fn put(&mut self, key: u64, weight: usize) -> usize;
+
+    fn remove(&mut self, key: u64);
+}
+
+impl Policy for S3FIFO<u64, ()> {
+    fn get(&mut self, key: u64) -> bool {
+        S3FIFO::get(self, &key).is_some()
+    }
+
+    fn put(&mut self, key: u64, weight: usize) -> usize {
+        S3FIFO::put(self, &key, (), weight)
+            .ok()
+            .flatten()
+            .map_or(0, |evicted| evicted.len())
+    }
+
+    fn remove(&mut self, key: u64) {
+        S3FIFO::remove(self, &key);
+    }
+}
+
+/// Baseline LRU, evicting the least recently used objects first.
+struct Lru {
+    entries: HashMap<u64, (u64, usize)>,
+    recency: BTreeMap<u64, u64>,
+    used_capacity: usize,
+    capacity: usize,
+    tick: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Lru {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            used_capacity: 0,
+            capacity,
+            tick: 0,
+        }
+    }
+
+    fn touch(&mut self, key: u64) -> u64 {
+        self.tick += 1;
+        self.recency.insert(self.tick, key);
+        self.tick
+    }
+}
+
+impl Policy for Lru {
+    fn get(&mut self, key: u64) -> bool {
+        let Some((tick, _)) = self.entries.get(&key).copied() else {
+            return false;
+        };
+        self.recency.remove(&tick);
+        let tick = self.touch(key);
+        self.entries.get_mut(&key).unwrap().0 = tick;
+        true
+    }
+
+    fn put(&mut self, key: u64, weight: usize) -> usize {
+        self.remove(key);
+        if weight > self.capacity {
+            return 0;
+        }
+
+        let mut evicted = 0;
+        while self.used_capacity + weight > self.capacity {
+            let (_, victim) = self.recency.pop_first().unwrap();
+            let (_, victim_weight) = self.entries.remove(&victim).unwrap();
+            self.used_capacity -= victim_weight;
+            evicted += 1;
+        }
+        let tick = self.touch(key);
+        self.entries.insert(key, (tick, weight));
+        self.used_capacity += weight;
+        evicted
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some((tick, weight)) = self.entries.remove(&key) {
+            self.recency.remove(&tick);
+            self.used_capacity -= weight;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    requests: u64,
+    hits: u64,
+    bytes_requested: u64,
+    bytes_hit: u64,
+    evictions: u64,
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 / total as f64
+}
+
+/// Runs `requests` through `policy` like [`kesh::sim::replay`], counting
+/// evictions too.
+fn run<I>(policy: &mut dyn Policy, requests: I) -> io::Result<Stats>
+where
+    I: IntoIterator<Item = io::Result<Request>>,
+{
+    let mut stats = Stats::default();
+    for request in requests {
+        let request = request?;
+        let weight = request.size.max(1);
+        match request.op {
+            Op::Get => {
+                stats.requests += 1;
+                stats.bytes_requested += request.size as u64;
+                if policy.get(request.key) {
+                    stats.hits += 1;
+                    stats.bytes_hit += request.size as u64;
+                } else {
+                    stats.evictions += policy.put(request.key, weight) as u64;
+                }
+            }
+            Op::Set => stats.evictions += policy.put(request.key, weight) as u64,
+            Op::Delete => policy.remove(request.key),
+        }
+    }
+
+    Ok(stats)
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    format: TraceFormat,
+    capacities: Vec<usize>,
+    policies: Vec<PolicyKind>,
+    csv: bool,
+    trace: String,
+}
+
+fn parse_list<T: FromStr>(list: &str) -> Result<Vec<T>, String> {
+    list.split(',')
+        .map(|item| item.parse().map_err(|_| format!("invalid value {item:?}")))
+        .collect()
+}
+
+fn parse_args<I>(args: I) -> Result<Args, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut format = TraceFormat::Csv;
+    let mut capacities = vec![1_000];
+    let mut policies = vec![PolicyKind::S3FIFO, PolicyKind::Lru];
+    let mut csv = false;
+    let mut trace = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "--format" => format = value()?.parse()?,
+            "--capacity" => capacities = parse_list(&value()?)?,
+            "--policy" => {
+                policies = value()?
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?;
+            }
+            "--csv" => csv = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => trace = Some(arg),
+        }
+    }
+
+    Ok(Args {
+        format,
+        capacities,
+        policies,
+        csv,
+        trace: trace.ok_or("missing trace file")?,
+    })
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{error}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.csv {
+        println!("policy,capacity,requests,hit_ratio,byte_hit_ratio,evictions");
+    } else {
+        println!(
+            "{:<16} {:>12} {:>12} {:>10} {:>15} {:>12}",
+            "policy", "capacity", "requests", "hit ratio", "byte hit ratio", "evictions"
+        );
+    }
+
+    for &capacity in &args.capacities {
+        for &policy in &args.policies {
+            let stats = File::open(&args.trace)
+                .and_then(|file| run(&mut *policy.build(capacity), args.format.reader(file)));
+            let stats = match stats {
+                Ok(stats) => stats,
+                Err(error) => {
+                    eprintln!("{}: {error}", args.trace);
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            let hit_ratio = ratio(stats.hits, stats.requests);
+            let byte_hit_ratio = ratio(stats.bytes_hit, stats.bytes_requested);
+            if args.csv {
+                println!(
+                    "{},{capacity},{},{hit_ratio:.6},{byte_hit_ratio:.6},{}",
+                    policy.name(),
+                    stats.requests,
+                    stats.evictions
+                );
+            } else {
+                println!(
+                    "{:<16} {capacity:>12} {:>12} {hit_ratio:>10.4} {byte_hit_ratio:>15.4} {:>12}",
+                    policy.name(),
+                    stats.requests,
+                    stats.evictions
+                );
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(ToString::to_string))
+    }
+
+    #[test]
+    fn it_should_parse_args() {
+        let parsed = args(&[
+            "--capacity",
+            "10,20",
+            "--policy",
+            "lru",
+            "--csv",
+            "trace.csv",
+        ]);
+
+        assert_eq!(
+            parsed,
+            Ok(Args {
+                format: TraceFormat::Csv,
+                capacities: vec![10, 20],
+                policies: vec![PolicyKind::Lru],
+                csv: true,
+                trace: "trace.csv".to_string(),
+            })
+        );
+        assert!(args(&["--policy", "arc", "trace.csv"]).is_err());
+        assert!(args(&["--csv"]).is_err());
+    }
+
+    #[test]
+    fn it_should_evict_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.put(1, 1);
+        lru.put(2, 1);
+        lru.get(1);
+
+        assert_eq!(lru.put(3, 1), 1);
+        assert!(lru.get(1));
+        assert!(!lru.get(2));
+    }
+}
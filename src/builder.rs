@@ -0,0 +1,916 @@
+use crate::changes::ChangeFeed;
+use crate::events::Publisher;
+use crate::expiry::{Clock, Expiry};
+use crate::fifo::FIFO;
+use crate::fifo_reinserion::FIFOReinsertion;
+use crate::ghost_fifo::GhostFIFO;
+use crate::hot_keys::HotKeys;
+use crate::lifetime::Lifetimes;
+use crate::listener::{Listener, RefListener};
+use crate::pool::PoolMember;
+use crate::quota::{Quotas, TenantOf};
+use crate::replication::Replicator;
+use crate::shadow::ShadowLru;
+use crate::version::Versions;
+use crate::{
+    CacheObserver, CacheStats, CapacityPool, Classifier, CloneKey, ConfigError, DefaultState,
+    GhostSizing, Mutation, RemovalCause, SegmentSizes, Weigher, S3FIFO,
+};
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The builder options sizing the queues of a cache, kept by the cache to
+/// build another one alike, see [`S3FIFO::split_off`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Layout {
+    capacity: usize,
+    ratios: (u8, u8),
+    ghost: bool,
+    ghost_sizing: GhostSizing,
+    ghost_capacity: Option<usize>,
+    hard_capacity: Option<usize>,
+}
+
+pub struct S3FIFOBuilder<K, V, S = DefaultState> {
+    capacity: usize,
+    ghost_sizing: GhostSizing,
+    ghost_capacity: Option<usize>,
+    hard_capacity: Option<usize>,
+    ratios: (u8, u8),
+    ghost: bool,
+    listener: Option<RefListener<K, V>>,
+    owning_listener: Option<Listener<K, V>>,
+    observer: Option<Box<dyn CacheObserver<K>>>,
+    hot_keys: Option<usize>,
+    shadow_lru: Option<u64>,
+    generations: Option<u64>,
+    small_queues: usize,
+    classifier: Option<Classifier<K>>,
+    weigher: Option<Weigher<K, V>>,
+    tenant: Option<TenantOf<K>>,
+    tenant_quota: Option<usize>,
+    tenant_shares: Option<HashMap<u64, u64>>,
+    versioned: bool,
+    lifetimes: Option<usize>,
+    change_feed: Option<usize>,
+    replication: Option<Replicator<K, V>>,
+    pool: Option<PoolMember>,
+    clock: Option<Clock>,
+    clone_key: Option<CloneKey<K>>,
+    hasher: S,
+}
+
+impl<K, V, S> Debug for S3FIFOBuilder<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3FIFOBuilder")
+            .field("capacity", &self.capacity)
+            .field("ghost_sizing", &self.ghost_sizing)
+            .field("ghost_capacity", &self.ghost_capacity)
+            .field("hard_capacity", &self.hard_capacity)
+            .field("ratios", &self.ratios)
+            .field("ghost", &self.ghost)
+            .field("listener", &self.listener.is_some())
+            .field("owning_listener", &self.owning_listener.is_some())
+            .field("observer", &self.observer.is_some())
+            .field("hot_keys", &self.hot_keys)
+            .field("shadow_lru", &self.shadow_lru)
+            .field("generations", &self.generations)
+            .field("small_queues", &self.small_queues)
+            .field("weigher", &self.weigher.is_some())
+            .field("tenant_quota", &self.tenant_quota)
+            .field("tenant_shares", &self.tenant_shares)
+            .field("versioned", &self.versioned)
+            .field("lifetimes", &self.lifetimes)
+            .field("change_feed", &self.change_feed)
+            .field("replication", &self.replication.is_some())
+            .field("pool", &self.pool.is_some())
+            .field("clock", &self.clock.is_some())
+            .finish()
+    }
+}
+
+impl<K, V> S3FIFOBuilder<K, V> {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ghost_sizing: GhostSizing::default(),
+            ghost_capacity: None,
+            hard_capacity: None,
+            ratios: (10, 90),
+            ghost: true,
+            listener: None,
+            owning_listener: None,
+            observer: None,
+            hot_keys: None,
+            shadow_lru: None,
+            generations: None,
+            small_queues: 1,
+            classifier: None,
+            weigher: None,
+            tenant: None,
+            tenant_quota: None,
+            tenant_shares: None,
+            versioned: false,
+            lifetimes: None,
+            change_feed: None,
+            replication: None,
+            pool: None,
+            clock: None,
+            clone_key: None,
+            hasher: DefaultState::default(),
+        }
+    }
+}
+
+impl<K, V, S> S3FIFOBuilder<K, V, S> {
+    /// Hashes keys with hashers built by `hasher`, for example
+    /// [`crate::BuildIdentityHasher`] for integer keys.
+    #[must_use]
+    pub fn hasher<H>(self, hasher: H) -> S3FIFOBuilder<K, V, H> {
+        S3FIFOBuilder {
+            capacity: self.capacity,
+            ghost_sizing: self.ghost_sizing,
+            ghost_capacity: self.ghost_capacity,
+            hard_capacity: self.hard_capacity,
+            ratios: self.ratios,
+            ghost: self.ghost,
+            listener: self.listener,
+            owning_listener: self.owning_listener,
+            observer: self.observer,
+            hot_keys: self.hot_keys,
+            shadow_lru: self.shadow_lru,
+            generations: self.generations,
+            small_queues: self.small_queues,
+            classifier: self.classifier,
+            weigher: self.weigher,
+            tenant: self.tenant,
+            tenant_quota: self.tenant_quota,
+            tenant_shares: self.tenant_shares,
+            versioned: self.versioned,
+            lifetimes: self.lifetimes,
+            change_feed: self.change_feed,
+            replication: self.replication,
+            pool: self.pool,
+            clock: self.clock,
+            clone_key: self.clone_key,
+            hasher,
+        }
+    }
+
+    #[must_use]
+    pub fn ghost_sizing(mut self, ghost_sizing: GhostSizing) -> Self {
+        self.ghost_sizing = ghost_sizing;
+        self
+    }
+
+    /// Sizes the ghost queue to `capacity`, instead of following
+    /// [`S3FIFOBuilder::ghost_sizing`]. It can't be below the capacity of the
+    /// small queues.
+    #[must_use]
+    pub fn ghost_capacity(mut self, capacity: usize) -> Self {
+        self.ghost_capacity = Some(capacity);
+        self
+    }
+
+    /// Lets puts overcommit the capacity, taken as a soft limit, up to `hard`
+    /// before they evict, so bursts don't pay for long eviction loops
+    /// inline. [`S3FIFO::maintain`] evicts back down to the capacity. The
+    /// overcommit window is split between the queues like the capacity.
+    #[must_use]
+    pub fn hard_capacity(mut self, hard: usize) -> Self {
+        self.hard_capacity = Some(hard);
+        self
+    }
+
+    /// Gives `small` percent of the capacity to the small queues and `main`
+    /// percent to the main queue, 10 and 90 by default. They can't add up to
+    /// more than 100.
+    #[must_use]
+    pub fn ratios(mut self, small: u8, main: u8) -> Self {
+        self.ratios = (small, main);
+        self
+    }
+
+    /// Disables the ghost queue. Keys evicted from the small queue are
+    /// forgotten right away, so the cache degrades to a FIFO in front of a
+    /// FIFO-reinsertion queue.
+    #[must_use]
+    pub fn without_ghost(mut self) -> Self {
+        self.ghost = false;
+        self
+    }
+
+    /// Calls `listener` with the key, value, weight and [`RemovalCause`] of
+    /// every entry that leaves the cache, before the value is dropped.
+    ///
+    /// Entries moving between queues don't leave the cache, so the listener
+    /// isn't called when a hit entry is promoted to the main queue.
+    #[must_use]
+    pub fn eviction_listener_ref<F>(mut self, listener: F) -> Self
+    where
+        F: FnMut(&K, &V, usize, RemovalCause) + Send + 'static,
+    {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Hands `listener` the value of every entry that leaves the cache, with
+    /// its key, weight and [`RemovalCause`], so victims can be moved
+    /// elsewhere without a copy. It's called after the listener of
+    /// [`S3FIFOBuilder::eviction_listener_ref`], once the operation that
+    /// removed the entries is done.
+    ///
+    /// The entries handed back by [`S3FIFO::put_owned`] are the caller's, so
+    /// the listener isn't called with them.
+    #[must_use]
+    pub fn eviction_listener<F>(mut self, listener: F) -> Self
+    where
+        F: FnMut(&K, V, usize, RemovalCause) + Send + 'static,
+    {
+        self.owning_listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Installs `observer`, whose hooks are called on every cache operation.
+    #[must_use]
+    pub fn observer<O>(mut self, observer: O) -> Self
+    where
+        O: CacheObserver<K> + 'static,
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Counts lookups in a heavy hitters sketch of `counters` keys, reported
+    /// by [`S3FIFO::hot_keys`]. More counters give more accurate counts.
+    #[must_use]
+    pub fn hot_keys(mut self, counters: usize) -> Self
+    where
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.hot_keys = Some(counters);
+        self
+    }
+
+    /// Runs a metadata-only LRU over one key in `one_in`, with the same share
+    /// of the capacity, and compares its hit ratio with the one of the cache.
+    /// 100 samples 1% of the keys. See [`S3FIFO::shadow_stats`].
+    #[must_use]
+    pub fn shadow_lru(mut self, one_in: u64) -> Self
+    where
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.shadow_lru = Some(one_in);
+        self
+    }
+
+    /// Lets [`S3FIFO::rotate`] evict the main entries that went unused for
+    /// `generations` rotations, so no entry stays in the main queue on the
+    /// strength of old hits alone.
+    #[must_use]
+    pub fn generations(mut self, generations: u64) -> Self {
+        self.generations = Some(generations);
+        self
+    }
+
+    /// Splits the small queue into `count` independent queues of equal
+    /// capacity, feeding the same main queue. `classifier` picks the queue of
+    /// every key, modulo `count`, so a scan of keys in one queue can't flush
+    /// the new entries of another one.
+    #[must_use]
+    pub fn small_queues<F>(self, count: usize, classifier: F) -> Self
+    where
+        F: Fn(&K) -> usize + Send + Sync + 'static,
+    {
+        self.classifier(count, Some(Arc::new(classifier)))
+    }
+
+    #[must_use]
+    pub(crate) fn classifier(mut self, count: usize, classifier: Option<Classifier<K>>) -> Self {
+        self.small_queues = count.max(1);
+        self.classifier = classifier;
+        self
+    }
+
+    /// Weighs the entries of [`S3FIFO::insert`] with `weigher`, for example
+    /// by the size of the value, instead of passing a weight on every put.
+    #[must_use]
+    pub fn weigher<F>(self, weigher: F) -> Self
+    where
+        F: Fn(&K, &V) -> usize + Send + Sync + 'static,
+    {
+        self.with_weigher(Some(Arc::new(weigher)))
+    }
+
+    /// Sizes the queues like `layout` does for its capacity. The ghost
+    /// capacity and the overcommit window scale with the capacity.
+    #[must_use]
+    pub(crate) fn with_layout(mut self, layout: Layout) -> Self {
+        let scale = |weight: usize| {
+            (weight as u128 * self.capacity as u128 / layout.capacity.max(1) as u128) as usize
+        };
+        self.ratios = layout.ratios;
+        self.ghost = layout.ghost;
+        self.ghost_sizing = layout.ghost_sizing;
+        self.hard_capacity = layout
+            .hard_capacity
+            .map(|hard| self.capacity + scale(hard - layout.capacity));
+        let small = self.segment_sizes().small;
+        self.ghost_capacity = layout.ghost_capacity.map(|ghost| scale(ghost).max(small));
+        self
+    }
+
+    #[must_use]
+    pub(crate) fn with_weigher(mut self, weigher: Option<Weigher<K, V>>) -> Self {
+        self.weigher = weigher;
+        self
+    }
+
+    /// Caps the weight of the entries of every tenant at `quota`. `tenant`
+    /// derives the tenant of a key. A put that would take a tenant over its
+    /// quota evicts the oldest entries of that tenant first, so a noisy
+    /// tenant can't evict the entries of the others.
+    ///
+    /// Preloading, merging and splitting don't enforce quotas.
+    #[must_use]
+    pub fn tenant_quota<F>(mut self, quota: usize, tenant: F) -> Self
+    where
+        F: Fn(&K) -> u64 + Send + Sync + 'static,
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.tenant = Some(Arc::new(tenant));
+        self.tenant_quota = Some(quota);
+        self
+    }
+
+    /// Spreads eviction pressure across tenants in proportion to `shares`,
+    /// tenants without one get a share of 1. `tenant` derives the tenant of a
+    /// key, and replaces the one given to [`S3FIFOBuilder::tenant_quota`].
+    ///
+    /// Tenants may use free space beyond their share. Once a queue is full, a
+    /// put evicts the oldest entries of the tenant furthest above its share
+    /// before the queue evicts as usual. See [`S3FIFO::tenant_stats`].
+    #[must_use]
+    pub fn tenant_shares<I, F>(mut self, shares: I, tenant: F) -> Self
+    where
+        I: IntoIterator<Item = (u64, u64)>,
+        F: Fn(&K) -> u64 + Send + Sync + 'static,
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.tenant = Some(Arc::new(tenant));
+        self.tenant_shares = Some(shares.into_iter().collect());
+        self
+    }
+
+    #[must_use]
+    pub(crate) fn tenants(
+        mut self,
+        tenant: TenantOf<K>,
+        quota: Option<usize>,
+        shares: Option<HashMap<u64, u64>>,
+    ) -> Self
+    where
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.tenant = Some(tenant);
+        self.tenant_quota = quota;
+        self.tenant_shares = shares;
+        self
+    }
+
+    /// Gives every entry a version, bumped on every put of its key and
+    /// reported by [`S3FIFO::version`], so writers can detect lost updates
+    /// with [`S3FIFO::compare_and_put`].
+    #[must_use]
+    pub fn versioned(mut self) -> Self
+    where
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.versioned = true;
+        self
+    }
+
+    /// Records how long every evicted entry lived and how often it was hit,
+    /// keeping the last `samples` evictions of every segment for the
+    /// percentiles of [`S3FIFO::lifetime_stats`].
+    #[must_use]
+    pub fn lifetimes(mut self, samples: usize) -> Self
+    where
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.lifetimes = Some(samples);
+        self
+    }
+
+    /// Numbers every change of the cache with a generation and keeps the last
+    /// `capacity` ones, so a replica or an index can catch up with
+    /// [`S3FIFO::changes_since`] instead of rescanning the cache.
+    #[must_use]
+    pub fn change_feed(mut self, capacity: usize) -> Self
+    where
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.change_feed = Some(capacity);
+        self
+    }
+
+    /// Calls `sink` with every put, removal and eviction, in the order they
+    /// happen, for a standby cache or a mirror in another process to replay
+    /// with [`S3FIFO::apply`]. A put is told after the evictions it caused.
+    /// Writes deferred by [`S3FIFO::freeze`] are told once applied.
+    #[must_use]
+    pub fn replication<F>(mut self, sink: F) -> Self
+    where
+        F: FnMut(Mutation<&K, &V>) + Send + 'static,
+        K: Clone,
+    {
+        self.clone_key = Some(K::clone);
+        self.replication = Some(Box::new(sink));
+        self
+    }
+
+    /// Draws the weight of the entries from `pool` too, with `share` setting
+    /// the fair share of the budget owed to the cache, at least 1. The
+    /// capacity still bounds the cache on its own.
+    #[must_use]
+    pub fn capacity_pool(mut self, pool: &CapacityPool, share: u64) -> Self {
+        self.pool = Some(pool.join(share));
+        self
+    }
+
+    /// Reads the current time from `clock` instead of [`Instant::now`] to
+    /// expire the entries of [`S3FIFO::put_with_ttl`], for example to test
+    /// expiration without waiting.
+    #[must_use]
+    pub fn clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> Instant + Send + Sync + 'static,
+    {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Capacities the cache will give to its queues, following
+    /// [`S3FIFOBuilder::ratios`]: the small queues get their share rounded
+    /// down, at least 1 each, and the main queue its share rounded up, at
+    /// least 1, within what's left.
+    #[must_use]
+    pub fn segment_sizes(&self) -> SegmentSizes {
+        let (small_ratio, main_ratio) = self.ratios;
+        let small = percent(self.capacity, small_ratio, false).max(self.small_queues);
+        let main = percent(self.capacity, main_ratio, true)
+            .max(1)
+            .min(self.capacity.saturating_sub(small));
+        let ghost = self.ghost_capacity.unwrap_or(match self.ghost_sizing {
+            GhostSizing::Main => main,
+            GhostSizing::Small => small,
+        });
+        SegmentSizes {
+            small,
+            main,
+            ghost: self.ghost.then_some(ghost),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see
+    /// [`S3FIFOBuilder::try_build`].
+    #[must_use]
+    pub fn build(self) -> S3FIFO<K, V, S>
+    where
+        K: Eq + Hash,
+        S: BuildHasher + Clone,
+    {
+        self.try_build().unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// # Errors
+    ///
+    /// This function will return an error if the capacity is zero, if the
+    /// ratios add up to more than 100, if the capacity can't give at least 1
+    /// to every queue or if the ghost queue is smaller than the small queues.
+    pub fn try_build(self) -> Result<S3FIFO<K, V, S>, ConfigError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher + Clone,
+    {
+        let (small_ratio, main_ratio) = self.ratios;
+        let sizes = self.segment_sizes();
+        if self.capacity == 0 {
+            return Err(ConfigError::ZeroCapacity);
+        }
+        if u16::from(small_ratio) + u16::from(main_ratio) > 100 {
+            return Err(ConfigError::RatiosOverflow {
+                small: small_ratio,
+                main: main_ratio,
+            });
+        }
+        if sizes.main == 0 && self.capacity > self.small_queues {
+            return Err(ConfigError::EmptyMainQueue {
+                small: small_ratio,
+                main: main_ratio,
+            });
+        }
+        if sizes.main == 0 {
+            return Err(ConfigError::CapacityTooSmall {
+                capacity: self.capacity,
+                minimum: self.small_queues + 1,
+            });
+        }
+        let ghost = self.ghost_capacity.filter(|_| self.ghost);
+        if let Some(ghost) = ghost.filter(|ghost| *ghost < sizes.small) {
+            return Err(ConfigError::GhostSmallerThanSmall {
+                ghost,
+                small: sizes.small,
+            });
+        }
+        let layout = Layout {
+            capacity: self.capacity,
+            ratios: self.ratios,
+            ghost: self.ghost,
+            ghost_sizing: self.ghost_sizing,
+            ghost_capacity: self.ghost_capacity,
+            hard_capacity: self.hard_capacity,
+        };
+        let overcommit = match self.hard_capacity {
+            Some(hard) if hard < self.capacity => {
+                return Err(ConfigError::HardCapacityBelowCapacity {
+                    hard,
+                    capacity: self.capacity,
+                })
+            }
+            Some(hard) => hard - self.capacity,
+            None => 0,
+        };
+
+        let mut cache = S3FIFO {
+            main: FIFOReinsertion::with_hasher(sizes.main, self.hasher.clone()),
+            small: (0..self.small_queues)
+                .map(|class| {
+                    let capacity = sizes.small / self.small_queues
+                        + usize::from(class < sizes.small % self.small_queues);
+                    FIFO::with_hasher(capacity, self.hasher.clone())
+                })
+                .collect(),
+            classifier: self.classifier,
+            weigher: self.weigher,
+            ghost: sizes
+                .ghost
+                .map(|capacity| GhostFIFO::with_hasher(capacity, self.hasher.clone())),
+            events: Publisher::default(),
+            listener: self.listener,
+            owning_listener: self.owning_listener,
+            observer: self.observer,
+            hot_keys: self
+                .hot_keys
+                .zip(self.clone_key)
+                .map(|(counters, clone_key)| {
+                    HotKeys::with_hasher(counters, self.hasher.clone(), clone_key)
+                }),
+            shadow: self
+                .shadow_lru
+                .zip(self.clone_key)
+                .map(|(one_in, clone_key)| {
+                    ShadowLru::with_hasher(self.capacity, one_in, self.hasher.clone(), clone_key)
+                }),
+            frozen: None,
+            generations: self.generations,
+            quotas: self.tenant.zip(self.clone_key).map(|(tenant, clone_key)| {
+                Quotas::with_hasher(
+                    tenant,
+                    self.tenant_quota,
+                    self.tenant_shares,
+                    self.hasher.clone(),
+                    clone_key,
+                )
+            }),
+            versions: self
+                .clone_key
+                .filter(|_| self.versioned)
+                .map(|clone_key| Versions::with_hasher(self.hasher.clone(), clone_key)),
+            lifetimes: self
+                .lifetimes
+                .zip(self.clone_key)
+                .map(|(samples, clone_key)| {
+                    Lifetimes::with_hasher(samples, self.hasher.clone(), clone_key)
+                }),
+            changes: self
+                .change_feed
+                .zip(self.clone_key)
+                .map(|(capacity, clone_key)| ChangeFeed::new(capacity, clone_key)),
+            replication: self.replication,
+            pool: self.pool,
+            expiry: Expiry::with_hasher(self.clock, self.hasher.clone()),
+            clone_key: self.clone_key,
+            evicted_deadlines: None,
+            layout,
+            stats: CacheStats::default(),
+            #[cfg(feature = "latency")]
+            latency: crate::LatencyStats::default(),
+        };
+        if overcommit > 0 {
+            let share = |capacity: usize| {
+                (overcommit as u128 * capacity as u128 / self.capacity as u128) as usize
+            };
+            let mut given = 0;
+            for small in &mut cache.small {
+                small.set_overcommit(share(small.capacity()));
+                given += share(small.capacity());
+            }
+            cache.main.set_overcommit(overcommit - given);
+        }
+        Ok(cache)
+    }
+}
+
+/// `percent` percent of `capacity`, without overflowing.
+fn percent(capacity: usize, percent: u8, round_up: bool) -> usize {
+    let percent = usize::from(percent);
+    let rest = capacity % 100 * percent;
+    capacity / 100 * percent
+        + if round_up {
+            rest.div_ceil(100)
+        } else {
+            rest / 100
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_without_ghost() {
+        let mut cache = S3FIFOBuilder::new(10).without_ghost().build();
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+
+        let removed_keys = cache.put(&1, 1, 1).unwrap();
+
+        assert!(cache.ghost.is_none());
+        assert_eq!(removed_keys, Some(vec![2]));
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn it_builds_with_ghost_by_default() {
+        let mut cache = S3FIFOBuilder::new(10).build();
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+
+        let removed_keys = cache.put(&1, 1, 1).unwrap();
+
+        assert!(cache.ghost.is_some());
+        assert_eq!(removed_keys, None);
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), Some(&2));
+    }
+
+    #[test]
+    fn it_builds_at_least_one_per_segment() {
+        let mut cache = S3FIFOBuilder::new(5).build();
+
+        assert_eq!(
+            cache.segment_sizes(),
+            SegmentSizes {
+                small: 1,
+                main: 4,
+                ghost: Some(1),
+            }
+        );
+        assert_eq!(cache.put(&1, 1, 1).unwrap(), None);
+        assert_eq!(
+            S3FIFOBuilder::<u32, u32>::new(10)
+                .small_queues(3, |key| *key as usize)
+                .without_ghost()
+                .segment_sizes(),
+            SegmentSizes {
+                small: 3,
+                main: 7,
+                ghost: None,
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_reject_invalid_configurations() {
+        let build = |builder: S3FIFOBuilder<u32, u32>| builder.try_build().err();
+
+        assert_eq!(
+            build(S3FIFOBuilder::new(0)),
+            Some(ConfigError::ZeroCapacity)
+        );
+        assert_eq!(
+            build(S3FIFOBuilder::new(10).ratios(20, 90)),
+            Some(ConfigError::RatiosOverflow {
+                small: 20,
+                main: 90,
+            })
+        );
+        assert_eq!(
+            build(S3FIFOBuilder::new(1000).ratios(100, 0)),
+            Some(ConfigError::EmptyMainQueue {
+                small: 100,
+                main: 0,
+            })
+        );
+        assert_eq!(
+            build(S3FIFOBuilder::new(2).small_queues(2, |key| *key as usize)),
+            Some(ConfigError::CapacityTooSmall {
+                capacity: 2,
+                minimum: 3,
+            })
+        );
+        assert_eq!(
+            build(S3FIFOBuilder::new(100).ghost_capacity(5)),
+            Some(ConfigError::GhostSmallerThanSmall {
+                ghost: 5,
+                small: 10,
+            })
+        );
+        assert_eq!(
+            build(S3FIFOBuilder::new(10).hard_capacity(9)),
+            Some(ConfigError::HardCapacityBelowCapacity {
+                hard: 9,
+                capacity: 10,
+            })
+        );
+        assert_eq!(
+            S3FIFOBuilder::<u32, u32>::new(100)
+                .ratios(30, 50)
+                .ghost_capacity(50)
+                .try_build()
+                .unwrap()
+                .segment_sizes(),
+            SegmentSizes {
+                small: 30,
+                main: 50,
+                ghost: Some(50),
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic = "capacity 1 is below the minimum of 2"]
+    fn it_should_panic_below_minimum_capacity() {
+        let _ = S3FIFOBuilder::<u32, u32>::new(1).build();
+    }
+
+    #[test]
+    fn it_builds_with_eviction_listener() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut cache = S3FIFOBuilder::new(10)
+            .eviction_listener_ref(move |key: &i32, value: &i32, weight, cause| {
+                sender.send((*key, *value, weight, cause)).unwrap();
+            })
+            .build();
+
+        cache.put(&1, 10, 1).unwrap();
+        cache.put(&1, 11, 1).unwrap();
+        cache.put(&2, 20, 1).unwrap();
+        cache.remove(&2);
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                (1, 10, 1, RemovalCause::Replaced),
+                (1, 11, 1, RemovalCause::Size),
+                (2, 20, 1, RemovalCause::Explicit),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_builds_with_owning_eviction_listener() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut cache = S3FIFOBuilder::new(10)
+            .eviction_listener(move |key: &i32, value: Box<i32>, weight, cause| {
+                sender.send((*key, *value, weight, cause)).unwrap();
+            })
+            .build();
+
+        cache.put(&1, Box::new(10), 1).unwrap();
+        cache.put(&1, Box::new(11), 1).unwrap();
+        cache.put(&2, Box::new(20), 1).unwrap();
+        cache.remove(&2);
+        cache.put_owned(3, Box::new(30), 1).unwrap();
+        cache.put_owned(4, Box::new(40), 1).unwrap();
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                (1, 10, 1, RemovalCause::Replaced),
+                (1, 11, 1, RemovalCause::Size),
+                (2, 20, 1, RemovalCause::Explicit),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_builds_with_hasher() {
+        let mut cache = S3FIFOBuilder::new(10)
+            .hasher(crate::BuildIdentityHasher::default())
+            .build();
+        cache.put(&1u64, 1, 1).unwrap();
+
+        let split = cache.split_off(10, |key, _| *key == 1);
+
+        assert_eq!(cache.get(&1), None);
+        assert!(split.small[0].contains_key(&1));
+    }
+
+    #[test]
+    fn it_builds_with_hot_keys() {
+        let mut cache = S3FIFOBuilder::new(10).hot_keys(10).build();
+        cache.put(&1, 1, 1).unwrap();
+
+        cache.get(&1);
+        cache.get(&2);
+        cache.get(&1);
+
+        assert_eq!(cache.hot_keys(1), vec![(1, 2)]);
+        assert!(S3FIFO::<i32, i32>::new(10).hot_keys(1).is_empty());
+    }
+
+    #[test]
+    fn it_builds_with_shadow_lru() {
+        let mut cache = S3FIFOBuilder::new(10).shadow_lru(1).build();
+        cache.put(&1, 1, 1).unwrap();
+
+        cache.get(&1);
+        cache.get(&2);
+
+        let stats = cache.shadow_stats().unwrap();
+        assert_eq!(stats.lookups, 2);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.lru_hits, 1);
+    }
+
+    #[test]
+    fn it_builds_with_lifetimes() {
+        let mut cache = S3FIFOBuilder::new(10).lifetimes(10).build();
+        cache.put(&1, 1, 1).unwrap();
+        cache.get(&1);
+        cache.put_with_hint(&2, 2, 1, crate::Hint::Hot).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+        cache.remove(&2);
+        cache.put(&4, 4, 1).unwrap();
+
+        let stats = cache.lifetime_stats(None).unwrap();
+        assert_eq!((stats.evicted, stats.never_hit), (1, 1));
+        assert_eq!(
+            cache
+                .lifetime_stats(Some(crate::Segment::Main))
+                .unwrap()
+                .evicted,
+            0
+        );
+        assert!(S3FIFO::<i32, i32>::new(10).lifetime_stats(None).is_none());
+    }
+
+    #[test]
+    fn it_builds_with_weigher() {
+        let mut cache = S3FIFOBuilder::new(100)
+            .ratios(30, 70)
+            .ghost_capacity(50)
+            .weigher(|_, value: &String| value.len())
+            .build();
+        assert_eq!(
+            cache.segment_sizes(),
+            SegmentSizes {
+                small: 30,
+                main: 70,
+                ghost: Some(50)
+            }
+        );
+
+        cache.insert(&1, "a".repeat(20)).unwrap();
+        assert_eq!(cache.insert(&2, "b".repeat(20)).unwrap(), Some(vec![1]));
+        assert!(cache.insert(&3, "c".repeat(31)).is_err());
+        assert!(cache.ghost_contains(&1));
+
+        let mut split = cache.split_off(40, |_, _| false);
+        split.insert(&4, "d".repeat(3)).unwrap();
+        assert_eq!(split.weight(), 3);
+        let mut unweighed = S3FIFO::new(10);
+        unweighed.insert(&1, ()).unwrap();
+        assert_eq!(unweighed.weight(), 1);
+    }
+}
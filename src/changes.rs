@@ -0,0 +1,94 @@
+use crate::CloneKey;
+
+use std::collections::VecDeque;
+
+/// A mutation of the cache, see [`crate::S3FIFO::changes_since`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K> {
+    /// A put stored the key, which wasn't in the cache.
+    Insert(K),
+    /// A put overwrote the entry stored under the key.
+    Update(K),
+    /// The key left the cache, evicted or removed.
+    Remove(K),
+}
+
+impl<K> Change<&K> {
+    fn cloned_with(self, clone_key: CloneKey<K>) -> Change<K> {
+        match self {
+            Change::Insert(key) => Change::Insert(clone_key(key)),
+            Change::Update(key) => Change::Update(clone_key(key)),
+            Change::Remove(key) => Change::Remove(clone_key(key)),
+        }
+    }
+}
+
+/// The most recent changes of a cache, numbered by generation.
+#[derive(Debug)]
+pub struct ChangeFeed<K> {
+    changes: VecDeque<Change<K>>,
+    capacity: usize,
+    generation: u64,
+    clone_key: CloneKey<K>,
+}
+
+impl<K> ChangeFeed<K> {
+    pub fn new(capacity: usize, clone_key: CloneKey<K>) -> Self {
+        ChangeFeed {
+            changes: VecDeque::new(),
+            capacity: capacity.max(1),
+            generation: 0,
+            clone_key,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn push(&mut self, change: Change<&K>) {
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(change.cloned_with(self.clone_key));
+        self.generation += 1;
+    }
+
+    /// Changes after `generation`, with their generation, or `None` if some
+    /// of them were already dropped.
+    pub fn since(&self, generation: u64) -> Option<impl Iterator<Item = (u64, &Change<K>)>> {
+        let oldest = self.generation - self.changes.len() as u64;
+        if generation < oldest {
+            return None;
+        }
+
+        let skip = usize::try_from(generation - oldest).unwrap_or(usize::MAX);
+        Some((oldest + 1..).zip(&self.changes).skip(skip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_replay_changes_since_a_generation() {
+        let mut feed = ChangeFeed::new(2, Clone::clone);
+        feed.push(Change::Insert(&1));
+        feed.push(Change::Update(&1));
+        feed.push(Change::Remove(&1));
+
+        assert_eq!(feed.generation(), 3);
+        assert!(feed.since(0).is_none());
+        assert_eq!(
+            feed.since(1).unwrap().collect::<Vec<_>>(),
+            vec![(2, &Change::Update(1)), (3, &Change::Remove(1))]
+        );
+        assert_eq!(feed.since(3).unwrap().count(), 0);
+        assert_eq!(feed.since(7).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,465 @@
+use crate::{DefaultState, EvictedEntry, S3FIFOError, S3FIFO};
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// How the load of a flight ended, handed to the callers waiting for it.
+#[derive(Clone)]
+enum Outcome<K, V> {
+    Loaded(V),
+    /// The loaded entry didn't fit in its shard.
+    Rejected(S3FIFOError<K>),
+    /// The load failed with this error of
+    /// [`ConcurrentS3FIFO::try_get_or_insert_with`].
+    Failed(Arc<dyn Any + Send + Sync>),
+}
+
+/// Load of one key by [`ConcurrentS3FIFO::get_or_insert_with`], awaited by
+/// the callers that missed the key while it ran.
+struct Flight<K, V> {
+    /// `None` until the load lands, then its outcome, or `None` if it
+    /// panicked.
+    landed: Mutex<Option<Option<Outcome<K, V>>>>,
+    done: Condvar,
+}
+
+impl<K: Clone, V: Clone> Flight<K, V> {
+    fn lock(&self) -> MutexGuard<'_, Option<Option<Outcome<K, V>>>> {
+        self.landed
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn land(&self, outcome: Option<Outcome<K, V>>) {
+        *self.lock() = Some(outcome);
+        self.done.notify_all();
+    }
+
+    fn wait(&self) -> Option<Outcome<K, V>> {
+        let mut landed = self.lock();
+        while landed.is_none() {
+            landed = self
+                .done
+                .wait(landed)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        landed.clone().flatten()
+    }
+}
+
+/// A cache of a [`ConcurrentS3FIFO`] and the loads in flight for its keys,
+/// behind the same lock.
+struct Shard<K, V, S> {
+    cache: S3FIFO<K, V, S>,
+    flights: HashMap<K, Arc<Flight<K, V>>, DefaultState>,
+}
+
+/// Ends the flight of the loading caller when dropped, even if the load
+/// panicked, so the waiting callers don't wait forever.
+struct Landing<'a, K: Eq + Hash + Clone, V: Clone, S> {
+    shard: &'a Mutex<Shard<K, V, S>>,
+    key: &'a K,
+    flight: Arc<Flight<K, V>>,
+    outcome: Option<Outcome<K, V>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone, S> Drop for Landing<'_, K, V, S> {
+    fn drop(&mut self) {
+        self.shard
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .flights
+            .remove(self.key);
+        self.flight.land(self.outcome.take());
+    }
+}
+
+/// A locked shard of a [`ConcurrentS3FIFO`], see
+/// [`ConcurrentS3FIFO::lock_shard`]. Dereferences to its cache.
+pub struct ShardGuard<'a, K, V, S = DefaultState>(MutexGuard<'a, Shard<K, V, S>>);
+
+impl<K, V, S> Deref for ShardGuard<'_, K, V, S> {
+    type Target = S3FIFO<K, V, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.cache
+    }
+}
+
+impl<K, V, S> DerefMut for ShardGuard<'_, K, V, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0.cache
+    }
+}
+
+/// A thread-safe cache made of independent [`S3FIFO`] shards, each behind
+/// its own lock.
+///
+/// Keys are spread over the shards by hash, so operations on keys of
+/// different shards run in parallel, and every operation only locks the
+/// shard of its key. Every shard evicts on its own, within its share of the
+/// capacity.
+///
+/// Values are handed out by clone, or borrowed for the duration of a closure
+/// with [`ConcurrentS3FIFO::get_with`], since the shard lock can't outlive
+/// the call.
+pub struct ConcurrentS3FIFO<K, V, S = DefaultState> {
+    shards: Box<[Mutex<Shard<K, V, S>>]>,
+    router: DefaultState,
+}
+
+impl<K, V> ConcurrentS3FIFO<K, V>
+where
+    K: Eq + Hash,
+{
+    /// Splits `capacity` evenly over `shards` caches built like
+    /// [`S3FIFO::new`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0 or if a shard gets a capacity below 2.
+    #[must_use]
+    pub fn new(capacity: usize, shards: usize) -> Self {
+        assert!(shards > 0, "a concurrent cache needs at least one shard");
+        Self::with_shards(shards, |shard| {
+            S3FIFO::new(capacity / shards + usize::from(shard < capacity % shards))
+        })
+    }
+}
+
+impl<K, V, S> ConcurrentS3FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Builds every one of the `shards` caches with `build`, called with the
+    /// index of the shard, for example from an [`crate::S3FIFOBuilder`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0.
+    pub fn with_shards<F>(shards: usize, mut build: F) -> Self
+    where
+        F: FnMut(usize) -> S3FIFO<K, V, S>,
+    {
+        assert!(shards > 0, "a concurrent cache needs at least one shard");
+        ConcurrentS3FIFO {
+            shards: (0..shards)
+                .map(|index| {
+                    Mutex::new(Shard {
+                        cache: build(index),
+                        flights: HashMap::default(),
+                    })
+                })
+                .collect(),
+            router: DefaultState::default(),
+        }
+    }
+
+    /// Index of the shard holding `key`.
+    pub fn shard_of(&self, key: &K) -> usize {
+        // The high bits, the shards' maps bucket by the low ones.
+        let hash = self.router.hash_one(key) >> 32;
+        (hash % self.shards.len() as u64) as usize
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Locks the shard at `index`, for operations without a concurrent
+    /// counterpart.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not below [`ConcurrentS3FIFO::shards`].
+    pub fn lock_shard(&self, index: usize) -> ShardGuard<'_, K, V, S> {
+        ShardGuard(self.lock_index(index))
+    }
+
+    fn lock_index(&self, index: usize) -> MutexGuard<'_, Shard<K, V, S>> {
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock(&self, key: &K) -> MutexGuard<'_, Shard<K, V, S>> {
+        self.lock_index(self.shard_of(key))
+    }
+
+    /// Like [`S3FIFO::get`], but returns a copy of the value.
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.lock(key).cache.get_cloned(key)
+    }
+
+    /// Looks `key` up like [`S3FIFO::get`] and calls `f` with its value,
+    /// while the shard is locked.
+    pub fn get_with<F, R>(&self, key: &K, f: F) -> Option<R>
+    where
+        F: FnOnce(&V) -> R,
+    {
+        self.lock(key).cache.get(key).map(f)
+    }
+
+    /// Puts an entry into the shard of `key`, see [`S3FIFO::put`]. Only
+    /// entries of that shard are evicted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry doesn't fit in its
+    /// shard.
+    pub fn put(&self, key: &K, value: V, weight: usize) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        self.lock(key).cache.put(key, value, weight)
+    }
+
+    /// Puts an entry into the shard of `key` without copying the key, see
+    /// [`S3FIFO::put_owned`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry doesn't fit in its
+    /// shard.
+    pub fn put_owned(
+        &self,
+        key: K,
+        value: V,
+        weight: usize,
+    ) -> Result<Vec<EvictedEntry<K, V>>, S3FIFOError<K>> {
+        let mut shard = self.lock(&key);
+        shard.cache.put_owned(key, value, weight)
+    }
+
+    /// Looks `key` up like [`ConcurrentS3FIFO::get`], and on a miss puts the
+    /// value returned by `f`, see [`S3FIFO::get_or_insert_with`].
+    ///
+    /// `f` runs without the shard locked, and at most once for callers
+    /// missing `key` at the same time: the others wait for its outcome, the
+    /// value or the error of a value that doesn't fit. Only if `f` panics
+    /// does one of them run its own `f` instead.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the computed entry doesn't fit
+    /// in its shard.
+    pub fn get_or_insert_with<F>(&self, key: &K, weight: usize, f: F) -> Result<V, S3FIFOError<K>>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnOnce() -> V,
+    {
+        match self.load(key, weight, || Ok::<_, Infallible>(f())) {
+            Ok(result) => result,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`ConcurrentS3FIFO::get_or_insert_with`], but `f` may fail, in
+    /// which case nothing is put, and the callers waiting for it get a clone
+    /// of its error. Callers of another error type run their own `f` instead.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the error of `f`, or an error converted from
+    /// [`S3FIFOError`] if the computed entry doesn't fit in its shard.
+    pub fn try_get_or_insert_with<F, E>(&self, key: &K, weight: usize, f: F) -> Result<V, E>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnOnce() -> Result<V, E>,
+        E: From<S3FIFOError<K>> + Clone + Send + Sync + 'static,
+    {
+        self.load(key, weight, f)?.map_err(E::from)
+    }
+
+    fn load<F, E>(&self, key: &K, weight: usize, f: F) -> Result<Result<V, S3FIFOError<K>>, E>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnOnce() -> Result<V, E>,
+        E: Clone + Send + Sync + 'static,
+    {
+        let index = self.shard_of(key);
+        let flight = loop {
+            let mut shard = self.lock_index(index);
+            if let Some(value) = shard.cache.get_cloned(key) {
+                return Ok(Ok(value));
+            }
+            // Flights start and are looked up with the shard locked, and end
+            // after their put, so a caller missing the key either finds the
+            // flight or the loaded entry.
+            match shard.flights.get(key) {
+                Some(flight) => {
+                    let flight = Arc::clone(flight);
+                    drop(shard);
+                    match flight.wait() {
+                        Some(Outcome::Loaded(value)) => return Ok(Ok(value)),
+                        Some(Outcome::Rejected(error)) => return Ok(Err(error)),
+                        Some(Outcome::Failed(error)) => {
+                            if let Some(error) = error.downcast_ref::<E>() {
+                                return Err(error.clone());
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                None => {
+                    let flight = Arc::new(Flight {
+                        landed: Mutex::new(None),
+                        done: Condvar::new(),
+                    });
+                    shard.flights.insert(key.clone(), Arc::clone(&flight));
+                    break flight;
+                }
+            }
+        };
+
+        let mut landing = Landing {
+            shard: &self.shards[index],
+            key,
+            flight,
+            outcome: None,
+        };
+        let value = match f() {
+            Ok(value) => value,
+            Err(error) => {
+                landing.outcome = Some(Outcome::Failed(Arc::new(error.clone())));
+                return Err(error);
+            }
+        };
+        let result = self.lock_index(index).cache.put(key, value.clone(), weight);
+        landing.outcome = Some(match &result {
+            Ok(_) => Outcome::Loaded(value.clone()),
+            Err(error) => Outcome::Rejected(error.clone()),
+        });
+        Ok(result.map(|_| value))
+    }
+
+    pub fn remove(&self, key: &K) {
+        self.lock(key).cache.remove(key);
+    }
+
+    /// Runs [`S3FIFO::maintain`] on every shard, one at a time. Returns the
+    /// evicted keys.
+    pub fn maintain(&self) -> Vec<K> {
+        (0..self.shards.len())
+            .flat_map(|index| self.lock_index(index).cache.maintain())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn it_should_be_shared_between_threads() {
+        assert_send_sync::<ConcurrentS3FIFO<String, Vec<u8>>>();
+        let cache = Arc::new(ConcurrentS3FIFO::new(4000, 4));
+
+        let writers: Vec<_> = (0..4)
+            .map(|thread| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    for key in thread * 100..(thread + 1) * 100 {
+                        cache.put(&key, key * 2, 1).unwrap();
+                        assert_eq!(cache.get(&key), Some(key * 2));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(cache.get_with(&399, |value| value + 1), Some(799));
+        cache.remove(&399);
+        assert_eq!(cache.get(&399), None);
+        assert_eq!(cache.shards(), 4);
+    }
+
+    #[test]
+    fn it_should_build_shards() {
+        let cache: ConcurrentS3FIFO<u32, u32> =
+            ConcurrentS3FIFO::with_shards(2, |_| S3FIFO::builder(10).hard_capacity(20).build());
+        for key in 0..30 {
+            cache.put(&key, key, 1).unwrap();
+        }
+
+        assert!(!cache.maintain().is_empty());
+        assert_eq!(cache.lock_shard(0).overcommitted(), 0);
+    }
+
+    #[test]
+    fn it_should_load_a_missing_key_once() {
+        let cache = Arc::new(ConcurrentS3FIFO::new(100, 2));
+        let loads = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, loads, barrier) =
+                    (Arc::clone(&cache), Arc::clone(&loads), Arc::clone(&barrier));
+                thread::spawn(move || {
+                    barrier.wait();
+                    cache
+                        .get_or_insert_with(&1, 1, || {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(Duration::from_millis(50));
+                            String::from("one")
+                        })
+                        .unwrap()
+                })
+            })
+            .collect();
+        for caller in callers {
+            assert_eq!(caller.join().unwrap(), "one");
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn it_should_share_the_outcome_of_a_load_that_does_not_fit() {
+        let cache: Arc<ConcurrentS3FIFO<u32, u32>> = Arc::new(ConcurrentS3FIFO::new(100, 2));
+        let loads = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, loads, barrier) =
+                    (Arc::clone(&cache), Arc::clone(&loads), Arc::clone(&barrier));
+                thread::spawn(move || {
+                    barrier.wait();
+                    cache.get_or_insert_with(&1, 1000, || {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(50));
+                        1
+                    })
+                })
+            })
+            .collect();
+        for caller in callers {
+            assert!(matches!(
+                caller.join().unwrap(),
+                Err(S3FIFOError::BeyondCapacity { key: 1, .. })
+            ));
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::CloneKey;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+/// Buffer size used by [`crate::S3FIFO::subscribe`].
+pub const DEFAULT_EVENT_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<K> {
+    /// The key was inserted or updated by a put.
+    Put(K),
+    /// The key was evicted to make room for other entries.
+    Evict(K),
+    /// The key was removed explicitly.
+    Remove(K),
+}
+
+impl<K> Event<&K> {
+    fn cloned_with(&self, clone_key: CloneKey<K>) -> Event<K> {
+        match *self {
+            Event::Put(key) => Event::Put(clone_key(key)),
+            Event::Evict(key) => Event::Evict(clone_key(key)),
+            Event::Remove(key) => Event::Remove(clone_key(key)),
+        }
+    }
+}
+
+/// Receiving half of a cache subscription.
+///
+/// Every receiver has its own bounded buffer. The cache never waits for a
+/// subscriber: when the buffer is full, new events for that subscriber are
+/// dropped and counted in [`EventReceiver::dropped`].
+#[derive(Debug)]
+pub struct EventReceiver<K> {
+    receiver: Receiver<Event<K>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl<K> EventReceiver<K> {
+    /// Returns the next buffered event without blocking.
+    pub fn try_recv(&self) -> Option<Event<K>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Blocks until an event arrives. Returns `None` once the cache is dropped
+    /// and the buffer is drained.
+    pub fn recv(&self) -> Option<Event<K>> {
+        self.receiver.recv().ok()
+    }
+
+    /// Iterates over the events buffered so far.
+    pub fn try_iter(&self) -> impl Iterator<Item = Event<K>> + '_ {
+        self.receiver.try_iter()
+    }
+
+    /// Number of events lost because the buffer was full.
+    #[must_use]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber<K> {
+    sender: SyncSender<Event<K>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+pub struct Publisher<K> {
+    subscribers: Vec<Subscriber<K>>,
+    /// Set by the first subscription, every subscriber gets its own copy of
+    /// the key.
+    clone_key: Option<CloneKey<K>>,
+}
+
+impl<K> Default for Publisher<K> {
+    fn default() -> Self {
+        Publisher {
+            subscribers: Vec::new(),
+            clone_key: None,
+        }
+    }
+}
+
+impl<K> Publisher<K> {
+    pub fn subscribe(&mut self, buffer: usize, clone_key: CloneKey<K>) -> EventReceiver<K> {
+        self.clone_key = Some(clone_key);
+        let (sender, receiver) = mpsc::sync_channel(buffer);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        self.subscribers.push(Subscriber {
+            sender,
+            dropped: dropped.clone(),
+        });
+        EventReceiver { receiver, dropped }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /// Sends the event to every subscriber, forgetting the ones whose
+    /// receiver was dropped.
+    pub fn publish(&mut self, event: Event<&K>) {
+        let Some(clone_key) = self.clone_key else {
+            return;
+        };
+        self.subscribers.retain(|subscriber| {
+            match subscriber.sender.try_send(event.cloned_with(clone_key)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_fan_out() {
+        let mut publisher = Publisher::default();
+        let first = publisher.subscribe(10, Clone::clone);
+        let second = publisher.subscribe(10, Clone::clone);
+
+        publisher.publish(Event::Put(&1));
+        publisher.publish(Event::Evict(&1));
+
+        assert_eq!(
+            first.try_iter().collect::<Vec<_>>(),
+            vec![Event::Put(1), Event::Evict(1)]
+        );
+        assert_eq!(
+            second.try_iter().collect::<Vec<_>>(),
+            vec![Event::Put(1), Event::Evict(1)]
+        );
+    }
+
+    #[test]
+    fn it_should_drop_events_when_full() {
+        let mut publisher = Publisher::default();
+        let receiver = publisher.subscribe(1, Clone::clone);
+
+        publisher.publish(Event::Put(&1));
+        publisher.publish(Event::Put(&2));
+
+        assert_eq!(receiver.try_recv(), Some(Event::Put(1)));
+        assert_eq!(receiver.try_recv(), None);
+        assert_eq!(receiver.dropped(), 1);
+    }
+
+    #[test]
+    fn it_should_forget_dropped_receivers() {
+        let mut publisher = Publisher::default();
+        let receiver = publisher.subscribe(1, Clone::clone);
+        drop(receiver);
+
+        publisher.publish(Event::Remove(&1));
+
+        assert!(publisher.is_empty());
+    }
+}
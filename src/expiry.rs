@@ -0,0 +1,118 @@
+use crate::slab::{Handle, Table};
+
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Current time of a cache, see [`crate::S3FIFOBuilder::clock`].
+pub type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Deadline of every live entry put with a time to live, see
+/// [`crate::S3FIFO::put_with_ttl`].
+pub struct Expiry<K, S> {
+    deadlines: Table<K, (Instant, u64), S>,
+    /// Entries by deadline, ties broken by the order they were set in.
+    order: BTreeMap<(Instant, u64), Handle>,
+    next: u64,
+    clock: Option<Clock>,
+}
+
+impl<K, S> Expiry<K, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn with_hasher(clock: Option<Clock>, hasher: S) -> Self {
+        Expiry {
+            deadlines: Table::with_hasher(hasher),
+            order: BTreeMap::new(),
+            next: 0,
+            clock,
+        }
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock
+            .as_ref()
+            .map_or_else(Instant::now, |clock| clock())
+    }
+
+    pub fn deadline(&self, key: &K) -> Option<Instant> {
+        let (_, (deadline, _)) = self.deadlines.get(self.deadlines.find(key)?)?;
+        Some(*deadline)
+    }
+
+    /// Whether the deadline of `key` passed. Keys without a deadline don't
+    /// read the clock.
+    pub fn is_expired(&self, key: &K) -> bool {
+        self.deadline(key)
+            .is_some_and(|deadline| deadline <= self.now())
+    }
+
+    pub fn set(&mut self, key: K, deadline: Instant) {
+        self.remove(&key);
+        let slot = (deadline, self.next);
+        self.next += 1;
+        let handle = self.deadlines.insert(key, slot);
+        self.order.insert(slot, handle);
+    }
+
+    /// Drops the deadline of `key`, and returns it with the key it was set
+    /// under.
+    pub fn remove(&mut self, key: &K) -> Option<(K, Instant)> {
+        let handle = self.deadlines.find(key)?;
+        let (key, slot) = self.deadlines.remove(handle)?;
+        self.order.remove(&slot);
+        Some((key, slot.0))
+    }
+
+    /// Drops and returns the key with the earliest deadline, if it is at or
+    /// before `now`.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<K> {
+        let entry = self.order.first_entry()?;
+        if entry.key().0 > now {
+            return None;
+        }
+        let handle = entry.remove();
+        self.deadlines.remove(handle).map(|(key, _)| key)
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, Instant)> + '_ {
+        let order = std::mem::take(&mut self.order);
+        let deadlines = &mut self.deadlines;
+        order.into_values().filter_map(move |handle| {
+            deadlines
+                .remove(handle)
+                .map(|(key, (deadline, _))| (key, deadline))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultState;
+
+    use std::time::Duration;
+
+    #[test]
+    fn it_should_pop_keys_by_deadline() {
+        let start = Instant::now();
+        let mut expiry = Expiry::with_hasher(None, DefaultState::default());
+        expiry.set(1, start + Duration::from_secs(2));
+        expiry.set(2, start + Duration::from_secs(1));
+        expiry.set(3, start + Duration::from_secs(1));
+        expiry.set(2, start + Duration::from_secs(3));
+
+        let now = start + Duration::from_secs(2);
+        assert_eq!(expiry.pop_expired(now), Some(3));
+        assert_eq!(expiry.pop_expired(now), Some(1));
+        assert_eq!(expiry.pop_expired(now), None);
+        assert_eq!(expiry.deadline(&2), Some(start + Duration::from_secs(3)));
+    }
+}
@@ -1,97 +1,162 @@
-use std::collections::{HashMap, VecDeque};
-use std::fmt::Debug;
-use std::hash::Hash;
+use crate::hash::DefaultState;
+use crate::slab::{Handle, Table};
+
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::sync::{Arc, Weak};
 
 #[derive(Debug)]
 struct Item<V> {
     value: V,
     weight: usize,
     freq: usize,
-    removed: bool,
+    pin: Weak<()>,
 }
 
 #[derive(Debug)]
-pub struct FIFO<K, V> {
-    hash: HashMap<K, Item<V>>,
-    vec_deque: VecDeque<K>,
+#[allow(clippy::upper_case_acronyms)]
+pub struct FIFO<K, V, S = DefaultState> {
+    entries: Table<K, Item<V>, S>,
+    /// Handles in queue order, the stale handles of removed entries included.
+    vec_deque: VecDeque<Handle>,
+    stale: usize,
     used_capacity: usize,
     capacity: usize,
+    overcommit: usize,
 }
 
 #[derive(Debug)]
-pub enum FIFOError {
-    BeyondCapacity,
+pub enum FIFOError<K> {
+    BeyondCapacity {
+        key: K,
+        weight: usize,
+        capacity: usize,
+    },
 }
 
 #[derive(Debug, PartialEq)]
-pub struct Removed<K, V: Clone> {
+pub struct Removed<K, V> {
     pub key: K,
     pub value: V,
     pub weight: usize,
     pub freq: usize,
 }
 
+/// The entry an update replaced, if any, and the entries evicted by the put.
+pub type Replaced<K, V> = (Option<Removed<K, V>>, Option<Vec<Removed<K, V>>>);
+
+impl<K, V> From<(K, Item<V>)> for Removed<K, V> {
+    fn from((key, item): (K, Item<V>)) -> Self {
+        Removed {
+            key,
+            value: item.value,
+            weight: item.weight,
+            freq: item.freq,
+        }
+    }
+}
+
 impl<K, V> FIFO<K, V>
 where
-    K: Eq + Hash + Debug + Clone,
-    V: Clone + Debug,
+    K: Eq + Hash,
 {
     #[must_use]
+    #[allow(dead_code)]
     pub fn new(capacity: usize) -> Self {
+        Self::with_hasher(capacity, DefaultState::default())
+    }
+}
+
+impl<K, V, S> FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    #[must_use]
+    pub fn with_hasher(capacity: usize, hasher: S) -> Self {
         FIFO {
-            hash: HashMap::new(),
+            entries: Table::with_hasher(hasher),
             vec_deque: VecDeque::new(),
+            stale: 0,
             used_capacity: 0,
             capacity,
+            overcommit: 0,
         }
     }
 
-    pub fn get(&mut self, key: &K) -> Option<&V> {
-        if let Some(item) = self.hash.get_mut(key) {
-            if item.removed {
-                return None;
-            }
+    fn item(&self, key: &K) -> Option<&Item<V>> {
+        self.entries
+            .get(self.entries.find(key)?)
+            .map(|(_, item)| item)
+    }
 
-            if item.freq + 1 < usize::MAX {
-                item.freq += 1;
-            }
-            Some(&item.value)
-        } else {
-            None
+    /// Counts a hit on the entry of `handle`.
+    fn hit(&mut self, handle: Handle) {
+        let item = self.entries.get_mut(handle).unwrap();
+        if item.freq + 1 < usize::MAX {
+            item.freq += 1;
         }
     }
 
-    fn update(&mut self, key: &K, value: V, weight: usize) -> Option<Vec<Removed<K, V>>> {
-        let item = self.hash.get_mut(key).unwrap();
-        item.value = value;
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_key_value(key).map(|(_, value)| value)
+    }
+
+    pub fn get_key_value(&mut self, key: &K) -> Option<(&K, &V)> {
+        let handle = self.entries.find(key)?;
+        self.hit(handle);
+        self.entries
+            .get(handle)
+            .map(|(key, item)| (key, &item.value))
+    }
+
+    fn update(
+        &mut self,
+        handle: Handle,
+        value: V,
+        weight: usize,
+        freq: Option<usize>,
+    ) -> (V, usize, Option<Vec<Removed<K, V>>>) {
+        let item = self.entries.get_mut(handle).unwrap();
+        let old_value = mem::replace(&mut item.value, value);
         let old_weight = item.weight;
         item.weight = weight;
-        item.removed = false;
+
+        if let Some(freq) = freq {
+            item.freq = freq;
+        }
 
         if weight > old_weight {
             let needed_space = weight - old_weight;
-            let removed_keys = self.free(needed_space, Some(key));
+            let removed_keys = self.free(needed_space, Some(handle));
             self.used_capacity += needed_space;
-            removed_keys
+            (old_value, old_weight, removed_keys)
         } else {
             self.used_capacity -= old_weight - weight;
-            None
+            (old_value, old_weight, None)
         }
     }
 
-    fn insert(&mut self, key: &K, value: V, weight: usize) -> Option<Vec<Removed<K, V>>> {
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        weight: usize,
+        freq: Option<usize>,
+    ) -> Option<Vec<Removed<K, V>>> {
         let removed_keys = self.free(weight, None);
         self.used_capacity += weight;
-        self.hash.insert(
-            key.clone(),
+        let handle = self.entries.insert(
+            key,
             Item {
                 value,
                 weight,
-                freq: 0,
-                removed: false,
+                freq: freq.unwrap_or(0),
+                pin: Weak::new(),
             },
         );
-        self.vec_deque.push_back(key.clone());
+        self.vec_deque.push_back(handle);
 
         removed_keys
     }
@@ -102,46 +167,105 @@ where
     /// Returns `CacheError::BeyondCapacity` if the weight is greater than the capacity.
     pub fn put(
         &mut self,
-        key: &K,
+        key: K,
         value: V,
         weight: usize,
-    ) -> Result<Option<Vec<Removed<K, V>>>, FIFOError> {
+    ) -> Result<Option<Vec<Removed<K, V>>>, FIFOError<K>> {
+        self.replace(key, value, weight).map(|(_, removed)| removed)
+    }
+
+    /// Like [`FIFO::put`], but hands back the entry an update of `key`
+    /// replaced, under the key given to the update.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CacheError::BeyondCapacity` if the weight is greater than the capacity.
+    pub fn replace(
+        &mut self,
+        key: K,
+        value: V,
+        weight: usize,
+    ) -> Result<Replaced<K, V>, FIFOError<K>> {
         if weight > self.capacity {
-            return Err(FIFOError::BeyondCapacity);
+            return Err(FIFOError::BeyondCapacity {
+                key,
+                weight,
+                capacity: self.capacity,
+            });
         }
 
-        if self.hash.contains_key(key) {
-            Ok(self.update(key, value, weight))
-        } else {
-            Ok(self.insert(key, value, weight))
+        match self.entries.find(&key) {
+            Some(handle) => {
+                let (_, item) = self.entries.get(handle).unwrap();
+                let old_freq = item.freq;
+                let (value, weight, removed) = self.update(handle, value, weight, None);
+                let replaced = Removed {
+                    key,
+                    value,
+                    weight,
+                    freq: old_freq,
+                };
+                Ok((Some(replaced), removed))
+            }
+            None => Ok((None, self.insert(key, value, weight, None))),
         }
     }
 
-    fn free(&mut self, weight: usize, ignore_key: Option<&K>) -> Option<Vec<Removed<K, V>>> {
-        let mut removed_keys = vec![];
-        while self.used_capacity + weight > self.capacity {
-            let key = self.vec_deque.pop_front().unwrap();
-            let item = self.hash.get(&key).unwrap();
+    /// # Errors
+    ///
+    /// Returns `CacheError::BeyondCapacity` if the weight is greater than the capacity.
+    pub fn put_with_freq(
+        &mut self,
+        key: K,
+        value: V,
+        weight: usize,
+        freq: usize,
+    ) -> Result<Option<Vec<Removed<K, V>>>, FIFOError<K>> {
+        if weight > self.capacity {
+            return Err(FIFOError::BeyondCapacity {
+                key,
+                weight,
+                capacity: self.capacity,
+            });
+        }
 
-            if item.removed {
-                self.used_capacity -= item.weight;
-                self.hash.remove(&key);
-                continue;
+        match self.entries.find(&key) {
+            Some(handle) => {
+                let (_, _, removed) = self.update(handle, value, weight, Some(freq));
+                Ok(removed)
             }
+            None => Ok(self.insert(key, value, weight, Some(freq))),
+        }
+    }
 
-            if Some(&key) == ignore_key {
-                self.vec_deque.push_back(key);
+    fn free(&mut self, weight: usize, ignore: Option<Handle>) -> Option<Vec<Removed<K, V>>> {
+        let mut removed_keys = vec![];
+        let mut skipped = 0;
+        while self.used_capacity + weight > self.hard_capacity() {
+            let Some(handle) = self.vec_deque.pop_front() else {
+                break;
+            };
+            let Some((_, item)) = self.entries.get(handle) else {
+                self.stale -= 1;
+                skipped = 0;
+                continue;
+            };
+
+            if Some(handle) == ignore || item.pin.strong_count() > 0 {
+                self.vec_deque.push_back(handle);
+                // Only pinned entries are left, so the queue goes over
+                // capacity until they are unpinned.
+                skipped += 1;
+                if skipped >= self.vec_deque.len() {
+                    break;
+                }
                 continue;
             }
 
-            removed_keys.push(Removed {
-                key: key.clone(),
-                value: item.value.clone(),
-                weight: item.weight,
-                freq: item.freq,
-            });
-            self.used_capacity -= item.weight;
-            self.hash.remove(&key);
+            let removed = self.entries.remove(handle).unwrap();
+            self.used_capacity -= removed.1.weight;
+            removed_keys.push(removed.into());
+            skipped = 0;
         }
 
         if removed_keys.is_empty() {
@@ -151,12 +275,138 @@ where
         }
     }
 
-    pub fn remove(&mut self, key: &K) {
-        let item = self.hash.get_mut(key);
+    /// Counts a hit on `key` and pins it: the entry is not evicted while the
+    /// returned token is alive.
+    pub fn pin(&mut self, key: &K) -> Option<(&V, Arc<()>)> {
+        let handle = self.entries.find(key)?;
+        self.hit(handle);
+        let item = self.entries.get_mut(handle).unwrap();
+        let pin = item.pin.upgrade().unwrap_or_else(|| {
+            let pin = Arc::new(());
+            item.pin = Arc::downgrade(&pin);
+            pin
+        });
+        Some((&item.value, pin))
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.find(key).is_some()
+    }
+
+    /// Removes every entry, in queue order.
+    pub fn drain(&mut self) -> Vec<Removed<K, V>> {
+        let mut removed = vec![];
+        for handle in std::mem::take(&mut self.vec_deque) {
+            if let Some(entry) = self.entries.remove(handle) {
+                removed.push(entry.into());
+            }
+        }
+        self.entries.clear();
+        self.stale = 0;
+        self.used_capacity = 0;
+
+        removed
+    }
+
+    /// Drops the stale handles removals left in the queue. Returns the number
+    /// of handles dropped.
+    pub fn compact(&mut self) -> usize {
+        let entries = &self.entries;
+        self.vec_deque
+            .retain(|handle| entries.get(*handle).is_some());
+        debug_assert_eq!(self.entries.len(), self.vec_deque.len());
+
+        std::mem::take(&mut self.stale)
+    }
 
-        if let Some(item) = item {
-            item.removed = true;
+    /// Removes the entries matching `predicate`, in queue order.
+    pub fn extract_if<F>(&mut self, mut predicate: F) -> Vec<Removed<K, V>>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut extracted = vec![];
+        let mut vec_deque = VecDeque::with_capacity(self.entries.len());
+        for handle in std::mem::take(&mut self.vec_deque) {
+            let Some((key, item)) = self.entries.get(handle) else {
+                continue;
+            };
+            if !predicate(key, &item.value) {
+                vec_deque.push_back(handle);
+                continue;
+            }
+
+            let removed = self.entries.remove(handle).unwrap();
+            self.used_capacity -= removed.1.weight;
+            extracted.push(removed.into());
         }
+        self.vec_deque = vec_deque;
+        self.stale = 0;
+
+        extracted
+    }
+
+    /// Returns the value and weight of `key` without counting a hit.
+    pub fn peek(&self, key: &K) -> Option<(&V, usize)> {
+        self.item(key).map(|item| (&item.value, item.weight))
+    }
+
+    /// Entries with their weight, in queue order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        self.vec_deque
+            .iter()
+            .filter_map(|handle| self.entries.get(*handle))
+            .map(|(key, item)| (key, &item.value, item.weight))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.entries.hasher()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn used_capacity(&self) -> usize {
+        self.used_capacity
+    }
+
+    /// Lets puts go `overcommit` over the capacity before evicting.
+    pub fn set_overcommit(&mut self, overcommit: usize) {
+        self.overcommit = overcommit;
+    }
+
+    pub fn hard_capacity(&self) -> usize {
+        self.capacity.saturating_add(self.overcommit)
+    }
+
+    /// Evicts entries until the used capacity is back within the capacity.
+    pub fn maintain(&mut self) -> Option<Vec<Removed<K, V>>> {
+        self.shrink(self.used_capacity.saturating_sub(self.capacity))
+    }
+
+    /// Evicts entries, following the usual order, until `weight` of the used
+    /// capacity is freed or only pinned entries are left.
+    pub fn shrink(&mut self, weight: usize) -> Option<Vec<Removed<K, V>>> {
+        let target = self.used_capacity.saturating_sub(weight);
+        self.free(self.hard_capacity().saturating_sub(target), None)
+    }
+
+    /// Removes `key` and returns its value and weight. Its weight is freed
+    /// right away, and its handle is left stale in the queue until popped,
+    /// or until stale handles outnumber the entries and the queue is
+    /// compacted.
+    pub fn remove(&mut self, key: &K) -> Option<(V, usize)> {
+        let (_, item) = self.entries.remove(self.entries.find(key)?)?;
+        self.used_capacity -= item.weight;
+        self.stale += 1;
+        if self.stale > self.entries.len() {
+            self.compact();
+        }
+        Some((item.value, item.weight))
     }
 }
 
@@ -167,7 +417,7 @@ mod tests {
     #[test]
     fn it_works() {
         let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 2).unwrap();
+        cache.put(1, 1, 2).unwrap();
         assert_eq!(cache.get(&1), Some(&1));
         assert_eq!(cache.get(&2), None);
 
@@ -175,13 +425,25 @@ mod tests {
         assert_eq!(cache.capacity, 10);
     }
 
+    #[test]
+    fn it_should_get_key_value() {
+        let mut cache = FIFO::new(10);
+        cache.put(1, 1, 2).unwrap();
+        cache.remove(&1);
+        cache.put(2, 2, 2).unwrap();
+
+        assert_eq!(cache.get_key_value(&1), None);
+        assert_eq!(cache.get_key_value(&2), Some((&2, &2)));
+        assert_eq!(cache.item(&2).unwrap().freq, 1);
+    }
+
     #[test]
     fn it_should_free_space() {
         let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
         cache.free(5, None);
 
@@ -193,13 +455,33 @@ mod tests {
         assert_eq!(cache.used_capacity, 5);
     }
 
+    #[test]
+    fn it_should_overcommit_until_maintained() {
+        let mut cache = FIFO::new(4);
+        cache.set_overcommit(2);
+        for key in 1..=6 {
+            assert_eq!(cache.put(key, key, 1).unwrap(), None);
+        }
+
+        let removed = cache.put(7, 7, 1).unwrap().unwrap();
+        assert_eq!(removed[0].key, 1);
+        let removed = cache.maintain().unwrap();
+
+        assert_eq!(
+            removed.iter().map(|item| item.key).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(cache.used_capacity, 4);
+        assert!(cache.maintain().is_none());
+    }
+
     #[test]
     fn it_should_remove() {
         let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
         cache.remove(&2);
 
@@ -208,20 +490,20 @@ mod tests {
         assert_eq!(cache.get(&3), Some(&3));
         assert_eq!(cache.get(&4), Some(&4));
 
-        assert_eq!(cache.used_capacity, 10);
+        assert_eq!(cache.used_capacity, 7);
     }
 
     #[test]
     fn it_should_hit_and_do_nothing() {
         let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
         cache.get(&1);
 
-        cache.put(&5, 5, 5).unwrap();
+        cache.put(5, 5, 5).unwrap();
 
         assert_eq!(cache.get(&1), None);
         assert_eq!(cache.get(&2), None);
@@ -232,27 +514,57 @@ mod tests {
         assert_eq!(cache.used_capacity, 10);
     }
 
+    #[test]
+    fn it_should_drain_in_order() {
+        let mut cache = FIFO::new(10);
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put_with_freq(3, 3, 4, 2).unwrap();
+        cache.remove(&2);
+
+        assert_eq!(
+            cache.drain(),
+            vec![
+                Removed {
+                    key: 1,
+                    value: 1,
+                    weight: 2,
+                    freq: 0,
+                },
+                Removed {
+                    key: 3,
+                    value: 3,
+                    weight: 4,
+                    freq: 2,
+                },
+            ]
+        );
+        assert_eq!(cache.vec_deque.len(), 0);
+        assert_eq!(cache.entries.len(), 0);
+        assert_eq!(cache.used_capacity, 0);
+    }
+
     #[test]
     #[should_panic = "BeyondCapacity"]
     fn it_should_panic() {
         let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
-        cache.put(&5, 5, 11).unwrap();
+        cache.put(5, 5, 11).unwrap();
     }
 
     #[test]
     fn it_should_update() {
         let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
-        cache.put(&1, 10, 3).unwrap();
+        cache.put(1, 10, 3).unwrap();
 
         assert_eq!(cache.get(&1), Some(&10));
         assert_eq!(cache.get(&2), None);
@@ -265,12 +577,12 @@ mod tests {
     #[test]
     fn it_should_update_to_lower_weight() {
         let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 3).unwrap();
-        cache.put(&2, 2, 2).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 3).unwrap();
+        cache.put(2, 2, 2).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
-        cache.put(&1, 10, 2).unwrap();
+        cache.put(1, 10, 2).unwrap();
 
         assert_eq!(cache.get(&1), Some(&10));
         assert_eq!(cache.get(&2), Some(&2));
@@ -284,14 +596,14 @@ mod tests {
     fn it_should_remove_removed_key() {
         let mut cache = FIFO::new(2);
 
-        cache.put(&1, 1, 1).unwrap();
+        cache.put(1, 1, 1).unwrap();
         cache.remove(&1);
-        cache.put(&2, 2, 2).unwrap();
+        cache.put(2, 2, 2).unwrap();
 
         assert_eq!(cache.get(&1), None);
         assert_eq!(cache.get(&2), Some(&2));
         assert_eq!(cache.vec_deque.len(), 1);
-        assert_eq!(cache.hash.len(), 1);
+        assert_eq!(cache.entries.len(), 1);
         assert_eq!(cache.used_capacity, 2);
     }
 
@@ -299,17 +611,17 @@ mod tests {
     fn it_should_remove_removed_key_2() {
         let mut cache = FIFO::new(3);
 
-        cache.put(&1, 1, 1).unwrap();
+        cache.put(1, 1, 1).unwrap();
         cache.remove(&1);
-        cache.put(&2, 2, 2).unwrap();
-        cache.put(&3, 3, 1).unwrap();
+        cache.put(2, 2, 2).unwrap();
+        cache.put(3, 3, 1).unwrap();
 
         assert_eq!(cache.get(&1), None);
         assert_eq!(cache.get(&2), Some(&2));
         assert_eq!(cache.get(&3), Some(&3));
 
         assert_eq!(cache.vec_deque.len(), 2);
-        assert_eq!(cache.hash.len(), 2);
+        assert_eq!(cache.entries.len(), 2);
         assert_eq!(cache.used_capacity, 3);
     }
 
@@ -317,10 +629,10 @@ mod tests {
     fn it_should_return_removed_key() {
         let mut cache = FIFO::new(3);
 
-        cache.put(&1, 1, 1).unwrap();
-        cache.put(&2, 2, 2).unwrap();
+        cache.put(1, 1, 1).unwrap();
+        cache.put(2, 2, 2).unwrap();
 
-        let removed_keys = cache.put(&3, 3, 1).unwrap().unwrap();
+        let removed_keys = cache.put(3, 3, 1).unwrap().unwrap();
 
         assert_eq!(
             removed_keys,
@@ -337,7 +649,20 @@ mod tests {
         assert_eq!(cache.get(&4), None);
 
         assert_eq!(cache.vec_deque.len(), 2);
-        assert_eq!(cache.hash.len(), 2);
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.used_capacity, 3);
+    }
+
+    #[test]
+    fn it_should_compact() {
+        let mut cache = FIFO::new(10);
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.remove(&1);
+
+        assert_eq!(cache.compact(), 1);
+        assert_eq!(cache.vec_deque.len(), 1);
+        assert_eq!(cache.entries.len(), 1);
         assert_eq!(cache.used_capacity, 3);
     }
 }
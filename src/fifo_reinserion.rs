@@ -1,84 +1,132 @@
 use std::cmp::min;
-use std::collections::{HashMap, VecDeque};
-use std::fmt::Debug;
-use std::hash::Hash;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::sync::{Arc, Weak};
+
+use crate::fifo::{Removed, Replaced};
+use crate::hash::DefaultState;
+use crate::slab::{Handle, Table};
 
 #[derive(Debug)]
 struct Item<V> {
     value: V,
     weight: usize,
     freq: usize,
-    removed: bool,
+    pin: Weak<()>,
+    generation: u64,
+}
+
+impl<K, V> From<(K, Item<V>)> for Removed<K, V> {
+    fn from((key, item): (K, Item<V>)) -> Self {
+        Removed {
+            key,
+            value: item.value,
+            weight: item.weight,
+            freq: item.freq,
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct FIFOReinsertion<K, V> {
-    hash: HashMap<K, Item<V>>,
-    vec_deque: VecDeque<K>,
+pub struct FIFOReinsertion<K, V, S = DefaultState> {
+    entries: Table<K, Item<V>, S>,
+    /// Handles in queue order, the stale handles of removed entries included.
+    vec_deque: VecDeque<Handle>,
+    stale: usize,
     used_capacity: usize,
     capacity: usize,
+    overcommit: usize,
     max_freq: usize,
+    generation: u64,
 }
 
 #[derive(Debug)]
-pub enum FIFOReinsertionError {
-    BeyondCapacity,
+pub enum FIFOReinsertionError<K> {
+    BeyondCapacity {
+        key: K,
+        weight: usize,
+        capacity: usize,
+    },
 }
 
-type RemovedKeys<K> = Vec<K>;
+type RemovedEntries<K, V> = Vec<Removed<K, V>>;
 
 impl<K, V> FIFOReinsertion<K, V>
 where
-    K: Eq + Hash + Debug + Clone,
-    V: Debug,
+    K: Eq + Hash,
 {
     #[must_use]
     pub fn new(capacity: usize) -> Self {
+        Self::with_hasher(capacity, DefaultState::default())
+    }
+
+    #[must_use]
+    #[allow(dead_code)]
+    pub fn new_with_max_freq(capacity: usize, max_freq: usize) -> Self {
         FIFOReinsertion {
-            hash: HashMap::new(),
-            vec_deque: VecDeque::new(),
-            used_capacity: 0,
-            capacity,
-            max_freq: 3,
+            max_freq,
+            ..Self::new(capacity)
         }
     }
+}
 
+impl<K, V, S> FIFOReinsertion<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
     #[must_use]
-    pub fn new_with_max_freq(capacity: usize, max_freq: usize) -> Self {
+    pub fn with_hasher(capacity: usize, hasher: S) -> Self {
         FIFOReinsertion {
-            hash: HashMap::new(),
+            entries: Table::with_hasher(hasher),
             vec_deque: VecDeque::new(),
+            stale: 0,
             used_capacity: 0,
             capacity,
-            max_freq,
+            overcommit: 0,
+            max_freq: 3,
+            generation: 0,
         }
     }
 
+    fn item(&self, key: &K) -> Option<&Item<V>> {
+        self.entries
+            .get(self.entries.find(key)?)
+            .map(|(_, item)| item)
+    }
+
+    /// Counts a hit on the entry of `handle`.
+    fn hit(&mut self, handle: Handle) {
+        let item = self.entries.get_mut(handle).unwrap();
+        item.freq = min(item.freq + 1, self.max_freq);
+        item.generation = self.generation;
+    }
+
     pub fn get(&mut self, key: &K) -> Option<&V> {
-        if let Some(item) = self.hash.get_mut(key) {
-            if item.removed {
-                return None;
-            }
+        self.get_key_value(key).map(|(_, value)| value)
+    }
 
-            item.freq = min(item.freq + 1, self.max_freq);
-            Some(&item.value)
-        } else {
-            None
-        }
+    pub fn get_key_value(&mut self, key: &K) -> Option<(&K, &V)> {
+        let handle = self.entries.find(key)?;
+        self.hit(handle);
+        self.entries
+            .get(handle)
+            .map(|(key, item)| (key, &item.value))
     }
 
     fn update(
         &mut self,
-        key: &K,
+        handle: Handle,
         value: V,
         weight: usize,
         freq: Option<usize>,
-    ) -> Option<RemovedKeys<K>> {
-        let item = self.hash.get_mut(key).unwrap();
-        item.value = value;
+    ) -> (V, usize, Option<RemovedEntries<K, V>>) {
+        let item = self.entries.get_mut(handle).unwrap();
+        let old_value = mem::replace(&mut item.value, value);
         let old_weight = item.weight;
         item.weight = weight;
-        item.removed = false;
+        item.generation = self.generation;
 
         if let Some(freq) = freq {
             item.freq = freq;
@@ -86,34 +134,35 @@ where
 
         if weight > old_weight {
             let needed_space = weight - old_weight;
-            let removed_keys = self.free(needed_space, Some(key));
+            let removed_keys = self.free(needed_space, Some(handle));
             self.used_capacity += needed_space;
-            removed_keys
+            (old_value, old_weight, removed_keys)
         } else {
             self.used_capacity -= old_weight - weight;
-            None
+            (old_value, old_weight, None)
         }
     }
 
     pub fn insert(
         &mut self,
-        key: &K,
+        key: K,
         value: V,
         weight: usize,
         freq: Option<usize>,
-    ) -> Option<RemovedKeys<K>> {
+    ) -> Option<RemovedEntries<K, V>> {
         let removed_keys = self.free(weight, None);
         self.used_capacity += weight;
-        self.hash.insert(
-            key.clone(),
+        let handle = self.entries.insert(
+            key,
             Item {
                 value,
                 weight,
                 freq: freq.unwrap_or(0),
-                removed: false,
+                pin: Weak::new(),
+                generation: self.generation,
             },
         );
-        self.vec_deque.push_back(key.clone());
+        self.vec_deque.push_back(handle);
 
         removed_keys
     }
@@ -122,67 +171,115 @@ where
     /// # Errors
     ///
     /// Returns `CacheError::BeyondCapacity` if the weight is greater than the capacity.
+    #[allow(dead_code)]
     pub fn put(
         &mut self,
-        key: &K,
+        key: K,
+        value: V,
+        weight: usize,
+    ) -> Result<Option<RemovedEntries<K, V>>, FIFOReinsertionError<K>> {
+        self.replace(key, value, weight, None)
+            .map(|(_, removed)| removed)
+    }
+
+    /// Like [`FIFOReinsertion::put`], but hands back the entry an update of
+    /// `key` replaced, under the key given to the update. `freq` overrides
+    /// the frequency of the entry, like [`FIFOReinsertion::put_with_freq`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `CacheError::BeyondCapacity` if the weight is greater than the capacity.
+    pub fn replace(
+        &mut self,
+        key: K,
         value: V,
         weight: usize,
-    ) -> Result<Option<RemovedKeys<K>>, FIFOReinsertionError> {
+        freq: Option<usize>,
+    ) -> Result<Replaced<K, V>, FIFOReinsertionError<K>> {
         if weight > self.capacity {
-            return Err(FIFOReinsertionError::BeyondCapacity);
+            return Err(FIFOReinsertionError::BeyondCapacity {
+                key,
+                weight,
+                capacity: self.capacity,
+            });
         }
 
-        if self.hash.contains_key(key) {
-            Ok(self.update(key, value, weight, None))
-        } else {
-            Ok(self.insert(key, value, weight, None))
+        match self.entries.find(&key) {
+            Some(handle) => {
+                let (_, item) = self.entries.get(handle).unwrap();
+                let old_freq = item.freq;
+                let (value, weight, removed) = self.update(handle, value, weight, freq);
+                let replaced = Removed {
+                    key,
+                    value,
+                    weight,
+                    freq: old_freq,
+                };
+                Ok((Some(replaced), removed))
+            }
+            None => Ok((None, self.insert(key, value, weight, freq))),
         }
     }
 
     pub fn put_with_freq(
         &mut self,
-        key: &K,
+        key: K,
         value: V,
         weight: usize,
         freq: usize,
-    ) -> Result<Option<RemovedKeys<K>>, FIFOReinsertionError> {
+    ) -> Result<Option<RemovedEntries<K, V>>, FIFOReinsertionError<K>> {
         if weight > self.capacity {
-            return Err(FIFOReinsertionError::BeyondCapacity);
+            return Err(FIFOReinsertionError::BeyondCapacity {
+                key,
+                weight,
+                capacity: self.capacity,
+            });
         }
 
-        if self.hash.contains_key(key) {
-            Ok(self.update(key, value, weight, Some(freq)))
-        } else {
-            Ok(self.insert(key, value, weight, Some(freq)))
+        match self.entries.find(&key) {
+            Some(handle) => {
+                let (_, _, removed) = self.update(handle, value, weight, Some(freq));
+                Ok(removed)
+            }
+            None => Ok(self.insert(key, value, weight, Some(freq))),
         }
     }
 
-    fn free(&mut self, weight: usize, ignore_key: Option<&K>) -> Option<RemovedKeys<K>> {
+    fn free(&mut self, weight: usize, ignore: Option<Handle>) -> Option<RemovedEntries<K, V>> {
         let mut removed_keys = vec![];
-        while self.used_capacity + weight > self.capacity {
-            let key = self.vec_deque.pop_front().unwrap();
-            let item = self.hash.get_mut(&key).unwrap();
-
-            if item.removed {
-                self.used_capacity -= item.weight;
-                self.hash.remove(&key);
+        let mut skipped = 0;
+        while self.used_capacity + weight > self.hard_capacity() {
+            let Some(handle) = self.vec_deque.pop_front() else {
+                break;
+            };
+            let Some(item) = self.entries.get_mut(handle) else {
+                self.stale -= 1;
+                skipped = 0;
                 continue;
-            }
-
-            if Some(&key) == ignore_key {
-                self.vec_deque.push_back(key);
+            };
+
+            if Some(handle) == ignore || item.pin.strong_count() > 0 {
+                self.vec_deque.push_back(handle);
+                // Only pinned entries are left, so the queue goes over
+                // capacity until they are unpinned.
+                skipped += 1;
+                if skipped >= self.vec_deque.len() {
+                    break;
+                }
                 continue;
             }
 
             if item.freq > 0 {
-                self.vec_deque.push_back(key);
+                self.vec_deque.push_back(handle);
                 item.freq -= 1;
+                skipped = 0;
                 continue;
             }
 
-            self.used_capacity -= item.weight;
-            self.hash.remove(&key);
-            removed_keys.push(key);
+            let removed = self.entries.remove(handle).unwrap();
+            self.used_capacity -= removed.1.weight;
+            removed_keys.push(removed.into());
+            skipped = 0;
         }
 
         if removed_keys.is_empty() {
@@ -192,12 +289,151 @@ where
         }
     }
 
-    pub fn remove(&mut self, key: &K) {
-        let item = self.hash.get_mut(key);
+    /// Counts a hit on `key` and pins it: the entry is not evicted while the
+    /// returned token is alive.
+    pub fn pin(&mut self, key: &K) -> Option<(&V, Arc<()>)> {
+        let handle = self.entries.find(key)?;
+        self.hit(handle);
+        let item = self.entries.get_mut(handle).unwrap();
+        let pin = item.pin.upgrade().unwrap_or_else(|| {
+            let pin = Arc::new(());
+            item.pin = Arc::downgrade(&pin);
+            pin
+        });
+        Some((&item.value, pin))
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.find(key).is_some()
+    }
+
+    /// Removes every entry, in queue order.
+    pub fn drain(&mut self) -> Vec<Removed<K, V>> {
+        let mut removed = vec![];
+        for handle in std::mem::take(&mut self.vec_deque) {
+            if let Some(entry) = self.entries.remove(handle) {
+                removed.push(entry.into());
+            }
+        }
+        self.entries.clear();
+        self.stale = 0;
+        self.used_capacity = 0;
+
+        removed
+    }
+
+    /// Drops the stale handles removals left in the queue. Returns the number
+    /// of handles dropped.
+    pub fn compact(&mut self) -> usize {
+        let entries = &self.entries;
+        self.vec_deque
+            .retain(|handle| entries.get(*handle).is_some());
+        debug_assert_eq!(self.entries.len(), self.vec_deque.len());
+
+        std::mem::take(&mut self.stale)
+    }
+
+    /// Starts a new generation and removes the unpinned entries that were
+    /// neither put nor hit during the last `generations` ones, in queue order.
+    pub fn rotate(&mut self, generations: u64) -> RemovedEntries<K, V> {
+        self.generation += 1;
+        let generation = self.generation;
+        self.extract_if_item(|_, item| {
+            generation - item.generation >= generations && item.pin.strong_count() == 0
+        })
+    }
 
-        if let Some(item) = item {
-            item.removed = true;
+    /// Removes the entries matching `predicate`, in queue order.
+    pub fn extract_if<F>(&mut self, mut predicate: F) -> Vec<Removed<K, V>>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.extract_if_item(|key, item| predicate(key, &item.value))
+    }
+
+    fn extract_if_item<F>(&mut self, mut predicate: F) -> Vec<Removed<K, V>>
+    where
+        F: FnMut(&K, &Item<V>) -> bool,
+    {
+        let mut extracted = vec![];
+        let mut vec_deque = VecDeque::with_capacity(self.entries.len());
+        for handle in std::mem::take(&mut self.vec_deque) {
+            let Some((key, item)) = self.entries.get(handle) else {
+                continue;
+            };
+            if !predicate(key, item) {
+                vec_deque.push_back(handle);
+                continue;
+            }
+
+            let removed = self.entries.remove(handle).unwrap();
+            self.used_capacity -= removed.1.weight;
+            extracted.push(removed.into());
         }
+        self.vec_deque = vec_deque;
+        self.stale = 0;
+
+        extracted
+    }
+
+    /// Returns the value and weight of `key` without counting a hit.
+    pub fn peek(&self, key: &K) -> Option<(&V, usize)> {
+        self.item(key).map(|item| (&item.value, item.weight))
+    }
+
+    /// Entries with their weight, in queue order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        self.vec_deque
+            .iter()
+            .filter_map(|handle| self.entries.get(*handle))
+            .map(|(key, item)| (key, &item.value, item.weight))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn used_capacity(&self) -> usize {
+        self.used_capacity
+    }
+
+    /// Lets puts go `overcommit` over the capacity before evicting.
+    pub fn set_overcommit(&mut self, overcommit: usize) {
+        self.overcommit = overcommit;
+    }
+
+    pub fn hard_capacity(&self) -> usize {
+        self.capacity.saturating_add(self.overcommit)
+    }
+
+    /// Evicts entries until the used capacity is back within the capacity.
+    pub fn maintain(&mut self) -> Option<RemovedEntries<K, V>> {
+        self.shrink(self.used_capacity.saturating_sub(self.capacity))
+    }
+
+    /// Evicts entries, following the usual order, until `weight` of the used
+    /// capacity is freed or only pinned entries are left.
+    pub fn shrink(&mut self, weight: usize) -> Option<RemovedEntries<K, V>> {
+        let target = self.used_capacity.saturating_sub(weight);
+        self.free(self.hard_capacity().saturating_sub(target), None)
+    }
+
+    /// Removes `key` and returns its value and weight. Its weight is freed
+    /// right away, and its handle is left stale in the queue until popped,
+    /// or until stale handles outnumber the entries and the queue is
+    /// compacted.
+    pub fn remove(&mut self, key: &K) -> Option<(V, usize)> {
+        let (_, item) = self.entries.remove(self.entries.find(key)?)?;
+        self.used_capacity -= item.weight;
+        self.stale += 1;
+        if self.stale > self.entries.len() {
+            self.compact();
+        }
+        Some((item.value, item.weight))
     }
 }
 
@@ -208,7 +444,7 @@ mod tests {
     #[test]
     fn it_works() {
         let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 2).unwrap();
+        cache.put(1, 1, 2).unwrap();
         assert_eq!(cache.get(&1), Some(&1));
         assert_eq!(cache.get(&2), None);
 
@@ -219,10 +455,10 @@ mod tests {
     #[test]
     fn it_should_free_space() {
         let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
         cache.free(5, None);
 
@@ -237,10 +473,10 @@ mod tests {
     #[test]
     fn it_should_remove() {
         let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
         cache.remove(&2);
 
@@ -249,20 +485,20 @@ mod tests {
         assert_eq!(cache.get(&3), Some(&3));
         assert_eq!(cache.get(&4), Some(&4));
 
-        assert_eq!(cache.used_capacity, 10);
+        assert_eq!(cache.used_capacity, 7);
     }
 
     #[test]
     fn it_should_hit_and_stay_in_cache() {
         let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
         cache.get(&1);
 
-        cache.put(&5, 5, 5).unwrap();
+        cache.put(5, 5, 5).unwrap();
 
         assert_eq!(cache.get(&1), Some(&1));
         assert_eq!(cache.get(&2), None);
@@ -273,27 +509,54 @@ mod tests {
         assert_eq!(cache.used_capacity, 8);
     }
 
+    #[test]
+    fn it_should_extract_matching_entries() {
+        let mut cache = FIFOReinsertion::new(10);
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
+        cache.remove(&4);
+
+        let extracted = cache.extract_if(|key, _| key % 2 == 0);
+
+        assert_eq!(
+            extracted,
+            vec![Removed {
+                key: 2,
+                value: 2,
+                weight: 3,
+                freq: 0,
+            }]
+        );
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&3), Some(&3));
+        let keys: Vec<_> = cache.iter().map(|(key, _, _)| *key).collect();
+        assert_eq!(keys, vec![1, 3]);
+        assert_eq!(cache.used_capacity, 6);
+    }
+
     #[test]
     #[should_panic = "BeyondCapacity"]
     fn it_should_panic() {
         let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
-        cache.put(&5, 5, 11).unwrap();
+        cache.put(5, 5, 11).unwrap();
     }
 
     #[test]
     fn it_should_update() {
         let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        cache.put(&2, 2, 3).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 2).unwrap();
+        cache.put(2, 2, 3).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
-        cache.put(&1, 10, 3).unwrap();
+        cache.put(1, 10, 3).unwrap();
 
         assert_eq!(cache.get(&1), Some(&10));
         assert_eq!(cache.get(&2), None);
@@ -306,12 +569,12 @@ mod tests {
     #[test]
     fn it_should_update_to_lower_weight() {
         let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 3).unwrap();
-        cache.put(&2, 2, 2).unwrap();
-        cache.put(&3, 3, 4).unwrap();
-        cache.put(&4, 4, 1).unwrap();
+        cache.put(1, 1, 3).unwrap();
+        cache.put(2, 2, 2).unwrap();
+        cache.put(3, 3, 4).unwrap();
+        cache.put(4, 4, 1).unwrap();
 
-        cache.put(&1, 10, 2).unwrap();
+        cache.put(1, 10, 2).unwrap();
 
         assert_eq!(cache.get(&1), Some(&10));
         assert_eq!(cache.get(&2), Some(&2));
@@ -325,14 +588,14 @@ mod tests {
     fn it_should_remove_removed_key() {
         let mut cache = FIFOReinsertion::new(2);
 
-        cache.put(&1, 1, 1).unwrap();
+        cache.put(1, 1, 1).unwrap();
         cache.remove(&1);
-        cache.put(&2, 2, 2).unwrap();
+        cache.put(2, 2, 2).unwrap();
 
         assert_eq!(cache.get(&1), None);
         assert_eq!(cache.get(&2), Some(&2));
         assert_eq!(cache.vec_deque.len(), 1);
-        assert_eq!(cache.hash.len(), 1);
+        assert_eq!(cache.entries.len(), 1);
         assert_eq!(cache.used_capacity, 2);
     }
 
@@ -340,17 +603,17 @@ mod tests {
     fn it_should_remove_removed_key_2() {
         let mut cache = FIFOReinsertion::new(3);
 
-        cache.put(&1, 1, 1).unwrap();
+        cache.put(1, 1, 1).unwrap();
         cache.remove(&1);
-        cache.put(&2, 2, 2).unwrap();
-        cache.put(&3, 3, 1).unwrap();
+        cache.put(2, 2, 2).unwrap();
+        cache.put(3, 3, 1).unwrap();
 
         assert_eq!(cache.get(&1), None);
         assert_eq!(cache.get(&2), Some(&2));
         assert_eq!(cache.get(&3), Some(&3));
 
         assert_eq!(cache.vec_deque.len(), 2);
-        assert_eq!(cache.hash.len(), 2);
+        assert_eq!(cache.entries.len(), 2);
         assert_eq!(cache.used_capacity, 3);
     }
 
@@ -358,19 +621,43 @@ mod tests {
     fn it_should_return_removed_key() {
         let mut cache = FIFOReinsertion::new(3);
 
-        cache.put(&1, 1, 1).unwrap();
-        cache.put(&2, 2, 2).unwrap();
+        cache.put(1, 1, 1).unwrap();
+        cache.put(2, 2, 2).unwrap();
 
-        let removed_keys = cache.put(&3, 3, 1).unwrap().unwrap();
+        let removed = cache.put(3, 3, 1).unwrap().unwrap();
 
-        assert_eq!(removed_keys, vec![1]);
+        assert_eq!(
+            removed,
+            vec![Removed {
+                key: 1,
+                value: 1,
+                weight: 1,
+                freq: 0,
+            }]
+        );
         assert_eq!(cache.get(&1), None);
         assert_eq!(cache.get(&2), Some(&2));
         assert_eq!(cache.get(&3), Some(&3));
         assert_eq!(cache.get(&4), None);
 
         assert_eq!(cache.vec_deque.len(), 2);
-        assert_eq!(cache.hash.len(), 2);
+        assert_eq!(cache.entries.len(), 2);
         assert_eq!(cache.used_capacity, 3);
     }
+
+    #[test]
+    fn it_should_rotate_out_unproven_entries() {
+        let mut cache = FIFOReinsertion::new(10);
+        cache.put(1, 1, 1).unwrap();
+        cache.put(2, 2, 1).unwrap();
+
+        assert!(cache.rotate(2).is_empty());
+        cache.get(&1);
+        let removed = cache.rotate(2);
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].key, 2);
+        assert!(cache.contains_key(&1));
+        assert_eq!(cache.used_capacity, 1);
+    }
 }
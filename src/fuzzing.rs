@@ -0,0 +1,154 @@
+//! Differential harness behind the `fuzz` crate: runs an op sequence decoded
+//! from raw bytes against [`S3FIFO`] and a reference model, and panics as soon
+//! as they disagree.
+//!
+//! The model only knows the last value written to every key. Eviction is up
+//! to the cache, but a key it returns must hold its latest value, and every
+//! queue must stay within its capacity.
+
+use crate::{Hint, S3FIFO};
+
+use std::collections::HashMap;
+
+const CAPACITY: usize = 40;
+const KEYS: u8 = 16;
+
+#[derive(Debug)]
+enum Op {
+    Put(u8, u32, usize),
+    PutWithHint(u8, u32, usize, Hint),
+    Get(u8),
+    Remove(u8),
+}
+
+impl Op {
+    /// Decodes one op from three bytes: opcode, key and value. Weights go
+    /// up to 6 so puts heavier than the small queue are exercised too.
+    fn decode(bytes: &[u8]) -> Op {
+        let key = bytes[1] % KEYS;
+        let value = u32::from(bytes[2]);
+        let weight = 1 + usize::from(bytes[2] % 6);
+        match bytes[0] % 6 {
+            0 | 1 => Op::Put(key, value, weight),
+            2 => Op::PutWithHint(key, value, weight, Hint::Hot),
+            3 => Op::PutWithHint(key, value, weight, Hint::Cold),
+            4 => Op::Get(key),
+            _ => Op::Remove(key),
+        }
+    }
+}
+
+/// Runs the ops encoded in `data`, panicking on the first divergence.
+pub fn run(data: &[u8]) {
+    let mut cache = S3FIFO::new(CAPACITY);
+    let mut model = HashMap::new();
+
+    for bytes in data.chunks_exact(3) {
+        match Op::decode(bytes) {
+            Op::Put(key, value, weight) => {
+                let result = cache.put(&key, value, weight);
+                check_put(&cache, &mut model, key, value, result.ok());
+            }
+            Op::PutWithHint(key, value, weight, hint) => {
+                let result = cache.put_with_hint(&key, value, weight, hint);
+                check_put(&cache, &mut model, key, value, result.ok());
+            }
+            Op::Get(key) => {
+                let value = cache.get(&key).copied();
+                assert!(
+                    value.is_none() || value == model.get(&key).copied(),
+                    "get({key}) returned {value:?}, latest write is {:?}",
+                    model.get(&key)
+                );
+            }
+            Op::Remove(key) => {
+                cache.remove(&key);
+                model.remove(&key);
+                assert_eq!(peek(&cache, key), None, "removed key {key} is visible");
+            }
+        }
+        check_invariants(&cache, &model);
+    }
+}
+
+fn check_put(
+    cache: &S3FIFO<u8, u32>,
+    model: &mut HashMap<u8, u32>,
+    key: u8,
+    value: u32,
+    evicted: Option<Option<Vec<u8>>>,
+) {
+    // A failed put leaves the cache as it was.
+    let Some(evicted) = evicted else {
+        return;
+    };
+
+    model.insert(key, value);
+    assert_eq!(
+        peek(cache, key),
+        Some(value),
+        "put key {key} is not visible"
+    );
+    for evicted in evicted.into_iter().flatten() {
+        assert_ne!(evicted, key, "put evicted its own key {key}");
+        assert_eq!(
+            peek(cache, evicted),
+            None,
+            "evicted key {evicted} is visible"
+        );
+    }
+}
+
+fn check_invariants(cache: &S3FIFO<u8, u32>, model: &HashMap<u8, u32>) {
+    for small in &cache.small {
+        assert!(small.used_capacity() <= small.capacity());
+    }
+    assert!(cache.main.used_capacity() <= cache.main.capacity());
+    if let Some(ghost) = &cache.ghost {
+        assert!(ghost.used_capacity() <= ghost.capacity());
+    }
+
+    for key in 0..KEYS {
+        let value = peek(cache, key);
+        assert!(
+            value.is_none() || value == model.get(&key).copied(),
+            "key {key} holds {value:?}, latest write is {:?}",
+            model.get(&key)
+        );
+        assert!(
+            value.is_none() || !cache.ghost_contains(&key),
+            "live key {key} is in the ghost queue"
+        );
+    }
+}
+
+/// Looks `key` up like [`S3FIFO::get`] without counting a hit.
+fn peek(cache: &S3FIFO<u8, u32>, key: u8) -> Option<u32> {
+    cache
+        .small
+        .iter()
+        .find_map(|small| small.peek(&key))
+        .or_else(|| cache.main.peek(&key))
+        .map(|(value, _)| *value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_agree_with_the_model() {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..200 {
+            let data = (0..300)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state.to_le_bytes()[0]
+                })
+                .collect::<Vec<_>>();
+            run(&data);
+        }
+    }
+}
@@ -1,138 +1,247 @@
+use crate::hash::{BuildIdentityHasher, DefaultState};
+use crate::slab::{Handle, Slab};
+
 use std::collections::{HashMap, VecDeque};
-use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
 
 #[derive(Debug)]
 struct Item {
+    fingerprint: u64,
     weight: usize,
-    removed: bool,
 }
 
+/// Keys recently evicted from the small queues, remembered by fingerprint,
+/// the hash of the key, rather than by the key itself.
+///
+/// Keys sharing a fingerprint are taken for one another, which at worst
+/// admits an entry into the main queue without its key being seen before.
 #[derive(Debug)]
-pub struct GhostFIFO<K> {
-    hash: HashMap<K, Item>,
-    vec_deque: VecDeque<K>,
+pub struct GhostFIFO<K, S = DefaultState> {
+    hash: HashMap<u64, Handle, BuildIdentityHasher>,
+    items: Slab<Item>,
+    /// Handles in queue order, the stale handles of removed keys included.
+    vec_deque: VecDeque<Handle>,
+    stale: usize,
+    hasher: S,
     used_capacity: usize,
     capacity: usize,
+    prune_batch: usize,
+    key: PhantomData<fn(&K)>,
 }
 
+/// Once the ghost queue is full, every prune drops at least
+/// `capacity / PRUNE_BATCH_DIVISOR` keys, so the following puts find room
+/// without touching the queue.
+const PRUNE_BATCH_DIVISOR: usize = 64;
+
 #[derive(Debug)]
 pub enum GhostFIFOError {
     BeyondCapacity,
 }
 
-type RemovedKeys<K> = Vec<K>;
-
 impl<K> GhostFIFO<K>
 where
-    K: Eq + Hash + Debug + Clone,
+    K: Hash,
 {
     #[must_use]
+    #[allow(dead_code)]
     pub fn new(capacity: usize) -> Self {
+        Self::with_hasher(capacity, DefaultState::default())
+    }
+}
+
+impl<K, S> GhostFIFO<K, S>
+where
+    K: Hash,
+    S: BuildHasher,
+{
+    #[must_use]
+    pub fn with_hasher(capacity: usize, hasher: S) -> Self {
         GhostFIFO {
-            hash: HashMap::new(),
+            hash: HashMap::default(),
+            items: Slab::default(),
             vec_deque: VecDeque::new(),
+            stale: 0,
+            hasher,
             used_capacity: 0,
             capacity,
+            prune_batch: (capacity / PRUNE_BATCH_DIVISOR).max(1),
+            key: PhantomData,
         }
     }
 
+    fn fingerprint(&self, key: &K) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    /// Whether `other` gives keys the same fingerprints, so its fingerprints
+    /// can be moved over.
+    pub fn hashes_like(&self, other: &Self) -> bool {
+        [0, u64::MAX]
+            .iter()
+            .all(|probe| self.hasher.hash_one(probe) == other.hasher.hash_one(probe))
+    }
+
+    #[allow(dead_code)]
     pub fn get(&mut self, key: &K) -> bool {
-        if let Some(item) = self.hash.get(key) {
-            if item.removed {
-                return false;
-            }
-            return true;
-        }
-        false
+        self.contains(key)
     }
 
-    fn update(&mut self, key: &K, weight: usize) -> Option<RemovedKeys<K>> {
-        let item = self.hash.get_mut(key).unwrap();
+    pub fn contains(&self, key: &K) -> bool {
+        self.hash.contains_key(&self.fingerprint(key))
+    }
+
+    /// Number of keys that entered the ghost queue after `key`, or `None` if
+    /// `key` is not in the ghost queue.
+    pub fn depth(&self, key: &K) -> Option<usize> {
+        let handle = *self.hash.get(&self.fingerprint(key))?;
+
+        self.vec_deque
+            .iter()
+            .rev()
+            .filter(|ghost| self.items.get(**ghost).is_some())
+            .position(|ghost| *ghost == handle)
+    }
+
+    pub fn used_capacity(&self) -> usize {
+        self.used_capacity
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    fn update(&mut self, handle: Handle, weight: usize) -> usize {
+        let item = self.items.get_mut(handle).unwrap();
         let old_weight = item.weight;
         item.weight = weight;
-        item.removed = false;
 
         if weight > old_weight {
             let needed_space = weight - old_weight;
-            let removed_keys = self.free(needed_space, Some(key));
+            let pruned = self.free(needed_space, Some(handle));
             self.used_capacity += needed_space;
-            removed_keys
+            pruned
         } else {
             self.used_capacity -= old_weight - weight;
-            None
+            0
         }
     }
 
-    fn insert(&mut self, key: &K, weight: usize) -> Option<RemovedKeys<K>> {
-        let removed_keys = self.free(weight, None);
+    fn insert(&mut self, fingerprint: u64, weight: usize) -> usize {
+        let pruned = self.free(weight, None);
         self.used_capacity += weight;
-        self.hash.insert(
-            key.clone(),
-            Item {
-                weight,
-                removed: false,
-            },
-        );
-        self.vec_deque.push_back(key.clone());
-
-        removed_keys
+        let handle = self.items.insert(Item {
+            fingerprint,
+            weight,
+        });
+        self.hash.insert(fingerprint, handle);
+        self.vec_deque.push_back(handle);
+
+        pruned
     }
 
-    //
+    /// Remembers `key`. Returns the number of keys pruned to make room.
+    ///
     /// # Errors
     ///
-    /// Returns `CacheError::BeyondCapacity` if the weight is greater than the capacity.
-    pub fn put(
+    /// Returns `GhostFIFOError::BeyondCapacity` if the weight is greater than the capacity.
+    pub fn put(&mut self, key: &K, weight: usize) -> Result<usize, GhostFIFOError> {
+        self.put_fingerprint(self.fingerprint(key), weight)
+    }
+
+    /// # Errors
+    ///
+    /// Returns `GhostFIFOError::BeyondCapacity` if the weight is greater than the capacity.
+    pub fn put_fingerprint(
         &mut self,
-        key: &K,
+        fingerprint: u64,
         weight: usize,
-    ) -> Result<Option<RemovedKeys<K>>, GhostFIFOError> {
+    ) -> Result<usize, GhostFIFOError> {
         if weight > self.capacity {
             return Err(GhostFIFOError::BeyondCapacity);
         }
 
-        if self.hash.contains_key(key) {
-            Ok(self.update(key, weight))
-        } else {
-            Ok(self.insert(key, weight))
+        match self.hash.get(&fingerprint) {
+            Some(handle) => Ok(self.update(*handle, weight)),
+            None => Ok(self.insert(fingerprint, weight)),
         }
     }
 
-    fn free(&mut self, weight: usize, ignore_key: Option<&K>) -> Option<RemovedKeys<K>> {
-        let mut removed_keys = vec![];
-        while self.used_capacity + weight > self.capacity {
-            let key = self.vec_deque.pop_front().unwrap();
-            let item = self.hash.get_mut(&key).unwrap();
-
-            if item.removed {
-                self.used_capacity -= item.weight;
-                self.hash.remove(&key);
+    fn free(&mut self, weight: usize, ignore: Option<Handle>) -> usize {
+        let mut pruned = 0;
+        while self.used_capacity + weight > self.capacity
+            || (pruned > 0 && pruned < self.prune_batch)
+        {
+            let Some(handle) = self.vec_deque.pop_front() else {
+                break;
+            };
+            if self.items.get(handle).is_none() {
+                self.stale -= 1;
                 continue;
             }
 
-            if Some(&key) == ignore_key {
-                self.vec_deque.push_back(key);
+            if Some(handle) == ignore {
+                self.vec_deque.push_back(handle);
+                // Only the updated key is left to prune.
+                if self.used_capacity + weight <= self.capacity {
+                    break;
+                }
                 continue;
             }
 
+            let item = self.items.remove(handle).unwrap();
+            self.hash.remove(&item.fingerprint);
             self.used_capacity -= item.weight;
-            self.hash.remove(&key);
-            removed_keys.push(key);
+            pruned += 1;
         }
 
-        if removed_keys.is_empty() {
-            None
-        } else {
-            Some(removed_keys)
+        pruned
+    }
+
+    /// Drops the stale handles removals left in the queue. Returns the number
+    /// of handles dropped.
+    pub fn compact(&mut self) -> usize {
+        let items = &self.items;
+        self.vec_deque.retain(|handle| items.get(*handle).is_some());
+        debug_assert_eq!(self.hash.len(), self.vec_deque.len());
+
+        std::mem::take(&mut self.stale)
+    }
+
+    /// Removes every fingerprint with its weight, in queue order.
+    pub fn drain(&mut self) -> Vec<(u64, usize)> {
+        let mut removed = vec![];
+        for handle in std::mem::take(&mut self.vec_deque) {
+            if let Some(item) = self.items.remove(handle) {
+                removed.push((item.fingerprint, item.weight));
+            }
         }
+        self.hash.clear();
+        self.items.clear();
+        self.stale = 0;
+        self.used_capacity = 0;
+
+        removed
     }
 
+    /// Forgets `key`, freeing its weight right away. Its handle is left stale
+    /// in the queue until popped, or until stale handles outnumber the keys
+    /// and the queue is compacted.
     pub fn remove(&mut self, key: &K) {
-        let item = self.hash.get_mut(key);
-
-        if let Some(item) = item {
-            item.removed = true;
+        let Some(handle) = self.hash.remove(&self.fingerprint(key)) else {
+            return;
+        };
+        if let Some(item) = self.items.remove(handle) {
+            self.used_capacity -= item.weight;
+            self.stale += 1;
+        }
+        if self.stale > self.hash.len() {
+            self.compact();
         }
     }
 }
@@ -165,6 +274,22 @@ mod tests {
         assert_eq!(cache.capacity, 10);
     }
 
+    #[test]
+    fn it_should_report_depth() {
+        let mut cache = GhostFIFO::new(10);
+        cache.put(&1, 1).unwrap();
+        cache.put(&2, 1).unwrap();
+        cache.put(&3, 1).unwrap();
+        cache.remove(&2);
+
+        assert!(cache.contains(&1));
+        assert!(!cache.contains(&2));
+        assert_eq!(cache.depth(&3), Some(0));
+        assert_eq!(cache.depth(&1), Some(1));
+        assert_eq!(cache.depth(&2), None);
+        assert_eq!(cache.len(), 2);
+    }
+
     #[test]
     fn it_should_free_space() {
         let mut cache = GhostFIFO::new(10);
@@ -198,7 +323,7 @@ mod tests {
         assert!(cache.get(&3));
         assert!(cache.get(&4));
 
-        assert_eq!(cache.used_capacity, 10);
+        assert_eq!(cache.used_capacity, 7);
     }
 
     #[test]
@@ -304,15 +429,15 @@ mod tests {
     }
 
     #[test]
-    fn it_should_return_removed_key() {
+    fn it_should_count_pruned_keys() {
         let mut cache = GhostFIFO::new(3);
 
         cache.put(&1, 1).unwrap();
         cache.put(&2, 2).unwrap();
 
-        let removed_keys = cache.put(&3, 1).unwrap().unwrap();
+        let pruned = cache.put(&3, 1).unwrap();
 
-        assert_eq!(removed_keys, vec![1]);
+        assert_eq!(pruned, 1);
         assert!(!cache.get(&1));
         assert!(cache.get(&2));
         assert!(cache.get(&3));
@@ -322,4 +447,19 @@ mod tests {
         assert_eq!(cache.hash.len(), 2);
         assert_eq!(cache.used_capacity, 3);
     }
+
+    #[test]
+    fn it_should_prune_in_batches() {
+        let mut cache = GhostFIFO::new(128);
+        for key in 0..128 {
+            cache.put(&key, 1).unwrap();
+        }
+
+        let pruned = cache.put(&128, 1).unwrap();
+
+        assert_eq!(pruned, 2);
+        assert!(!cache.contains(&0) && !cache.contains(&1));
+        assert_eq!(cache.put(&129, 1).unwrap(), 0);
+        assert_eq!(cache.used_capacity, 128);
+    }
 }
@@ -0,0 +1,117 @@
+//! Hashers for the internal maps of the cache.
+//!
+//! Queue order never depends on hashing, but map layout and anything derived
+//! from hashes do. The `deterministic-hash` feature swaps the randomly seeded
+//! std hasher for a fixed-seed one, so runs are reproducible across processes
+//! and machines. Fixed seeds make HashDoS attacks possible, so only enable it
+//! for tests, simulations and debugging.
+
+use std::hash::{BuildHasherDefault, Hasher};
+
+/// Hasher used unless another one is set with
+/// [`crate::S3FIFOBuilder::hasher`].
+#[cfg(feature = "deterministic-hash")]
+pub type DefaultState = BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
+
+/// Hasher used unless another one is set with
+/// [`crate::S3FIFOBuilder::hasher`].
+#[cfg(not(feature = "deterministic-hash"))]
+pub type DefaultState = std::collections::hash_map::RandomState;
+
+/// Hasher that uses integer keys as their own hash, mixed by one multiply
+/// and one xor-shift.
+///
+/// Hashing integer keys with a full hash function is mostly overhead. The
+/// mix is a bijection, so distinct keys of 64 bits or fewer never collide,
+/// and it spreads small and sequential keys over the high bits, which the
+/// maps use to tell the keys of a group apart. Wider keys, such as `u128`,
+/// UUIDs or strings, are folded into 64 bits 8 bytes at a time, so distinct
+/// ones may collide.
+///
+/// There is no seed, so anyone choosing the keys can make them all collide
+/// and degrade the cache to linear scans. Only use it for trusted keys.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityHasher {
+    hash: u64,
+}
+
+/// Odd multiplier of [`IdentityHasher::finish`], 2^64 divided by the golden
+/// ratio.
+const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Builds [`IdentityHasher`]s, pass it to [`crate::S3FIFOBuilder::hasher`].
+pub type BuildIdentityHasher = BuildHasherDefault<IdentityHasher>;
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        let hash = self.hash.wrapping_mul(MIX);
+        hash ^ (hash >> 32)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.write_u64(u64::from(n));
+    }
+
+    fn write_u16(&mut self, n: u16) {
+        self.write_u64(u64::from(n));
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write_u64(u64::from(n));
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.hash = self.hash.rotate_left(5) ^ n;
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.write_u64(n as u64);
+        self.write_u64((n >> 64) as u64);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn it_should_use_integers_as_hash() {
+        let state = BuildIdentityHasher::default();
+        let mix = |n: u64| {
+            let hash = n.wrapping_mul(MIX);
+            hash ^ (hash >> 32)
+        };
+
+        assert_eq!(state.hash_one(42u64), mix(42));
+        assert_eq!(state.hash_one(7u32), mix(7));
+        assert_eq!(state.hash_one(1u128 << 64), mix(1));
+
+        // Sequential keys differ in the top 7 bits the maps tag slots with.
+        let tags: HashSet<_> = (0..64u64).map(|n| state.hash_one(n) >> 57).collect();
+        assert!(tags.len() > 32);
+    }
+
+    #[test]
+    #[cfg(feature = "deterministic-hash")]
+    fn it_should_hash_deterministically() {
+        assert_eq!(
+            DefaultState::default().hash_one(42),
+            DefaultState::default().hash_one(42)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use crate::CloneKey;
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+
+#[derive(Debug)]
+struct Counter {
+    count: u64,
+    error: u64,
+}
+
+/// Space-Saving heavy hitters sketch over cache lookups.
+///
+/// It tracks at most `capacity` keys. A lookup of an untracked key when the
+/// sketch is full takes over the counter with the lowest count, so counts
+/// overestimate the true number of lookups by at most the count they took
+/// over, and any key looked up more than `lookups / capacity` times is
+/// tracked.
+#[derive(Debug)]
+pub struct HotKeys<K, S> {
+    counters: HashMap<K, Counter, S>,
+    capacity: usize,
+    clone_key: CloneKey<K>,
+}
+
+impl<K, S> HotKeys<K, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn with_hasher(capacity: usize, hasher: S, clone_key: CloneKey<K>) -> Self {
+        HotKeys {
+            counters: HashMap::with_capacity_and_hasher(capacity, hasher),
+            capacity,
+            clone_key,
+        }
+    }
+
+    pub fn record(&mut self, key: &K) {
+        if let Some(counter) = self.counters.get_mut(key) {
+            counter.count += 1;
+            return;
+        }
+
+        if self.counters.len() < self.capacity {
+            self.counters
+                .insert((self.clone_key)(key), Counter { count: 1, error: 0 });
+            return;
+        }
+
+        let Some(min_key) = self
+            .counters
+            .iter()
+            .min_by_key(|(_, counter)| counter.count)
+            .map(|(key, _)| (self.clone_key)(key))
+        else {
+            return;
+        };
+        let min = self.counters.remove(&min_key).unwrap();
+        self.counters.insert(
+            (self.clone_key)(key),
+            Counter {
+                count: min.count + 1,
+                error: min.count,
+            },
+        );
+    }
+
+    /// The `k` most looked up keys with their estimated lookup counts, most
+    /// looked up first.
+    pub fn top(&self, k: usize) -> Vec<(K, u64)> {
+        let mut top = self
+            .counters
+            .iter()
+            .map(|(key, counter)| (key, counter.count, counter.error))
+            .collect::<Vec<_>>();
+        // Ties go to the key with the more reliable count.
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)));
+        top.into_iter()
+            .take(k)
+            .map(|(key, count, _)| ((self.clone_key)(key), count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultState;
+
+    #[test]
+    fn it_should_track_heavy_hitters() {
+        let mut hot_keys = HotKeys::with_hasher(2, DefaultState::default(), Clone::clone);
+        for key in [1, 1, 1, 2, 3, 1, 4] {
+            hot_keys.record(&key);
+        }
+
+        assert_eq!(hot_keys.top(1), vec![(1, 4)]);
+        assert_eq!(hot_keys.top(5), vec![(1, 4), (4, 3)]);
+    }
+}
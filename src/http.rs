@@ -0,0 +1,188 @@
+//! HTTP cache semantics for proxies caching responses in [`crate::S3FIFO`].
+//!
+//! [`CachePolicy::from_headers`] turns the `Cache-Control`, `Age` and `ETag`
+//! headers of a response into how long it stays fresh and how much it
+//! weighs. Put fresh responses with [`crate::S3FIFO::put_with_ttl`], or
+//! store the expiry next to the value, like [`crate::MemoCache`] does, to
+//! serve stale responses while revalidating.
+
+use std::time::Duration;
+
+/// The `Cache-Control` directives that matter to a cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub max_age: Option<Duration>,
+    pub s_maxage: Option<Duration>,
+    pub stale_while_revalidate: Option<Duration>,
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub must_revalidate: bool,
+}
+
+impl CacheControl {
+    /// Parses a `Cache-Control` header value. Unknown directives and
+    /// malformed durations are ignored, and directive names are case
+    /// insensitive.
+    #[must_use]
+    pub fn parse(header: &str) -> Self {
+        let mut cache_control = CacheControl::default();
+        for directive in header.split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = value
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => cache_control.max_age = seconds,
+                "s-maxage" => cache_control.s_maxage = seconds,
+                "stale-while-revalidate" => cache_control.stale_while_revalidate = seconds,
+                "no-store" => cache_control.no_store = true,
+                "no-cache" => cache_control.no_cache = true,
+                "private" => cache_control.private = true,
+                "must-revalidate" | "proxy-revalidate" => cache_control.must_revalidate = true,
+                _ => {}
+            }
+        }
+
+        cache_control
+    }
+}
+
+/// Headers of a response, as supplied by the caller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResponseHeaders<'a> {
+    pub cache_control: Option<&'a str>,
+    pub age: Option<&'a str>,
+    pub etag: Option<&'a str>,
+}
+
+/// How to cache a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// How long the response stays fresh from now, `Age` included.
+    pub ttl: Duration,
+    /// How long a stale response may still be served while it is
+    /// revalidated in the background.
+    pub stale_while_revalidate: Duration,
+    /// Weight of the entry: the body length plus the stored `ETag`.
+    pub weight: usize,
+    /// Validator to send in `If-None-Match` once the response is stale.
+    pub etag: Option<String>,
+    /// Stale responses must not be served without revalidation.
+    pub must_revalidate: bool,
+}
+
+impl CachePolicy {
+    /// Derives the policy of a response of `body_len` bytes. `shared` caches,
+    /// like proxies, honor `s-maxage` and never store `private` responses.
+    ///
+    /// Returns `None` if the response must not be stored, or has neither an
+    /// explicit lifetime nor `no-cache`. `no-cache` responses are stored with
+    /// a TTL of zero, so they are revalidated on every use.
+    #[must_use]
+    pub fn from_headers(
+        headers: &ResponseHeaders<'_>,
+        body_len: usize,
+        shared: bool,
+    ) -> Option<Self> {
+        let cache_control = CacheControl::parse(headers.cache_control?);
+        if cache_control.no_store || (shared && cache_control.private) {
+            return None;
+        }
+
+        let lifetime = if shared {
+            cache_control.s_maxage.or(cache_control.max_age)
+        } else {
+            cache_control.max_age
+        };
+        let age = headers
+            .age
+            .and_then(|age| age.trim().parse().ok())
+            .map_or(Duration::ZERO, Duration::from_secs);
+        let ttl = if cache_control.no_cache {
+            Duration::ZERO
+        } else {
+            lifetime?.saturating_sub(age)
+        };
+        let etag = headers.etag.map(str::to_owned);
+
+        Some(CachePolicy {
+            ttl,
+            stale_while_revalidate: cache_control.stale_while_revalidate.unwrap_or_default(),
+            weight: body_len + etag.as_ref().map_or(0, String::len),
+            etag,
+            must_revalidate: cache_control.must_revalidate || cache_control.no_cache,
+        })
+    }
+}
+
+/// Returns `true` if an `If-None-Match` header matches `etag`, using the weak
+/// comparison of conditional requests, so a `304 Not Modified` can be sent.
+#[must_use]
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let etag = opaque(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_cache_control() {
+        let cache_control = CacheControl::parse("public, Max-Age=60, s-maxage=\"120\", no-cache");
+
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(cache_control.s_maxage, Some(Duration::from_secs(120)));
+        assert!(cache_control.no_cache);
+        assert!(!cache_control.no_store);
+    }
+
+    #[test]
+    fn it_should_subtract_age_from_ttl() {
+        let headers = ResponseHeaders {
+            cache_control: Some("max-age=60, s-maxage=120"),
+            age: Some("30"),
+            etag: Some("\"v1\""),
+        };
+
+        let shared = CachePolicy::from_headers(&headers, 10, true).unwrap();
+        let private = CachePolicy::from_headers(&headers, 10, false).unwrap();
+
+        assert_eq!(shared.ttl, Duration::from_secs(90));
+        assert_eq!(shared.weight, 14);
+        assert_eq!(private.ttl, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn it_should_not_store_private_responses_in_shared_caches() {
+        let headers = ResponseHeaders {
+            cache_control: Some("private, max-age=60"),
+            ..ResponseHeaders::default()
+        };
+
+        assert_eq!(CachePolicy::from_headers(&headers, 1, true), None);
+        assert!(CachePolicy::from_headers(&headers, 1, false).is_some());
+
+        let headers = ResponseHeaders {
+            cache_control: Some("no-cache"),
+            ..ResponseHeaders::default()
+        };
+        let policy = CachePolicy::from_headers(&headers, 1, true).unwrap();
+        assert_eq!(policy.ttl, Duration::ZERO);
+        assert!(policy.must_revalidate);
+    }
+
+    #[test]
+    fn it_should_match_weak_etags() {
+        assert!(etag_matches("\"a\", W/\"b\"", "\"b\""));
+        assert!(etag_matches("*", "\"b\""));
+        assert!(!etag_matches("\"a\"", "\"b\""));
+    }
+}
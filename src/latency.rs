@@ -0,0 +1,154 @@
+//! Operation latencies, recorded with the `latency` feature.
+
+use std::time::{Duration, Instant};
+
+/// Values below `2^SUB_BITS` nanoseconds get a bucket each, larger values
+/// `2^SUB_BITS` buckets per power of two, so every bucket is within about 3%
+/// of the values it counts.
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+
+/// Log-linear histogram of durations, in the spirit of HdrHistogram.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    total: u128,
+    max: u64,
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        let index = bucket(nanos);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.count += 1;
+        self.total += u128::from(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[must_use]
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    #[must_use]
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let mean = self.total / u128::from(self.count);
+        Duration::from_nanos(u64::try_from(mean).unwrap_or(u64::MAX))
+    }
+
+    /// Smallest duration at least `percentile` percent of the recorded ones
+    /// don't exceed, rounded up to the end of its bucket and capped at the
+    /// maximum. Zero when nothing was recorded.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * self.count as f64).ceil() as u64)
+            .clamp(1, self.count);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(bucket_end(index).min(self.max));
+            }
+        }
+        self.max()
+    }
+}
+
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let magnitude = 63 - nanos.leading_zeros();
+    let shift = magnitude - SUB_BITS;
+    (magnitude - SUB_BITS + 1) as usize * SUB_BUCKETS + (nanos >> shift) as usize - SUB_BUCKETS
+}
+
+/// Largest value counted by the bucket at `index`.
+fn bucket_end(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub = (index % SUB_BUCKETS + SUB_BUCKETS) as u64;
+    (sub << shift) + ((1 << shift) - 1)
+}
+
+/// Latency histograms of the operations of a cache, see
+/// [`crate::S3FIFO::latency_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Lookups, pinning included.
+    pub get: Histogram,
+    /// Every put, evicting or not.
+    pub put: Histogram,
+    /// Puts that evicted entries and rotations of the main queue, whose
+    /// tail shows how long eviction loops hold up the caller.
+    pub evict: Histogram,
+}
+
+impl LatencyStats {
+    pub(crate) fn record_get(&mut self, start: Instant) {
+        self.get.record(start.elapsed());
+    }
+
+    pub(crate) fn record_put(&mut self, start: Instant, evicted: bool) {
+        let elapsed = start.elapsed();
+        self.put.record(elapsed);
+        if evicted {
+            self.evict.record(elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_bucket_values_within_precision() {
+        for nanos in [0, 1, 31, 32, 33, 63, 64, 1000, 123_456_789, u64::MAX] {
+            let end = bucket_end(bucket(nanos));
+            assert!(end >= nanos);
+            assert!(end - nanos <= nanos / 32);
+        }
+        assert_eq!(bucket(63) + 1, bucket(64));
+    }
+
+    #[test]
+    fn it_should_report_percentiles() {
+        let mut histogram = Histogram::default();
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.max(), Duration::from_micros(100));
+        assert_eq!(histogram.mean(), Duration::from_nanos(50_500));
+        let p50 = histogram.percentile(50.0);
+        assert!(p50 >= Duration::from_micros(50) && p50 < Duration::from_micros(52));
+        assert_eq!(histogram.percentile(100.0), Duration::from_micros(100));
+        assert_eq!(Histogram::default().percentile(99.0), Duration::ZERO);
+    }
+}
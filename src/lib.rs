@@ -1,152 +1,2636 @@
+mod builder;
+mod changes;
+mod concurrent;
+mod events;
+#[cfg(test)]
+mod executor;
+mod expiry;
 mod fifo;
 mod fifo_reinserion;
+#[cfg(any(test, feature = "fuzzing"))]
+#[doc(hidden)]
+pub mod fuzzing;
 mod ghost_fifo;
+mod hash;
+mod hot_keys;
+pub mod http;
+#[cfg(feature = "latency")]
+mod latency;
+mod lifetime;
+mod listener;
+mod memo;
+mod observer;
+mod pin;
+mod pool;
+mod quota;
+mod replication;
+mod report;
+mod router;
+mod shadow;
+pub mod sim;
+mod slab;
+mod spill;
+mod stats;
+mod tier;
+mod version;
+mod weight;
+mod write_behind;
+
+#[cfg(all(test, feature = "memoize"))]
+extern crate self as kesh;
+
+pub use builder::S3FIFOBuilder;
+pub use changes::Change;
+pub use concurrent::{ConcurrentS3FIFO, ShardGuard};
+pub use events::{Event, EventReceiver, DEFAULT_EVENT_BUFFER};
+pub use hash::{BuildIdentityHasher, DefaultState, IdentityHasher};
+#[cfg(feature = "latency")]
+pub use latency::{Histogram, LatencyStats};
+pub use lifetime::LifetimeStats;
+pub use listener::RemovalCause;
+pub use memo::MemoCache;
+pub use observer::CacheObserver;
+pub use pin::EntryRef;
+pub use pool::CapacityPool;
+pub use quota::TenantStats;
+pub use replication::Mutation;
+pub use report::{Evicted, EvictedEntry, EvictionReport};
+pub use router::Router;
+pub use shadow::ShadowStats;
+pub use spill::{SpillCache, SpillStats};
+pub use stats::{CacheStats, Occupancy};
+pub use tier::{RemoteTier, TieredCache};
+pub use weight::{shallow_size, HeapSize, Weighted};
+pub use write_behind::{EvictionSink, WriteBehind};
+
+#[cfg(feature = "memoize")]
+pub use kesh_macros::memoize;
 
+use builder::Layout;
+use changes::ChangeFeed;
+use events::Publisher;
+use expiry::Expiry;
 use fifo::FIFOError;
+use fifo::Removed;
 use fifo::FIFO;
 use fifo_reinserion::FIFOReinsertion;
 use fifo_reinserion::FIFOReinsertionError;
 use ghost_fifo::GhostFIFO;
+use hot_keys::HotKeys;
+use lifetime::Lifetimes;
+use listener::{Listener, RefListener};
+use pool::PoolMember;
+use quota::Quotas;
+use replication::Replicator;
+use shadow::ShadowLru;
+use slab::Table;
+use version::Versions;
+
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type PutResult<K, V> = Result<Option<Vec<EvictedEntry<K, V>>>, S3FIFOError<K>>;
+
+/// Copies a key, for the parts of a cache that keep keys of their own. Set
+/// by the builder options and methods that need them, which require
+/// `K: Clone`, so the cache itself doesn't.
+type CloneKey<K> = fn(&K) -> K;
+
+/// Picks the small queue of a key, see [`S3FIFOBuilder::small_queues`].
+type Classifier<K> = Arc<dyn Fn(&K) -> usize + Send + Sync>;
+
+/// Weighs the entries of [`S3FIFO::insert`], see [`S3FIFOBuilder::weigher`].
+type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+/// Write deferred by [`S3FIFO::freeze`].
+enum QueuedWrite<K, V> {
+    Put {
+        key: K,
+        value: V,
+        weight: usize,
+        hint: Option<Hint>,
+        deadline: Option<(K, Instant)>,
+    },
+    Remove(K),
+}
+
+/// Writes deferred by [`S3FIFO::freeze`], and the copy a deferred remove
+/// keeps of its key.
+struct Frozen<K, V> {
+    writes: Vec<QueuedWrite<K, V>>,
+    clone_key: CloneKey<K>,
+}
+
+/// Entries moved out of a cache by [`S3FIFO::extract_if`], with their
+/// deadlines.
+pub(crate) struct Extracted<K, V> {
+    main: Vec<Removed<K, V>>,
+    small: Vec<Removed<K, V>>,
+    deadlines: Vec<(K, Instant)>,
+}
+
+impl<K, V> Extracted<K, V> {
+    /// Splits the entries into `count` parts, in their order, putting each
+    /// one into the part `part` picks for its key.
+    pub(crate) fn partition<F>(self, count: usize, mut part: F) -> Vec<Extracted<K, V>>
+    where
+        F: FnMut(&K) -> usize,
+    {
+        let mut parts: Vec<_> = (0..count)
+            .map(|_| Extracted {
+                main: vec![],
+                small: vec![],
+                deadlines: vec![],
+            })
+            .collect();
+        for item in self.main {
+            parts[part(&item.key)].main.push(item);
+        }
+        for item in self.small {
+            parts[part(&item.key)].small.push(item);
+        }
+        for (key, deadline) in self.deadlines {
+            parts[part(&key)].deadlines.push((key, deadline));
+        }
+        parts
+    }
+}
+
+/// S3FIFO cache. `S` builds the hashers of its internal maps, see
+/// [`S3FIFOBuilder::hasher`].
+pub struct S3FIFO<K, V, S = DefaultState> {
+    main: FIFOReinsertion<K, V, S>,
+    small: Vec<FIFO<K, V, S>>,
+    classifier: Option<Classifier<K>>,
+    weigher: Option<Weigher<K, V>>,
+    ghost: Option<GhostFIFO<K, S>>,
+    events: Publisher<K>,
+    listener: Option<RefListener<K, V>>,
+    owning_listener: Option<Listener<K, V>>,
+    observer: Option<Box<dyn CacheObserver<K>>>,
+    hot_keys: Option<HotKeys<K, S>>,
+    shadow: Option<ShadowLru<K, S>>,
+    frozen: Option<Frozen<K, V>>,
+    generations: Option<u64>,
+    quotas: Option<Quotas<K, S>>,
+    versions: Option<Versions<K, S>>,
+    lifetimes: Option<Lifetimes<K, S>>,
+    changes: Option<ChangeFeed<K>>,
+    replication: Option<Replicator<K, V>>,
+    pool: Option<PoolMember>,
+    expiry: Expiry<K, S>,
+    /// Copies the key of a put for the trackers, feeds and observers to see
+    /// it once a queue owns it, set whenever one of them is.
+    clone_key: Option<CloneKey<K>>,
+    /// Deadlines of the evicted entries, kept when set for a [`SpillCache`]
+    /// to carry them over to its secondary cache.
+    evicted_deadlines: Option<Vec<(K, Instant)>>,
+    /// Sizing options this cache was built with, for [`S3FIFO::split_off`].
+    layout: Layout,
+    stats: CacheStats,
+    #[cfg(feature = "latency")]
+    latency: LatencyStats,
+}
+
+#[derive(Debug, Clone)]
+pub enum S3FIFOError<K> {
+    /// The entry is heavier than the whole segment it was put into.
+    BeyondCapacity {
+        key: K,
+        weight: usize,
+        capacity: usize,
+        segment: Segment,
+    },
+    /// [`S3FIFO::compare_and_put`] found another version of the entry, `None`
+    /// meaning no live entry.
+    VersionMismatch {
+        key: K,
+        expected: Option<u64>,
+        actual: Option<u64>,
+    },
+}
+
+impl<K: Debug> Display for S3FIFOError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            S3FIFOError::BeyondCapacity {
+                key,
+                weight,
+                capacity,
+                segment,
+            } => write!(
+                f,
+                "entry {key:?} with weight {weight} is beyond capacity {capacity} of the {segment} queue"
+            ),
+            S3FIFOError::VersionMismatch {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "entry {key:?} is at version {actual:?}, expected {expected:?}"
+            ),
+        }
+    }
+}
+
+impl<K: Debug> Error for S3FIFOError<K> {}
+
+/// Invalid configuration rejected by [`S3FIFOBuilder::try_build`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    ZeroCapacity,
+    /// The small and main queue ratios add up to more than 100 percent.
+    RatiosOverflow {
+        small: u8,
+        main: u8,
+    },
+    /// The small queue ratio leaves nothing of the capacity to the main
+    /// queue.
+    EmptyMainQueue {
+        small: u8,
+        main: u8,
+    },
+    /// The capacity can't give at least 1 to the main queue and to every
+    /// small queue.
+    CapacityTooSmall {
+        capacity: usize,
+        minimum: usize,
+    },
+    /// The ghost queue can't remember all the keys of the small queues.
+    GhostSmallerThanSmall {
+        ghost: usize,
+        small: usize,
+    },
+    /// The hard capacity is below the capacity.
+    HardCapacityBelowCapacity {
+        hard: usize,
+        capacity: usize,
+    },
+}
 
-use std::fmt::Debug;
-use std::hash::Hash;
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::ZeroCapacity => f.write_str("capacity is zero"),
+            ConfigError::RatiosOverflow { small, main } => write!(
+                f,
+                "small queue ratio {small}% and main queue ratio {main}% add up to more than 100%"
+            ),
+            ConfigError::EmptyMainQueue { small, main } => write!(
+                f,
+                "small queue ratio {small}% and main queue ratio {main}% leave the main queue empty"
+            ),
+            ConfigError::CapacityTooSmall { capacity, minimum } => write!(
+                f,
+                "capacity {capacity} is below the minimum of {minimum}, 1 for the main queue and 1 for every small queue"
+            ),
+            ConfigError::GhostSmallerThanSmall { ghost, small } => write!(
+                f,
+                "ghost queue capacity {ghost} is below the small queue capacity {small}"
+            ),
+            ConfigError::HardCapacityBelowCapacity { hard, capacity } => write!(
+                f,
+                "hard capacity {hard} is below the capacity {capacity}"
+            ),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// The queues making up the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Small,
+    Main,
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Segment::Small => f.write_str("small"),
+            Segment::Main => f.write_str("main"),
+        }
+    }
+}
+
+/// Admission hint for [`S3FIFO::put_with_hint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hint {
+    /// The entry is known to be popular. It skips the small queue and is
+    /// inserted into the main queue with a nonzero frequency.
+    Hot,
+    /// The entry is not expected to be reused. It always goes through the
+    /// small queue, even when the ghost queue remembers its key.
+    Cold,
+}
 
-pub struct S3FIFO<K, V> {
-    main: FIFOReinsertion<K, V>,
-    small: FIFO<K, V>,
-    ghost: GhostFIFO<K>,
+/// Controls how much history the ghost queue keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GhostSizing {
+    /// Remember as much weight as the main queue can hold.
+    Main,
+    /// Remember the most recent departures from the small queue, bounded by
+    /// the small queue's capacity, as described in the S3-FIFO paper.
+    #[default]
+    Small,
 }
 
-#[derive(Debug)]
-pub enum S3FIFOError {
-    BeyondCapacity,
+/// Capacities of the queues of a cache, see [`S3FIFO::segment_sizes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentSizes {
+    /// Capacity of all the small queues together.
+    pub small: usize,
+    pub main: usize,
+    /// `None` without ghost queue.
+    pub ghost: Option<usize>,
 }
 
 impl<K, V> S3FIFO<K, V>
 where
-    K: Eq + Hash + Debug + Clone,
-    V: Clone + Debug,
+    K: Eq + Hash,
 {
+    /// Creates a cache with 10% of `capacity` in the small queue, at least 1,
+    /// and the rest in the main queue. The ghost queue uses
+    /// [`GhostSizing::Small`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is below 2.
     #[must_use]
     pub fn new(capacity: usize) -> Self {
-        let main_capacity = capacity * 90 / 100;
-        let small_capacity = capacity * 10 / 100;
-        Self {
-            main: FIFOReinsertion::new(main_capacity),
-            small: FIFO::new(small_capacity),
-            ghost: GhostFIFO::new(main_capacity),
-        }
+        Self::new_with_ghost_sizing(capacity, GhostSizing::default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `capacity` is below 2.
+    #[must_use]
+    pub fn new_with_ghost_sizing(capacity: usize, ghost_sizing: GhostSizing) -> Self {
+        S3FIFOBuilder::new(capacity)
+            .ghost_sizing(ghost_sizing)
+            .build()
+    }
+
+    /// Creates a cache like [`S3FIFO::new`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `capacity` is below 2.
+    pub fn try_new(capacity: usize) -> Result<Self, ConfigError> {
+        S3FIFOBuilder::new(capacity).try_build()
+    }
+
+    #[must_use]
+    pub fn builder(capacity: usize) -> S3FIFOBuilder<K, V> {
+        S3FIFOBuilder::new(capacity)
+    }
+}
+
+impl<K, V, S> S3FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Puts an entry and returns the keys evicted to make room for it, in the
+    /// order described by [`S3FIFO::put_with_report`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache is beyond capacity of small fifo.
+    pub fn put(
+        &mut self,
+        key: &K,
+        value: V,
+        weight: usize,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        self.put_inner(key.clone(), value, weight, None)
+            .map(|evicted| self.evicted_keys(evicted))
+    }
+
+    /// Puts an entry like [`S3FIFO::put`], taking the key instead of copying
+    /// it, and hands back the entries evicted to make room for it, with
+    /// their value and weight, in the order described by
+    /// [`S3FIFO::put_with_report`]. The listener by reference still sees
+    /// them first, the owning one of [`S3FIFOBuilder::eviction_listener`]
+    /// doesn't get them.
+    ///
+    /// The key is only copied when the cache tracks keys, for example with
+    /// [`S3FIFOBuilder::hot_keys`] or [`S3FIFO::subscribe`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache is beyond capacity of small fifo.
+    pub fn put_owned(
+        &mut self,
+        key: K,
+        value: V,
+        weight: usize,
+    ) -> Result<Vec<EvictedEntry<K, V>>, S3FIFOError<K>> {
+        self.put_inner(key, value, weight, None)
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Puts an entry and reports every entry that left the cache because of it.
+    ///
+    /// Victims are listed strictly in the order they left the cache. The small
+    /// queue is drained oldest first: a victim that was never hit leaves the
+    /// cache and is flagged [`Segment::Small`], while a victim that was hit is
+    /// promoted to the main queue, and the main queue victims that promotion
+    /// pushes out are listed right there, flagged [`Segment::Main`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache is beyond capacity of small fifo.
+    pub fn put_with_report(
+        &mut self,
+        key: &K,
+        value: V,
+        weight: usize,
+    ) -> Result<EvictionReport<K>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        let evicted = self.put_inner(key.clone(), value, weight, None)?;
+        let evicted = evicted.into_iter().flatten().map(|evicted| {
+            self.hand_off(
+                &evicted.key,
+                evicted.value,
+                evicted.weight,
+                RemovalCause::Size,
+            );
+            Evicted {
+                key: evicted.key,
+                segment: evicted.segment,
+            }
+        });
+        Ok(EvictionReport {
+            evicted: evicted.collect(),
+        })
     }
 
-    /// .
+    /// Puts an entry like [`S3FIFO::put`] that expires `ttl` after the put:
+    /// lookups miss it from then on, and remove it with
+    /// [`RemovalCause::Expired`], as does [`S3FIFO::expire_stale`]. Putting
+    /// the key again without a time to live keeps the entry until evicted.
     ///
     /// # Errors
     ///
     /// This function will return an error if the cache is beyond capacity of small fifo.
-    pub fn put(&mut self, key: &K, value: V, weight: usize) -> Result<Option<Vec<K>>, S3FIFOError> {
-        if self.ghost.get(key) {
-            self.ghost.remove(key);
-            match self.main.put(key, value, weight) {
-                Err(FIFOReinsertionError::BeyondCapacity) => Err(S3FIFOError::BeyondCapacity),
-                Ok(removed) => Ok(removed),
+    pub fn put_with_ttl(
+        &mut self,
+        key: &K,
+        value: V,
+        weight: usize,
+        ttl: Duration,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        let deadline = self.expiry.now().checked_add(ttl);
+        self.put_with_deadline(key, value, weight, deadline)
+    }
+
+    /// Puts an entry like [`S3FIFO::put`] that expires at `deadline`, if any.
+    pub(crate) fn put_with_deadline(
+        &mut self,
+        key: &K,
+        value: V,
+        weight: usize,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        let deadline = deadline.map(|deadline| (key.clone(), deadline));
+        self.put_inner(key.clone(), value, weight, deadline)
+            .map(|evicted| self.evicted_keys(evicted))
+    }
+
+    /// Whether a put of `key` with `weight` would succeed.
+    pub(crate) fn admits(&self, key: &K, weight: usize) -> bool {
+        self.fits(key, weight, self.put_segment(key))
+    }
+
+    /// Segment a put of `key` goes to.
+    fn put_segment(&self, key: &K) -> Segment {
+        // The ghost queue knows keys by fingerprint, so a live small entry
+        // whose fingerprint matches a ghost key stays where it is.
+        let to_main = self.main.contains_key(key)
+            || (!self.small[self.class(key)].contains_key(key)
+                && self.ghost.as_ref().is_some_and(|ghost| ghost.contains(key)));
+        if to_main {
+            Segment::Main
+        } else {
+            Segment::Small
+        }
+    }
+
+    fn put_inner(
+        &mut self,
+        key: K,
+        value: V,
+        weight: usize,
+        deadline: Option<(K, Instant)>,
+    ) -> PutResult<K, V> {
+        if let Some(frozen) = &mut self.frozen {
+            frozen.writes.push(QueuedWrite::Put {
+                key,
+                value,
+                weight,
+                hint: None,
+                deadline,
+            });
+            return Ok(None);
+        }
+
+        #[cfg(feature = "latency")]
+        let start = Instant::now();
+        let updated =
+            (self.observer.is_some() || self.changes.is_some()) && self.contains_live(&key);
+        // Live main entries are updated in place, a second copy in the small
+        // queue would shadow them and resurface the old value once evicted.
+        let in_main = self.main.contains_key(&key);
+        let segment = self.put_segment(&key);
+        let to_main = segment == Segment::Main;
+        let mut over_quota = self.enforce_quota(&key, weight, segment);
+        over_quota.extend(self.reclaim_pool(&key, weight, segment));
+        let tracked = self.track(&key);
+        if self.fits(&key, weight, segment) {
+            self.expiry.remove(&key);
+        }
+        let result = if to_main {
+            if !in_main {
+                self.remove_from_ghost(&key);
+                if let Some(observer) = &mut self.observer {
+                    observer.on_ghost_hit(&key);
+                }
+            }
+            self.notify_replaced(&key, weight, Segment::Main);
+            match self.main.replace(key, value, weight, None) {
+                Err(error) => Err(Self::main_error(error)),
+                Ok((replaced, removed)) => {
+                    self.hand_off_replaced(replaced);
+                    Ok(self.evicted_from_main(removed))
+                }
             }
         } else {
-            match self.small.put(key, value, weight) {
-                Err(FIFOError::BeyondCapacity) => Err(S3FIFOError::BeyondCapacity),
-                Ok(removed) => match removed {
-                    Some(removed) => {
-                        let mut removed_keys = vec![];
-                        for item in removed {
-                            if item.freq > 0 {
-                                if let Ok(Some(removed_from_main)) = self.main.put_with_freq(
-                                    &item.key,
-                                    item.value,
-                                    item.weight,
-                                    item.freq - 1,
-                                ) {
-                                    removed_keys.extend(removed_from_main);
-                                }
-                            } else {
-                                let _ = self.ghost.put(&item.key, item.weight);
-                                removed_keys.push(item.key);
-                            }
-                        }
-
-                        Ok(Some(removed_keys))
+            self.notify_replaced(&key, weight, Segment::Small);
+            self.put_small(key, value, weight)
+        };
+        let result = Self::prepend_evicted(over_quota, result);
+        self.observe_put(tracked.as_ref(), weight, updated, &result);
+        self.publish_put(tracked.as_ref(), &result);
+        if let (Some((key, deadline)), Ok(_)) = (deadline, &result) {
+            self.expiry.set(key, deadline);
+        }
+        #[cfg(feature = "latency")]
+        self.latency.record_put(
+            start,
+            matches!(&result, Ok(Some(evicted)) if !evicted.is_empty()),
+        );
+        result
+    }
+
+    /// Puts an entry weighed by the [`S3FIFOBuilder::weigher`] of the cache,
+    /// or weighing 1 without one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache is beyond capacity of small fifo.
+    pub fn insert(&mut self, key: &K, value: V) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        let weight = self
+            .weigher
+            .as_ref()
+            .map_or(1, |weigher| weigher(key, &value));
+        self.put(key, value, weight)
+    }
+
+    /// Puts an entry weighing [`Weighted::weight`] of the value.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache is beyond capacity of small fifo.
+    pub fn put_weighted(&mut self, key: &K, value: V) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+        V: Weighted,
+    {
+        let weight = value.weight();
+        self.put(key, value, weight)
+    }
+
+    /// Puts an entry weighing its approximate size in bytes, as measured by
+    /// [`shallow_size`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache is beyond capacity of small fifo.
+    pub fn put_sized(&mut self, key: &K, value: V) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+        V: HeapSize,
+    {
+        let weight = shallow_size(&value);
+        self.put(key, value, weight)
+    }
+
+    /// Puts an entry weighed by `weigher`.
+    ///
+    /// When the weigher returns `None` the value is not cached: any entry
+    /// already stored under `key` is removed, and `Ok(None)` is returned as if
+    /// the put succeeded without evicting anything.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache is beyond capacity of small fifo.
+    pub fn put_with_weigher<F>(
+        &mut self,
+        key: &K,
+        value: V,
+        weigher: F,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+        F: FnOnce(&K, &V) -> Option<usize>,
+    {
+        match weigher(key, &value) {
+            Some(weight) => self.put(key, value, weight),
+            None => {
+                self.remove(key);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Puts an entry, overriding the admission policy with `hint`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the weight is beyond capacity of the
+    /// queue selected by the hint.
+    pub fn put_with_hint(
+        &mut self,
+        key: &K,
+        value: V,
+        weight: usize,
+        hint: Hint,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        self.put_hinted(key.clone(), value, weight, hint)
+            .map(|evicted| self.evicted_keys(evicted))
+    }
+
+    fn put_hinted(&mut self, key: K, value: V, weight: usize, hint: Hint) -> PutResult<K, V> {
+        if let Some(frozen) = &mut self.frozen {
+            frozen.writes.push(QueuedWrite::Put {
+                key,
+                value,
+                weight,
+                hint: Some(hint),
+                deadline: None,
+            });
+            return Ok(None);
+        }
+
+        #[cfg(feature = "latency")]
+        let start = Instant::now();
+        let updated =
+            (self.observer.is_some() || self.changes.is_some()) && self.contains_live(&key);
+        let segment = match hint {
+            Hint::Hot => Segment::Main,
+            Hint::Cold => Segment::Small,
+        };
+        let mut over_quota = self.enforce_quota(&key, weight, segment);
+        over_quota.extend(self.reclaim_pool(&key, weight, segment));
+        let tracked = self.track(&key);
+        let fits = self.fits(&key, weight, segment);
+        if fits {
+            self.expiry.remove(&key);
+        }
+        // The copy left in the other segment goes before the queue takes the
+        // key.
+        let result = match hint {
+            Hint::Hot => {
+                self.notify_replaced(&key, weight, Segment::Main);
+                if fits {
+                    self.remove_replaced(&key, Segment::Small);
+                    self.remove_from_ghost(&key);
+                }
+                match self.main.replace(key, value, weight, Some(1)) {
+                    Err(error) => Err(Self::main_error(error)),
+                    Ok((replaced, removed)) => {
+                        self.hand_off_replaced(replaced);
+                        Ok(self.evicted_from_main(removed))
                     }
-                    None => Ok(None),
-                },
+                }
             }
+            Hint::Cold => {
+                self.remove_from_ghost(&key);
+                self.notify_replaced(&key, weight, Segment::Small);
+                if fits {
+                    self.remove_replaced(&key, Segment::Main);
+                }
+                self.put_small(key, value, weight)
+            }
+        };
+        let result = Self::prepend_evicted(over_quota, result);
+        self.observe_put(tracked.as_ref(), weight, updated, &result);
+        self.publish_put(tracked.as_ref(), &result);
+        #[cfg(feature = "latency")]
+        self.latency.record_put(
+            start,
+            matches!(&result, Ok(Some(evicted)) if !evicted.is_empty()),
+        );
+        result
+    }
+
+    /// Copy of `key` for the trackers, feeds and observers, if any.
+    fn track(&self, key: &K) -> Option<K> {
+        self.clone_key.map(|clone_key| clone_key(key))
+    }
+
+    /// Whether a put of `key` with `weight` into `segment` fits, the only
+    /// way it can fail.
+    fn fits(&self, key: &K, weight: usize, segment: Segment) -> bool {
+        let capacity = match segment {
+            Segment::Small => self.small[self.class(key)].capacity(),
+            Segment::Main => self.main.capacity(),
+        };
+        weight <= capacity
+    }
+
+    fn evicted_from_main(
+        &mut self,
+        removed: Option<Vec<Removed<K, V>>>,
+    ) -> Option<Vec<EvictedEntry<K, V>>> {
+        removed.map(|removed| {
+            removed
+                .into_iter()
+                .map(|item| {
+                    self.notify_evicted(&item, Segment::Main);
+                    EvictedEntry {
+                        key: item.key,
+                        value: item.value,
+                        weight: item.weight,
+                        segment: Segment::Main,
+                    }
+                })
+                .collect()
+        })
+    }
+
+    fn notify_evicted(&mut self, item: &Removed<K, V>, segment: Segment) {
+        if let Some(listener) = &mut self.listener {
+            listener(&item.key, &item.value, item.weight, RemovalCause::Size);
         }
+        self.record_evict(&item.key, segment);
+        self.untrack(&item.key);
     }
 
-    pub fn get(&mut self, key: &K) -> Option<&V> {
-        self.small.get(key).or_else(|| self.main.get(key))
+    fn record_evict(&mut self, key: &K, segment: Segment) {
+        self.stats.evictions += 1;
+        if let Some(deadlines) = &mut self.evicted_deadlines {
+            deadlines.extend(self.expiry.remove(key));
+        }
+        if let Some(lifetimes) = &mut self.lifetimes {
+            lifetimes.record_evict(key, segment);
+        }
     }
 
-    pub fn remove(&mut self, key: &K) {
-        self.main.remove(key);
-        self.small.remove(key);
-        self.ghost.remove(key);
+    /// Evicts the oldest entries of the tenant of `key` until a put of `key`
+    /// into `segment` keeps the tenant within its quota. Puts that will fail
+    /// evict nothing.
+    ///
+    /// With tenant shares, a put into a full segment then evicts the oldest
+    /// entries of that segment belonging to the tenant furthest above its
+    /// share, before the segment evicts as usual.
+    fn enforce_quota(
+        &mut self,
+        key: &K,
+        weight: usize,
+        segment: Segment,
+    ) -> Vec<EvictedEntry<K, V>> {
+        let class = self.class(key);
+        let (capacity, used_capacity) = match segment {
+            Segment::Small => (
+                self.small[class].capacity(),
+                self.small[class].used_capacity(),
+            ),
+            Segment::Main => (self.main.capacity(), self.main.used_capacity()),
+        };
+        let mut victims = match &self.quotas {
+            Some(quotas) if weight <= capacity => quotas.victims(key, weight),
+            _ => return vec![],
+        };
+
+        let in_segment = |victim: &K| match segment {
+            Segment::Small => self.small[self.class(victim)].contains_key(victim),
+            Segment::Main => self.main.contains_key(victim),
+        };
+        let quotas = self.quotas.as_ref().unwrap();
+        let freed: usize = victims
+            .iter()
+            .filter(|victim| in_segment(victim))
+            .map(|victim| quotas.weight(victim))
+            .sum();
+        let excess = (used_capacity + weight).saturating_sub(capacity + freed);
+        if excess > 0 {
+            let total_capacity =
+                self.main.capacity() + self.small.iter().map(FIFO::capacity).sum::<usize>();
+            let share_victims = quotas.share_victims(key, excess, total_capacity, |victim| {
+                in_segment(victim) && !victims.contains(victim)
+            });
+            victims.extend(share_victims);
+        }
+
+        let mut evicted = Vec::with_capacity(victims.len());
+        for victim in victims {
+            let class = self.class(&victim);
+            let (removed, segment) = match self.small[class].remove(&victim) {
+                Some(removed) => (Some(removed), Segment::Small),
+                None => (self.main.remove(&victim), Segment::Main),
+            };
+            let Some((value, weight)) = removed else {
+                continue;
+            };
+            if let Some(listener) = &mut self.listener {
+                listener(&victim, &value, weight, RemovalCause::Size);
+            }
+            self.record_evict(&victim, segment);
+            self.untrack(&victim);
+            evicted.push(EvictedEntry {
+                key: victim,
+                value,
+                weight,
+                segment,
+            });
+        }
+
+        evicted
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Evicts the entries a put of `key` into `segment` has to make room
+    /// for in the capacity pool, see [`CapacityPool`]. Puts that will fail
+    /// evict nothing.
+    fn reclaim_pool(
+        &mut self,
+        key: &K,
+        weight: usize,
+        segment: Segment,
+    ) -> Vec<EvictedEntry<K, V>> {
+        let fits = self.fits(key, weight, segment);
+        let Some(pool) = self.pool.as_ref().filter(|_| fits) else {
+            return vec![];
+        };
 
-    #[test]
-    fn fifo_works() {
-        let mut cache = FIFO::new(10);
-        cache.put(&1, 1, 2).unwrap();
+        let growth = weight.saturating_sub(self.peek_entry(key).map_or(0, |(_, weight)| weight));
+        match pool.excess(growth) {
+            0 => vec![],
+            excess => self.shrink(excess),
+        }
+    }
+
+    /// Evicts `weight` of the used capacity, from the small queues first.
+    /// Hit small entries are promoted as usual, so the main queue may evict
+    /// on their behalf.
+    fn shrink(&mut self, mut weight: usize) -> Vec<EvictedEntry<K, V>> {
+        let mut evicted = vec![];
+        for class in 0..self.small.len() {
+            if weight == 0 {
+                break;
+            }
+            let used = self.weight();
+            let removed = self.small[class].shrink(weight);
+            evicted.extend(self.demote_from_small(removed).into_iter().flatten());
+            weight = weight.saturating_sub(used.saturating_sub(self.weight()));
+        }
+        if weight > 0 {
+            let removed = self.main.shrink(weight);
+            evicted.extend(self.evicted_from_main(removed).into_iter().flatten());
+        }
+        evicted
+    }
+
+    /// Weight held by the live entries of the queues.
+    pub fn weight(&self) -> usize {
+        self.main.used_capacity() + self.small.iter().map(FIFO::used_capacity).sum::<usize>()
+    }
+
+    fn sync_pool(&self) {
+        if let Some(pool) = &self.pool {
+            pool.set_used(self.weight());
+        }
+    }
+
+    fn prepend_evicted(
+        mut evicted: Vec<EvictedEntry<K, V>>,
+        result: PutResult<K, V>,
+    ) -> PutResult<K, V> {
+        if evicted.is_empty() {
+            return result;
+        }
+        result.map(|more| {
+            evicted.extend(more.into_iter().flatten());
+            Some(evicted)
+        })
+    }
+
+    /// Drops a departed `key` from the quotas, versions, lifetimes and
+    /// deadlines.
+    fn untrack(&mut self, key: &K) {
+        self.expiry.remove(key);
+        if let Some(quotas) = &mut self.quotas {
+            quotas.record_remove(key);
+        }
+        if let Some(versions) = &mut self.versions {
+            versions.remove(key);
+        }
+        if let Some(lifetimes) = &mut self.lifetimes {
+            lifetimes.remove(key);
+        }
+    }
+
+    /// Tells the listener about the live entry a put of `key` into `segment`
+    /// is about to overwrite. Puts that will fail overwrite nothing.
+    fn notify_replaced(&mut self, key: &K, weight: usize, segment: Segment) {
+        let small = &self.small[self.class(key)];
+        let Some(listener) = &mut self.listener else {
+            return;
+        };
+        let replaced = match segment {
+            Segment::Small if weight <= small.capacity() => small.peek(key),
+            Segment::Main if weight <= self.main.capacity() => self.main.peek(key),
+            _ => None,
+        };
+        if let Some((value, weight)) = replaced {
+            listener(key, value, weight, RemovalCause::Replaced);
+        }
+    }
+
+    /// Removes the copy of `key` left in `segment` by a put that stored the
+    /// key in the other segment.
+    fn remove_replaced(&mut self, key: &K, segment: Segment) {
+        let class = self.class(key);
+        let removed = match segment {
+            Segment::Small => self.small[class].remove(key),
+            Segment::Main => self.main.remove(key),
+        };
+        if let Some((value, weight)) = removed {
+            if let Some(listener) = &mut self.listener {
+                listener(key, &value, weight, RemovalCause::Replaced);
+            }
+            self.hand_off(key, value, weight, RemovalCause::Replaced);
+        }
+    }
+
+    /// Hands the value an update replaced in place to the owning listener,
+    /// the listener by reference was told before the update.
+    fn hand_off_replaced(&mut self, replaced: Option<Removed<K, V>>) {
+        if let Some(item) = replaced {
+            self.hand_off(&item.key, item.value, item.weight, RemovalCause::Replaced);
+        }
+    }
+
+    /// Hands the value of an entry that left the cache to the listener of
+    /// [`S3FIFOBuilder::eviction_listener`], or drops it.
+    fn hand_off(&mut self, key: &K, value: V, weight: usize, cause: RemovalCause) {
+        if let Some(listener) = &mut self.owning_listener {
+            listener(key, value, weight, cause);
+        }
+    }
+
+    /// Hands the values of the evicted entries off and keeps their keys.
+    fn evicted_keys(&mut self, evicted: Option<Vec<EvictedEntry<K, V>>>) -> Option<Vec<K>> {
+        let evicted = evicted?;
+        let mut keys = Vec::with_capacity(evicted.len());
+        for evicted in evicted {
+            self.hand_off(
+                &evicted.key,
+                evicted.value,
+                evicted.weight,
+                RemovalCause::Size,
+            );
+            keys.push(evicted.key);
+        }
+        Some(keys)
+    }
+
+    fn main_error(error: FIFOReinsertionError<K>) -> S3FIFOError<K> {
+        match error {
+            FIFOReinsertionError::BeyondCapacity {
+                key,
+                weight,
+                capacity,
+            } => S3FIFOError::BeyondCapacity {
+                key,
+                weight,
+                capacity,
+                segment: Segment::Main,
+            },
+        }
+    }
+
+    fn small_error(error: FIFOError<K>) -> S3FIFOError<K> {
+        match error {
+            FIFOError::BeyondCapacity {
+                key,
+                weight,
+                capacity,
+            } => S3FIFOError::BeyondCapacity {
+                key,
+                weight,
+                capacity,
+                segment: Segment::Small,
+            },
+        }
+    }
+
+    fn put_small(&mut self, key: K, value: V, weight: usize) -> PutResult<K, V> {
+        let class = self.class(&key);
+        match self.small[class].replace(key, value, weight) {
+            Err(error) => Err(Self::small_error(error)),
+            Ok((replaced, removed)) => {
+                self.hand_off_replaced(replaced);
+                Ok(self.demote_from_small(removed))
+            }
+        }
+    }
+
+    /// Moves entries evicted from the small queue to main if they were hit,
+    /// or to ghost otherwise. Returns the entries that left the cache, in
+    /// eviction order.
+    fn demote_from_small(
+        &mut self,
+        removed: Option<Vec<Removed<K, V>>>,
+    ) -> Option<Vec<EvictedEntry<K, V>>> {
+        let removed = removed?;
+        let mut evicted = vec![];
+        for item in removed {
+            if item.freq > 0 && item.weight <= self.main.capacity() {
+                let removed_from_main = self
+                    .main
+                    .put_with_freq(item.key, item.value, item.weight, item.freq - 1)
+                    .ok()
+                    .flatten();
+                evicted.extend(
+                    self.evicted_from_main(removed_from_main)
+                        .into_iter()
+                        .flatten(),
+                );
+            } else {
+                self.notify_evicted(&item, Segment::Small);
+                // Hit entries too heavy for the main queue leave the cache
+                // without a trace in the ghost queue.
+                if item.freq == 0 {
+                    if let Some(ghost) = &mut self.ghost {
+                        let _ = ghost.put(&item.key, item.weight);
+                    }
+                }
+                evicted.push(EvictedEntry {
+                    key: item.key,
+                    value: item.value,
+                    weight: item.weight,
+                    segment: Segment::Small,
+                });
+            }
+        }
+
+        Some(evicted)
+    }
+
+    /// Loads `entries`, hottest first, into the free space of the cache
+    /// without evicting anything. The space held by removed entries is
+    /// reclaimed first, see [`S3FIFO::compact`].
+    ///
+    /// The hottest entries go to the main queue with a frequency of 1, so
+    /// they survive a first pass of the eviction hand, and the next ones to
+    /// the small queue. Each queue is filled coldest first, so the hottest
+    /// entries are the last to be evicted. Returns the keys that were not
+    /// loaded: entries that don't fit, keys already in the cache and
+    /// repeated keys.
+    pub fn preload<I>(&mut self, entries: I) -> Vec<K>
+    where
+        I: IntoIterator<Item = (K, V, usize)>,
+    {
+        self.compact();
+        let mut main_room = self
+            .main
+            .capacity()
+            .saturating_sub(self.main.used_capacity());
+        let mut small_rooms: Vec<_> = self
+            .small
+            .iter()
+            .map(|small| small.capacity().saturating_sub(small.used_capacity()))
+            .collect();
+        // Entries to load, keyed so repeated keys are caught without a copy.
+        let mut loaded = Table::with_hasher(DefaultState::default());
+        let mut main_entries = vec![];
+        let mut small_entries = vec![];
+        let mut skipped = vec![];
+        for (key, value, weight) in entries {
+            let class = self.class(&key);
+            if self.contains_live(&key) || loaded.find(&key).is_some() {
+                skipped.push(key);
+            } else if weight <= main_room {
+                main_room -= weight;
+                main_entries.push(loaded.insert(key, (value, weight)));
+            } else if weight <= small_rooms[class] {
+                small_rooms[class] -= weight;
+                small_entries.push((loaded.insert(key, (value, weight)), class));
+            } else {
+                skipped.push(key);
+            }
+        }
+
+        for handle in main_entries.into_iter().rev() {
+            let (key, (value, weight)) = loaded.remove(handle).unwrap();
+            let tracked = self.preloading(&key);
+            let _ = self.main.put_with_freq(key, value, weight, 1);
+            self.preloaded(tracked.as_ref(), weight);
+        }
+        for (handle, class) in small_entries.into_iter().rev() {
+            let (key, (value, weight)) = loaded.remove(handle).unwrap();
+            let tracked = self.preloading(&key);
+            let _ = self.small[class].put(key, value, weight);
+            self.preloaded(tracked.as_ref(), weight);
+        }
+
+        skipped
+    }
+
+    fn preloading(&mut self, key: &K) -> Option<K> {
+        self.remove_from_ghost(key);
+        self.track(key)
+    }
+
+    fn preloaded(&mut self, key: Option<&K>, weight: usize) {
+        self.observe_put(key, weight, false, &Ok(None));
+        self.publish_put(key, &Ok(None));
+    }
+
+    /// Folds the entries of `other` into this cache.
+    ///
+    /// Entries of the other main queue are merged first, then the entries of
+    /// the other small queue, each keeping its frequency and queue order. An
+    /// entry of `other` replaces an entry of this cache with the same key.
+    /// Entries that don't fit are evicted as usual, and entries heavier than
+    /// the segment they belong to are dropped. Returns the keys of both caches
+    /// that are not in the merged cache.
+    pub fn merge(&mut self, mut other: S3FIFO<K, V, S>) -> Vec<K> {
+        let deadlines = other.expiry.drain().collect();
+        let small = other.small.iter_mut().flat_map(FIFO::drain).collect();
+        let removed_keys = self.absorb(other.main.drain(), small);
+        self.restore_deadlines(deadlines);
+
+        // Fingerprints only carry over between ghost queues hashing alike.
+        if let (Some(ghost), Some(other_ghost)) = (&mut self.ghost, &mut other.ghost) {
+            if ghost.hashes_like(other_ghost) {
+                for (fingerprint, weight) in other_ghost.drain() {
+                    let _ = ghost.put_fingerprint(fingerprint, weight);
+                }
+                let small = self.small.iter().flat_map(FIFO::iter);
+                for (key, _, _) in small.chain(self.main.iter()) {
+                    ghost.remove(key);
+                }
+            }
+        }
+
+        removed_keys
+    }
+
+    /// Defers puts and removes until [`S3FIFO::thaw`], so the entries stay as
+    /// they are while an external snapshot reads them. Reads are still
+    /// served, from the entries as they were when the cache was frozen.
+    ///
+    /// Deferred puts return `Ok(None)`; their evictions and errors are
+    /// reported by `thaw`. Merging, splitting and preloading are not deferred.
+    pub fn freeze(&mut self)
+    where
+        K: Clone,
+    {
+        self.frozen.get_or_insert_with(|| Frozen {
+            writes: Vec::new(),
+            clone_key: K::clone,
+        });
+    }
+
+    #[must_use]
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// Applies the writes deferred since [`S3FIFO::freeze`], in order, and
+    /// resumes normal operation. Returns the keys that are not in the cache
+    /// because of them: evicted entries and entries too heavy for their
+    /// segment.
+    pub fn thaw(&mut self) -> Vec<K> {
+        let mut removed_keys = vec![];
+        let writes = self.frozen.take().map(|frozen| frozen.writes);
+        for write in writes.unwrap_or_default() {
+            match write {
+                QueuedWrite::Put {
+                    key,
+                    value,
+                    weight,
+                    hint,
+                    deadline,
+                } => {
+                    let result = match hint {
+                        Some(hint) => self.put_hinted(key, value, weight, hint),
+                        None => self.put_inner(key, value, weight, deadline),
+                    };
+                    let result = result.map(|evicted| self.evicted_keys(evicted));
+                    match result {
+                        Ok(evicted) => removed_keys.extend(evicted.into_iter().flatten()),
+                        Err(
+                            S3FIFOError::BeyondCapacity { key, .. }
+                            | S3FIFOError::VersionMismatch { key, .. },
+                        ) => removed_keys.push(key),
+                    }
+                }
+                QueuedWrite::Remove(key) => self.remove(&key),
+            }
+        }
+
+        removed_keys
+    }
+
+    /// Moves the entries matching `predicate` into a new cache of `capacity`.
+    ///
+    /// The new cache is built with the ratios, ghost, hard capacity, tenants
+    /// and generations of this one, scaled to `capacity`. The entries keep
+    /// their segment, frequency, deadline and queue order. Entries that
+    /// don't fit in the new cache are evicted from it as usual, through the
+    /// listeners of this cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is too small to build a cache with as many small
+    /// queues, see [`S3FIFOBuilder::build`].
+    pub fn split_off<F>(&mut self, capacity: usize, predicate: F) -> S3FIFO<K, V, S>
+    where
+        F: FnMut(&K, &V) -> bool,
+        K: Clone,
+        S: Clone,
+    {
+        let extracted = self.extract_if(predicate);
+
+        let mut builder = S3FIFOBuilder::new(capacity)
+            .with_layout(self.layout)
+            .hasher(self.small[0].hasher().clone())
+            .classifier(self.small.len(), self.classifier.clone())
+            .with_weigher(self.weigher.clone());
+        if let Some(generations) = self.generations {
+            builder = builder.generations(generations);
+        }
+        if let Some(quotas) = &self.quotas {
+            let (tenant, quota, shares) = quotas.config();
+            builder = builder.tenants(tenant, quota, shares);
+        }
+        if self.versions.is_some() {
+            builder = builder.versioned();
+        }
+        if let Some(lifetimes) = &self.lifetimes {
+            builder = builder.lifetimes(lifetimes.samples());
+        }
+        if let Some(changes) = &self.changes {
+            builder = builder.change_feed(changes.capacity());
+        }
+        if let Some(clock) = self.expiry.clock() {
+            let clock = Arc::clone(clock);
+            builder = builder.clock(move || clock());
+        }
+        let mut split = builder.build();
+
+        split.listener = self.listener.take();
+        split.owning_listener = self.owning_listener.take();
+        split.absorb_extracted(extracted);
+        self.listener = split.listener.take();
+        self.owning_listener = split.owning_listener.take();
+        split
+    }
+
+    /// Moves the entries matching `predicate` out of the cache, for
+    /// [`S3FIFO::absorb_extracted`] to put into another. The trackers and
+    /// feeds see them removed, the listeners don't.
+    pub(crate) fn extract_if<F>(&mut self, mut predicate: F) -> Extracted<K, V>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let main = self.main.extract_if(&mut predicate);
+        let small: Vec<_> = self
+            .small
+            .iter_mut()
+            .flat_map(|small| small.extract_if(&mut predicate))
+            .collect();
+
+        let deadlines = main
+            .iter()
+            .chain(&small)
+            .filter_map(|item| self.expiry.remove(&item.key))
+            .collect();
+        for item in main.iter().chain(&small) {
+            self.untrack(&item.key);
+            self.record_change(Change::Remove(&item.key));
+            if let Some(replication) = &mut self.replication {
+                replication(Mutation::Remove(&item.key));
+            }
+            self.events.publish(Event::Remove(&item.key));
+        }
+        self.sync_pool();
+
+        Extracted {
+            main,
+            small,
+            deadlines,
+        }
+    }
+
+    /// Puts entries taken by [`S3FIFO::extract_if`] like [`S3FIFO::merge`]
+    /// does and returns the keys that didn't stay in the cache.
+    pub(crate) fn absorb_extracted(&mut self, extracted: Extracted<K, V>) -> Vec<K> {
+        let removed_keys = self.absorb(extracted.main, extracted.small);
+        self.restore_deadlines(extracted.deadlines);
+        removed_keys
+    }
+
+    fn absorb(&mut self, main: Vec<Removed<K, V>>, small: Vec<Removed<K, V>>) -> Vec<K> {
+        let mut removed_keys = vec![];
+
+        for item in main {
+            self.forget(&item.key);
+            if !self.fits(&item.key, item.weight, Segment::Main) {
+                self.reject(item, &mut removed_keys);
+                continue;
+            }
+            let tracked = self.track(&item.key);
+            let result = match self
+                .main
+                .put_with_freq(item.key, item.value, item.weight, item.freq)
+            {
+                Err(error) => Err(Self::main_error(error)),
+                Ok(removed) => Ok(self.evicted_from_main(removed)),
+            };
+            self.collect_merged(tracked.as_ref(), item.weight, result, &mut removed_keys);
+        }
+
+        for item in small {
+            self.forget(&item.key);
+            if !self.fits(&item.key, item.weight, Segment::Small) {
+                self.reject(item, &mut removed_keys);
+                continue;
+            }
+            let tracked = self.track(&item.key);
+            let class = self.class(&item.key);
+            let result =
+                match self.small[class].put_with_freq(item.key, item.value, item.weight, item.freq)
+                {
+                    Err(error) => Err(Self::small_error(error)),
+                    Ok(removed) => Ok(self.demote_from_small(removed)),
+                };
+            self.collect_merged(tracked.as_ref(), item.weight, result, &mut removed_keys);
+        }
+
+        removed_keys
+    }
+
+    /// Hands an absorbed entry too heavy for its queue to the listeners.
+    fn reject(&mut self, item: Removed<K, V>, removed_keys: &mut Vec<K>) {
+        if let Some(listener) = &mut self.listener {
+            listener(&item.key, &item.value, item.weight, RemovalCause::Size);
+        }
+        self.hand_off(&item.key, item.value, item.weight, RemovalCause::Size);
+        removed_keys.push(item.key);
+    }
+
+    fn collect_merged(
+        &mut self,
+        key: Option<&K>,
+        weight: usize,
+        result: PutResult<K, V>,
+        removed_keys: &mut Vec<K>,
+    ) {
+        self.observe_put(key, weight, false, &result);
+        self.publish_put(key, &result);
+        match result {
+            Ok(evicted) => removed_keys.extend(self.evicted_keys(evicted).into_iter().flatten()),
+            Err(
+                S3FIFOError::BeyondCapacity { key, .. } | S3FIFOError::VersionMismatch { key, .. },
+            ) => removed_keys.push(key),
+        }
+    }
+
+    /// Drops `key` from every queue without publishing an event. The
+    /// listener sees the dropped entries as replaced.
+    fn forget(&mut self, key: &K) {
+        let class = self.class(key);
+        for (value, weight) in [self.main.remove(key), self.small[class].remove(key)]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(listener) = &mut self.listener {
+                listener(key, &value, weight, RemovalCause::Replaced);
+            }
+            self.hand_off(key, value, weight, RemovalCause::Replaced);
+        }
+        self.remove_from_ghost(key);
+        self.untrack(key);
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        #[cfg(feature = "latency")]
+        let start = Instant::now();
+        let expired = self.expire(key);
+        let class = self.class(key);
+        let value = if expired {
+            None
+        } else {
+            self.small[class].get(key).or_else(|| self.main.get(key))
+        };
+        Self::observe_get(
+            &mut self.observer,
+            &mut self.hot_keys,
+            &mut self.shadow,
+            &mut self.quotas,
+            &mut self.lifetimes,
+            key,
+            value.is_some(),
+        );
+        self.stats.record_get(value.is_some());
+        #[cfg(feature = "latency")]
+        self.latency.record_get(start);
+        value
+    }
+
+    /// Like [`S3FIFO::get`], but returns an owned copy of the value.
+    pub fn get_cloned(&mut self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.get(key).cloned()
+    }
+
+    /// Like [`S3FIFO::get`], but also returns the key stored in the cache.
+    pub fn get_key_value(&mut self, key: &K) -> Option<(&K, &V)> {
+        #[cfg(feature = "latency")]
+        let start = Instant::now();
+        let expired = self.expire(key);
+        let class = self.class(key);
+        let entry = if expired {
+            None
+        } else {
+            self.small[class]
+                .get_key_value(key)
+                .or_else(|| self.main.get_key_value(key))
+        };
+        Self::observe_get(
+            &mut self.observer,
+            &mut self.hot_keys,
+            &mut self.shadow,
+            &mut self.quotas,
+            &mut self.lifetimes,
+            key,
+            entry.is_some(),
+        );
+        self.stats.record_get(entry.is_some());
+        #[cfg(feature = "latency")]
+        self.latency.record_get(start);
+        entry
+    }
+
+    /// Version of the live entry of `key`, when built with
+    /// [`S3FIFOBuilder::versioned`]. Every successful put gives its entry a
+    /// version above all the versions given before it. Moving between queues
+    /// keeps the version.
+    pub fn version(&self, key: &K) -> Option<u64> {
+        self.versions.as_ref()?.get(key)
+    }
+
+    /// Looks `key` up like [`S3FIFO::get`], along with the version of its
+    /// entry. Always `None` without [`S3FIFOBuilder::versioned`].
+    pub fn get_versioned(&mut self, key: &K) -> Option<(&V, u64)> {
+        let version = self.version(key);
+        let value = self.get(key)?;
+        Some((value, version?))
+    }
+
+    /// Puts an entry like [`S3FIFO::put`] only if the live entry of `key` is at
+    /// version `expected`, `None` expecting no live entry. The new version is
+    /// then given by [`S3FIFO::version`].
+    ///
+    /// While the cache is frozen, the check is made against the entries of
+    /// the frozen cache, and the put is queued as usual.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`S3FIFOError::VersionMismatch`] if the entry
+    /// is at another version, and the errors of [`S3FIFO::put`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache was built without [`S3FIFOBuilder::versioned`].
+    pub fn compare_and_put(
+        &mut self,
+        key: &K,
+        expected: Option<u64>,
+        value: V,
+        weight: usize,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>>
+    where
+        K: Clone,
+    {
+        assert!(
+            self.versions.is_some(),
+            "compare_and_put needs a cache built with S3FIFOBuilder::versioned"
+        );
+        let actual = self.version(key);
+        if actual != expected {
+            return Err(S3FIFOError::VersionMismatch {
+                key: key.clone(),
+                expected,
+                actual,
+            });
+        }
+
+        self.put(key, value, weight)
+    }
+
+    /// Looks `key` up like [`S3FIFO::get`], and on a miss puts the value
+    /// returned by `f` like [`S3FIFO::put`], admitting it into the main queue
+    /// if the ghost queue remembers the key. The keys evicted by the put are
+    /// only reported to the listener.
+    ///
+    /// While the cache is frozen, a computed value is returned from the
+    /// queued put, and lookups keep missing it until [`S3FIFO::thaw`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the computed entry is beyond
+    /// capacity of its queue. The value is dropped.
+    pub fn get_or_insert_with<F>(
+        &mut self,
+        key: &K,
+        weight: usize,
+        f: F,
+    ) -> Result<&V, S3FIFOError<K>>
+    where
+        F: FnOnce() -> V,
+        K: Clone,
+    {
+        match self.load(key, weight, || Ok::<_, Infallible>(f())) {
+            Ok(result) => result,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`S3FIFO::get_or_insert_with`], but `f` may fail, in which case
+    /// nothing is put.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the error of `f`, or an error converted from
+    /// [`S3FIFOError`] if the computed entry is beyond capacity of its queue.
+    pub fn try_get_or_insert_with<F, E>(&mut self, key: &K, weight: usize, f: F) -> Result<&V, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+        E: From<S3FIFOError<K>>,
+        K: Clone,
+    {
+        self.load(key, weight, f)?.map_err(E::from)
+    }
+
+    fn load<F, E>(&mut self, key: &K, weight: usize, f: F) -> Result<Result<&V, S3FIFOError<K>>, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+        K: Clone,
+    {
+        let hit = self.get(key).is_some();
+        if !hit {
+            if let Err(error) = self.put(key, f()?, weight) {
+                return Ok(Err(error));
+            }
+        }
+
+        if let (Some([.., QueuedWrite::Put { value, .. }]), false) = (
+            self.frozen.as_ref().map(|frozen| frozen.writes.as_slice()),
+            hit,
+        ) {
+            return Ok(Ok(value));
+        }
+        let (value, _) = self
+            .peek_entry(key)
+            .expect("a successful put keeps its entry");
+        Ok(Ok(value))
+    }
+
+    /// Returns the `k` most looked up keys with their estimated lookup
+    /// counts, most looked up first. Lookups are only counted when enabled
+    /// with [`S3FIFOBuilder::hot_keys`], otherwise this is always empty.
+    pub fn hot_keys(&self, k: usize) -> Vec<(K, u64)> {
+        self.hot_keys
+            .as_ref()
+            .map_or_else(Vec::new, |hot_keys| hot_keys.top(k))
+    }
+
+    /// Compares the hit ratio of the cache with the one of an LRU of the
+    /// same size on a sample of the keys, when enabled with
+    /// [`S3FIFOBuilder::shadow_lru`].
+    pub fn shadow_stats(&self) -> Option<ShadowStats> {
+        self.shadow.as_ref().map(ShadowLru::stats)
+    }
+
+    /// Occupancy and hit ratio of every tenant, when tenants are set with
+    /// [`S3FIFOBuilder::tenant_quota`] or [`S3FIFOBuilder::tenant_shares`].
+    pub fn tenant_stats(&self) -> Vec<TenantStats> {
+        self.quotas.as_ref().map_or_else(Vec::new, Quotas::stats)
+    }
+
+    /// How long the entries evicted from `segment`, or from any segment with
+    /// `None`, lived and how often they were hit, when enabled with
+    /// [`S3FIFOBuilder::lifetimes`].
+    ///
+    /// Entries evicted from the small queue without hits point at a scan or
+    /// at a small queue too small to catch their reuse, main entries
+    /// evicted young at a main queue too small for the working set.
+    pub fn lifetime_stats(&self, segment: Option<Segment>) -> Option<LifetimeStats> {
+        self.lifetimes
+            .as_ref()
+            .map(|lifetimes| lifetimes.stats(segment))
+    }
+
+    /// Latency histograms of lookups, puts and evictions since the cache was
+    /// built or the stats were last reset. Deferred writes of a frozen cache
+    /// are timed when [`S3FIFO::thaw`] applies them.
+    #[cfg(feature = "latency")]
+    pub fn latency_stats(&self) -> &LatencyStats {
+        &self.latency
+    }
+
+    #[cfg(feature = "latency")]
+    pub fn reset_latency_stats(&mut self) {
+        self.latency = LatencyStats::default();
+    }
+
+    /// Number of live entries, expired entries not yet removed included.
+    pub fn len(&self) -> usize {
+        self.main.len() + self.small.iter().map(FIFO::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Capacity of the small and main queues together.
+    pub fn capacity(&self) -> usize {
+        self.main.capacity() + self.small.iter().map(FIFO::capacity).sum::<usize>()
+    }
+
+    /// Whether `key` has a live entry that didn't expire, without counting a
+    /// hit.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.contains_live(key) && !self.expiry.is_expired(key)
+    }
+
+    /// Live value of `key`, without counting a hit or touching the queues.
+    /// Expired entries are not returned.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        if self.expiry.is_expired(key) {
+            return None;
+        }
+        self.peek_entry(key).map(|(value, _)| value)
+    }
+
+    /// Live entries that didn't expire, the small queues first, every queue
+    /// oldest first. Iterating doesn't count hits.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.small
+            .iter()
+            .flat_map(FIFO::iter)
+            .chain(self.main.iter())
+            .filter(|(key, _, _)| !self.expiry.is_expired(key))
+            .map(|(key, value, _)| (key, value))
+    }
+
+    /// Hit, miss, insertion, eviction and expiration counters since the cache
+    /// was built, and the current occupancy of every segment.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            small: Occupancy {
+                len: self.small.iter().map(FIFO::len).sum(),
+                weight: self.small.iter().map(FIFO::used_capacity).sum(),
+                capacity: self.small.iter().map(FIFO::capacity).sum(),
+            },
+            main: Occupancy {
+                len: self.main.len(),
+                weight: self.main.used_capacity(),
+                capacity: self.main.capacity(),
+            },
+            ghost: self.ghost.as_ref().map(|ghost| Occupancy {
+                len: ghost.len(),
+                weight: ghost.used_capacity(),
+                capacity: ghost.capacity(),
+            }),
+            ..self.stats
+        }
+    }
+
+    /// Capacities the queues were built with.
+    pub fn segment_sizes(&self) -> SegmentSizes {
+        SegmentSizes {
+            small: self.small.iter().map(FIFO::capacity).sum(),
+            main: self.main.capacity(),
+            ghost: self.ghost.as_ref().map(GhostFIFO::capacity),
+        }
+    }
+
+    fn observe_get(
+        observer: &mut Option<Box<dyn CacheObserver<K>>>,
+        hot_keys: &mut Option<HotKeys<K, S>>,
+        shadow: &mut Option<ShadowLru<K, S>>,
+        quotas: &mut Option<Quotas<K, S>>,
+        lifetimes: &mut Option<Lifetimes<K, S>>,
+        key: &K,
+        hit: bool,
+    ) {
+        if let Some(lifetimes) = lifetimes {
+            lifetimes.record_get(key, hit);
+        }
+        if let Some(hot_keys) = hot_keys {
+            hot_keys.record(key);
+        }
+        if let Some(quotas) = quotas {
+            quotas.record_get(key, hit);
+        }
+        if let Some(shadow) = shadow {
+            shadow.record_get(key, hit);
+        }
+        match observer {
+            Some(observer) if hit => observer.on_hit(key),
+            Some(observer) => observer.on_miss(key),
+            None => {}
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(frozen) = &mut self.frozen {
+            let key = (frozen.clone_key)(key);
+            frozen.writes.push(QueuedWrite::Remove(key));
+            return;
+        }
+
+        self.remove_with_cause(key, RemovalCause::Explicit);
+    }
+
+    fn remove_with_cause(&mut self, key: &K, cause: RemovalCause) {
+        let class = self.class(key);
+        let mut removed = false;
+        for (value, weight) in [self.main.remove(key), self.small[class].remove(key)]
+            .into_iter()
+            .flatten()
+        {
+            removed = true;
+            if let Some(listener) = &mut self.listener {
+                listener(key, &value, weight, cause);
+            }
+            self.hand_off(key, value, weight, cause);
+        }
+        if removed && cause == RemovalCause::Expired {
+            self.stats.expirations += 1;
+        }
+        self.unlinked(key, removed);
+    }
+
+    /// Removes the live entry of `key` without telling the listeners, for a
+    /// [`SpillCache`] moving it to another cache, and returns its value,
+    /// weight and deadline. A frozen cache keeps its entries.
+    pub(crate) fn take(&mut self, key: &K) -> Option<(V, usize, Option<Instant>)> {
+        if self.frozen.is_some() || self.expire(key) {
+            return None;
+        }
+        let deadline = self.expiry.deadline(key);
+        let class = self.class(key);
+        let (value, weight) = self.small[class]
+            .remove(key)
+            .or_else(|| self.main.remove(key))?;
+        self.unlinked(key, true);
+        Some((value, weight, deadline))
+    }
+
+    /// Forgets `key` everywhere but in the queues, and tells everyone but
+    /// the listeners if an entry was `removed`.
+    fn unlinked(&mut self, key: &K, removed: bool) {
+        self.remove_from_ghost(key);
+        self.untrack(key);
+        if let Some(shadow) = &mut self.shadow {
+            shadow.remove(key);
+        }
+
+        self.sync_pool();
+        if removed {
+            self.record_change(Change::Remove(key));
+            if let Some(replication) = &mut self.replication {
+                replication(Mutation::Remove(key));
+            }
+        }
+        if removed && !self.events.is_empty() {
+            self.events.publish(Event::Remove(key));
+        }
+    }
+
+    /// Removes the entry of `key` if its time to live ran out, and tells
+    /// whether it did. A frozen cache keeps expired entries until thawed, but
+    /// lookups still miss them.
+    fn expire(&mut self, key: &K) -> bool {
+        if !self.expiry.is_expired(key) {
+            return false;
+        }
+        if self.frozen.is_none() {
+            self.remove_with_cause(key, RemovalCause::Expired);
+        }
+        true
+    }
+
+    /// Removes the entries whose time to live ran out, which lookups
+    /// otherwise only drop once they look them up. Returns the removed keys,
+    /// earliest deadline first, and nothing while frozen.
+    pub fn expire_stale(&mut self) -> Vec<K> {
+        if self.frozen.is_some() {
+            return vec![];
+        }
+
+        let now = self.expiry.now();
+        let mut expired = vec![];
+        while let Some(key) = self.expiry.pop_expired(now) {
+            self.remove_with_cause(&key, RemovalCause::Expired);
+            expired.push(key);
+        }
+        expired
+    }
+
+    /// Hands over the deadlines kept since the last call, see
+    /// [`S3FIFO::keep_evicted_deadlines`].
+    pub(crate) fn take_evicted_deadlines(&mut self) -> Vec<(K, Instant)> {
+        self.evicted_deadlines
+            .as_mut()
+            .map_or_else(Vec::new, std::mem::take)
+    }
+
+    /// Keeps the deadlines of the entries evicted from now on, for
+    /// [`S3FIFO::take_evicted_deadlines`].
+    pub(crate) fn keep_evicted_deadlines(&mut self) {
+        self.evicted_deadlines.get_or_insert_with(Vec::new);
+    }
+
+    /// Time left before the entry of `key` expires, zero once expired, and
+    /// `None` for entries put without a time to live.
+    pub fn time_to_live(&self, key: &K) -> Option<Duration> {
+        let deadline = self.expiry.deadline(key)?;
+        Some(deadline.saturating_duration_since(self.expiry.now()))
+    }
+
+    /// Gives the entries of `deadlines` still in the cache their deadline
+    /// back, after they were moved in from another cache.
+    fn restore_deadlines(&mut self, deadlines: Vec<(K, Instant)>) {
+        for (key, deadline) in deadlines {
+            if self.contains_live(&key) {
+                self.expiry.set(key, deadline);
+            }
+        }
+    }
+
+    /// Drops the stale handles removals left in the queues. Removals reclaim
+    /// their weight right away and compact a queue once its stale handles
+    /// outnumber its entries, so this only trims the queues' memory early.
+    /// Returns the number of dropped handles.
+    pub fn compact(&mut self) -> usize {
+        let compacted = self.main.compact()
+            + self.small.iter_mut().map(FIFO::compact).sum::<usize>()
+            + self.ghost.as_mut().map_or(0, GhostFIFO::compact);
+        self.sync_pool();
+        compacted
+    }
+
+    /// Evicts the entries this cache holds beyond its fair share of an
+    /// overcommitted [`CapacityPool`], which puts into it otherwise do
+    /// lazily. Returns the evicted keys, in eviction order.
+    pub fn reclaim(&mut self) -> Vec<K> {
+        let excess = self.pool.as_ref().map_or(0, |pool| pool.excess(0));
+        if excess == 0 {
+            return vec![];
+        }
+
+        let evicted = self.shrink(excess);
+        self.report_evicted(evicted)
+    }
+
+    /// Evicts the entries puts let in beyond the capacity, within the
+    /// overcommit window of [`S3FIFOBuilder::hard_capacity`]. Call it off the
+    /// hot path, from a maintenance task or between bursts. Returns the
+    /// evicted keys, in eviction order.
+    pub fn maintain(&mut self) -> Vec<K> {
+        let mut evicted = vec![];
+        for class in 0..self.small.len() {
+            let removed = self.small[class].maintain();
+            evicted.extend(self.demote_from_small(removed).into_iter().flatten());
+        }
+        let removed = self.main.maintain();
+        evicted.extend(self.evicted_from_main(removed).into_iter().flatten());
+        self.report_evicted(evicted)
+    }
+
+    /// Weight held beyond the capacity, waiting for [`S3FIFO::maintain`].
+    pub fn overcommitted(&self) -> usize {
+        self.small
+            .iter()
+            .map(|small| small.used_capacity().saturating_sub(small.capacity()))
+            .sum::<usize>()
+            + self
+                .main
+                .used_capacity()
+                .saturating_sub(self.main.capacity())
+    }
+
+    /// Tells everyone but the listener by reference about entries evicted
+    /// outside of a put, and returns their keys.
+    fn report_evicted(&mut self, evicted: Vec<EvictedEntry<K, V>>) -> Vec<K> {
+        self.sync_pool();
+        let mut keys = Vec::with_capacity(evicted.len());
+        for evicted in evicted {
+            if let Some(observer) = &mut self.observer {
+                observer.on_evict(&evicted.key, evicted.segment);
+            }
+            self.record_change(Change::Remove(&evicted.key));
+            if let Some(replication) = &mut self.replication {
+                replication(Mutation::Evict(&evicted.key));
+            }
+            if !self.events.is_empty() {
+                self.events.publish(Event::Evict(&evicted.key));
+            }
+            self.hand_off(
+                &evicted.key,
+                evicted.value,
+                evicted.weight,
+                RemovalCause::Size,
+            );
+            keys.push(evicted.key);
+        }
+        keys
+    }
+
+    /// Starts a new generation of the main queue and evicts the main entries
+    /// that were neither put nor hit during the last
+    /// [`S3FIFOBuilder::generations`] ones, however often they were hit
+    /// before. Pinned entries are kept. Returns the evicted keys, in queue
+    /// order, and nothing unless generations are set.
+    ///
+    /// Calling it on a schedule bounds how long an entry can stay in the cache
+    /// without being used.
+    pub fn rotate(&mut self) -> Vec<K> {
+        let Some(generations) = self.generations else {
+            return vec![];
+        };
+
+        #[cfg(feature = "latency")]
+        let start = Instant::now();
+        let rotated = self.main.rotate(generations);
+        let mut keys = Vec::with_capacity(rotated.len());
+        for item in rotated {
+            if let Some(listener) = &mut self.listener {
+                listener(&item.key, &item.value, item.weight, RemovalCause::Rotated);
+            }
+            self.record_evict(&item.key, Segment::Main);
+            self.untrack(&item.key);
+            self.record_change(Change::Remove(&item.key));
+            if let Some(replication) = &mut self.replication {
+                replication(Mutation::Evict(&item.key));
+            }
+            if let Some(observer) = &mut self.observer {
+                observer.on_evict(&item.key, Segment::Main);
+            }
+            if let Some(shadow) = &mut self.shadow {
+                shadow.remove(&item.key);
+            }
+            if !self.events.is_empty() {
+                self.events.publish(Event::Evict(&item.key));
+            }
+            self.hand_off(&item.key, item.value, item.weight, RemovalCause::Rotated);
+            keys.push(item.key);
+        }
+        self.sync_pool();
+        #[cfg(feature = "latency")]
+        self.latency.evict.record(start.elapsed());
+
+        keys
+    }
+
+    /// Replays a mutation told to the replication sink of another cache, see
+    /// [`S3FIFOBuilder::replication`]. Evictions are replayed as removals,
+    /// so a replica of another size or policy still drops what the primary
+    /// dropped, and may evict more on its own.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the errors of [`S3FIFO::put`].
+    pub fn apply(&mut self, mutation: Mutation<K, V>) -> Result<Option<Vec<K>>, S3FIFOError<K>> {
+        match mutation {
+            Mutation::Put { key, value, weight } => {
+                let result = self.put_inner(key, value, weight, None);
+                result.map(|evicted| self.evicted_keys(evicted))
+            }
+            Mutation::Remove(key) | Mutation::Evict(key) => {
+                self.remove(&key);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Number of changes made to the cache so far, when recorded with
+    /// [`S3FIFOBuilder::change_feed`], otherwise always 0. Pass it to
+    /// [`S3FIFO::changes_since`] later to catch up with the cache.
+    pub fn generation(&self) -> u64 {
+        self.changes.as_ref().map_or(0, ChangeFeed::generation)
+    }
+
+    /// Changes made after `generation`, oldest first, each with the
+    /// generation it started. Evictions, rotations and splits show up as
+    /// removals, merges and preloads as inserts.
+    ///
+    /// Returns `None` once the feed no longer holds every change since
+    /// `generation`, or without a feed: the caller has to rescan the cache.
+    pub fn changes_since(
+        &self,
+        generation: u64,
+    ) -> Option<impl Iterator<Item = (u64, &Change<K>)>> {
+        self.changes.as_ref()?.since(generation)
+    }
+
+    /// Returns `true` if `key` was recently evicted from the small queue and a
+    /// put would admit it straight into the main queue.
+    pub fn ghost_contains(&self, key: &K) -> bool {
+        self.ghost.as_ref().is_some_and(|ghost| ghost.contains(key))
+    }
+
+    /// Returns how many keys entered the ghost queue after `key`, so `Some(0)`
+    /// is the most recent departure from the small queue.
+    pub fn ghost_depth(&self, key: &K) -> Option<usize> {
+        self.ghost.as_ref().and_then(|ghost| ghost.depth(key))
+    }
+
+    /// Number of keys remembered by the ghost queue.
+    pub fn ghost_len(&self) -> usize {
+        self.ghost.as_ref().map_or(0, GhostFIFO::len)
+    }
+
+    /// Subscribes to the stream of cache events, buffering up to
+    /// [`DEFAULT_EVENT_BUFFER`] events.
+    pub fn subscribe(&mut self) -> EventReceiver<K>
+    where
+        K: Clone,
+    {
+        self.subscribe_with_buffer(DEFAULT_EVENT_BUFFER)
+    }
+
+    /// Subscribes to the stream of cache events. Events that don't fit in
+    /// `buffer` are dropped, see [`EventReceiver`].
+    pub fn subscribe_with_buffer(&mut self, buffer: usize) -> EventReceiver<K>
+    where
+        K: Clone,
+    {
+        self.clone_key.get_or_insert(K::clone);
+        self.events.subscribe(buffer, K::clone)
+    }
+
+    fn contains_live(&self, key: &K) -> bool {
+        self.small[self.class(key)].contains_key(key) || self.main.contains_key(key)
+    }
+
+    /// Live value and weight of `key`, without counting a hit.
+    fn peek_entry(&self, key: &K) -> Option<(&V, usize)> {
+        self.small[self.class(key)]
+            .peek(key)
+            .or_else(|| self.main.peek(key))
+    }
+
+    /// Index of the small queue holding `key`.
+    fn class(&self, key: &K) -> usize {
+        self.classifier
+            .as_ref()
+            .map_or(0, |classifier| classifier(key) % self.small.len())
+    }
+
+    /// `key` is the copy made by [`S3FIFO::track`], `None` when nothing
+    /// tracks keys.
+    fn observe_put(
+        &mut self,
+        key: Option<&K>,
+        weight: usize,
+        updated: bool,
+        result: &PutResult<K, V>,
+    ) {
+        self.sync_pool();
+        if result.is_ok() {
+            self.stats.insertions += 1;
+        }
+        let Some(key) = key else {
+            return;
+        };
+        if let (Some(shadow), Ok(_)) = (&mut self.shadow, result) {
+            shadow.record_put(key, weight);
+        }
+        if let (Some(quotas), Ok(_)) = (&mut self.quotas, result) {
+            quotas.record_put(key, weight);
+        }
+        if let (Some(versions), Ok(_)) = (&mut self.versions, result) {
+            versions.bump(key);
+        }
+        if let (Some(lifetimes), Ok(_)) = (&mut self.lifetimes, result) {
+            lifetimes.record_put(key);
+        }
+        if let (Some(changes), Ok(evicted)) = (&mut self.changes, result) {
+            for evicted in evicted.iter().flatten() {
+                changes.push(Change::Remove(&evicted.key));
+            }
+            changes.push(if updated {
+                Change::Update(key)
+            } else {
+                Change::Insert(key)
+            });
+        }
+        if let (Some(replication), Ok(evicted)) = (&mut self.replication, result) {
+            for evicted in evicted.iter().flatten() {
+                replication(Mutation::Evict(&evicted.key));
+            }
+            let class = self
+                .classifier
+                .as_ref()
+                .map_or(0, |classifier| classifier(key) % self.small.len());
+            if let Some((value, weight)) =
+                self.small[class].peek(key).or_else(|| self.main.peek(key))
+            {
+                replication(Mutation::Put { key, value, weight });
+            }
+        }
+        let Some(observer) = &mut self.observer else {
+            return;
+        };
+
+        if let Ok(evicted) = result {
+            for evicted in evicted.iter().flatten() {
+                observer.on_evict(&evicted.key, evicted.segment);
+            }
+            if updated {
+                observer.on_update(key, weight);
+            } else {
+                observer.on_insert(key, weight);
+            }
+        }
+    }
+
+    fn publish_put(&mut self, key: Option<&K>, result: &PutResult<K, V>) {
+        let (Some(key), Ok(evicted)) = (key, result) else {
+            return;
+        };
+
+        for evicted in evicted.iter().flatten() {
+            self.events.publish(Event::Evict(&evicted.key));
+        }
+        self.events.publish(Event::Put(key));
+    }
+
+    fn record_change(&mut self, change: Change<&K>) {
+        if let Some(changes) = &mut self.changes {
+            changes.push(change);
+        }
+    }
+
+    fn remove_from_ghost(&mut self, key: &K) {
+        if let Some(ghost) = &mut self.ghost {
+            ghost.remove(key);
+        }
+    }
+}
+
+impl<K, T, S> S3FIFO<K, Arc<T>, S>
+where
+    K: Eq + Hash,
+    T: ?Sized,
+    S: BuildHasher,
+{
+    /// Like [`S3FIFO::get`], but pins the entry: it is not evicted while the
+    /// returned [`EntryRef`] is alive, and the handle shares the value
+    /// instead of cloning it.
+    ///
+    /// When pinned entries take up a whole queue, puts still succeed and the
+    /// queue goes over capacity until the entries are unpinned.
+    pub fn pin(&mut self, key: &K) -> Option<EntryRef<T>> {
+        #[cfg(feature = "latency")]
+        let start = Instant::now();
+        let expired = self.expire(key);
+        let class = self.class(key);
+        let pinned = if expired {
+            None
+        } else if self.small[class].contains_key(key) {
+            self.small[class].pin(key)
+        } else {
+            self.main.pin(key)
+        };
+        let pinned = pinned.map(|(value, pin)| EntryRef::new(Arc::clone(value), pin));
+        Self::observe_get(
+            &mut self.observer,
+            &mut self.hot_keys,
+            &mut self.shadow,
+            &mut self.quotas,
+            &mut self.lifetimes,
+            key,
+            pinned.is_some(),
+        );
+        self.stats.record_get(pinned.is_some());
+        #[cfg(feature = "latency")]
+        self.latency.record_get(start);
+        pinned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn fifo_works() {
+        let mut cache = FIFO::new(10);
+        cache.put(1, 1, 2).unwrap();
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn fifo_reinserion() {
+        let mut cache = FIFOReinsertion::new(10);
+        cache.put(1, 1, 2).unwrap();
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn ghost_fifo() {
+        let mut cache = GhostFIFO::new(10);
+        cache.put(&1, 2).unwrap();
+        assert!(cache.get(&1));
+        assert!(!cache.get(&2));
+    }
+
+    #[test]
+    fn s3fifo_works() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, 1, 1).unwrap();
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn s3fifo_works_with_string() {
+        let mut cache = S3FIFO::new(10);
+        let key = String::from("1");
+
+        cache.put(&key, 1, 1).unwrap();
+        assert_eq!(cache.get(&"1".to_string()), Some(&1));
+        assert_eq!(cache.get(&"2".to_string()), None);
+    }
+
+    #[test]
+    fn s3fifo_works_with_string_2() {
+        let mut cache = S3FIFO::new(10);
+        let key = String::from("1");
+        let key2 = String::from("1");
+
+        cache.put(&key, 1, 1).unwrap();
+        assert_eq!(cache.get(&key2), Some(&1));
+        assert_eq!(cache.get(&key), Some(&1));
+        assert_eq!(cache.get(&"2".to_string()), None);
+    }
+
+    #[test]
+    fn s3fifo_get_cloned() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, String::from("1"), 1).unwrap();
+
+        let value = cache.get_cloned(&1);
+        cache.put(&2, String::from("2"), 1).unwrap();
+
+        assert_eq!(value, Some(String::from("1")));
+        assert_eq!(cache.get_cloned(&3), None);
+    }
+
+    #[test]
+    fn s3fifo_get_key_value() {
+        let mut cache = S3FIFO::new(10);
+        let key = String::from("1");
+
+        cache.put(&key, 1, 1).unwrap();
+        cache
+            .put_with_hint(&"2".to_string(), 2, 1, Hint::Hot)
+            .unwrap();
+
+        assert_eq!(cache.get_key_value(&"1".to_string()), Some((&key, &1)));
+        assert_eq!(
+            cache.get_key_value(&"2".to_string()),
+            Some((&"2".to_string(), &2))
+        );
+        assert_eq!(cache.get_key_value(&"3".to_string()), None);
+    }
+
+    #[test]
+    fn it_should_publish_events() {
+        let mut cache = S3FIFO::new(10);
+        let receiver = cache.subscribe();
+
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.remove(&2);
+        cache.remove(&3);
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                Event::Put(1),
+                Event::Evict(1),
+                Event::Put(2),
+                Event::Remove(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_merge() {
+        let mut cache = S3FIFO::new(20);
+        cache.put(&1, 1, 1).unwrap();
+        cache.put_with_hint(&2, 2, 1, Hint::Hot).unwrap();
+
+        let mut other = S3FIFO::new(20);
+        other.put(&3, 3, 1).unwrap();
+        other.put_with_hint(&4, 4, 1, Hint::Hot).unwrap();
+        other.put_with_hint(&2, 20, 1, Hint::Hot).unwrap();
+
+        let removed_keys = cache.merge(other);
+
+        assert_eq!(removed_keys, vec![]);
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), Some(&20));
+        assert_eq!(cache.get(&3), Some(&3));
+        assert_eq!(cache.get(&4), Some(&4));
+    }
+
+    #[test]
+    fn it_should_merge_within_capacity() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, 1, 1).unwrap();
+
+        let mut other = S3FIFO::new(100);
+        other.put(&2, 2, 1).unwrap();
+        other.put(&3, 3, 9).unwrap();
+        other.put_with_hint(&4, 4, 50, Hint::Hot).unwrap();
+
+        let removed_keys = cache.merge(other);
+
+        assert_eq!(removed_keys, vec![4, 1, 3]);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&4), None);
+        assert!(cache.ghost_contains(&1));
+    }
+
+    #[test]
+    fn it_should_split_off() {
+        let mut cache = S3FIFO::new(20);
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put_with_hint(&3, 3, 1, Hint::Hot).unwrap();
+        cache.put_with_hint(&4, 4, 1, Hint::Hot).unwrap();
+        let receiver = cache.subscribe();
+
+        let mut split = cache.split_off(10, |key, _| key % 2 == 0);
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![Event::Remove(4), Event::Remove(2)]
+        );
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some(&3));
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(split.get(&2), Some(&2));
+        assert_eq!(split.get(&4), Some(&4));
+        assert_eq!(split.get(&1), None);
+    }
+
+    #[test]
+    fn it_should_split_off_alike_and_report_the_overflow() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut cache = S3FIFO::builder(40)
+            .ratios(50, 50)
+            .eviction_listener(move |key: &i32, _, _, cause| sender.send((*key, cause)).unwrap())
+            .build();
+        for key in 0..20 {
+            cache.put(&key, key, 1).unwrap();
+        }
+
+        let split = cache.split_off(10, |key, _| key % 2 == 0);
+
+        assert_eq!(
+            split.segment_sizes(),
+            SegmentSizes {
+                small: 5,
+                main: 5,
+                ghost: Some(5),
+            }
+        );
+        let evicted: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(evicted.len(), 5);
+        assert!(evicted
+            .iter()
+            .all(|(key, cause)| key % 2 == 0 && *cause == RemovalCause::Size));
+        assert_eq!(cache.len() + split.len() + evicted.len(), 20);
+    }
+
+    #[test]
+    fn it_should_put_cow_values() {
+        let mut cache: S3FIFO<u32, Cow<'static, str>> = S3FIFO::new(200);
+        cache.put_weighted(&1, Cow::Borrowed("static")).unwrap();
+        cache
+            .put_weighted(&2, Cow::Owned(String::from("owned")))
+            .unwrap();
+
+        let error = cache
+            .put_weighted(&3, Cow::Borrowed("far too long for small"))
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            S3FIFOError::BeyondCapacity { weight: 22, .. }
+        ));
+        assert_eq!(cache.get(&1), Some(&Cow::Borrowed("static")));
+        assert_eq!(cache.get(&2), Some(&Cow::Borrowed("owned")));
+    }
+
+    #[test]
+    fn it_should_pass_through_rejected_values() {
+        let mut cache = S3FIFO::new(100);
+        let weigher = |_: &u32, value: &String| (value != "secret").then_some(value.len());
+
+        cache
+            .put_with_weigher(&1, String::from("public"), weigher)
+            .unwrap();
+        cache
+            .put_with_weigher(&2, String::from("x"), weigher)
+            .unwrap();
+        let removed_keys = cache
+            .put_with_weigher(&2, String::from("secret"), weigher)
+            .unwrap();
+
+        assert_eq!(removed_keys, None);
+        assert_eq!(cache.get(&1), Some(&String::from("public")));
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn it_should_report_victims_in_eviction_order() {
+        let mut cache = S3FIFO::new(20);
+        cache.put_with_hint(&100, 100, 9, Hint::Hot).unwrap();
+        cache.put_with_hint(&101, 101, 9, Hint::Hot).unwrap();
+        cache.put(&1, 1, 1).unwrap();
+        cache.get(&1);
+        cache.put(&2, 2, 1).unwrap();
+
+        let report = cache.put_with_report(&3, 3, 2).unwrap();
+
+        assert_eq!(
+            report.evicted,
+            vec![
+                Evicted {
+                    key: 100,
+                    segment: Segment::Main,
+                },
+                Evicted {
+                    key: 2,
+                    segment: Segment::Small,
+                },
+            ]
+        );
         assert_eq!(cache.get(&1), Some(&1));
-        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&101), Some(&101));
+        assert!(cache.ghost_contains(&2));
     }
 
     #[test]
-    fn fifo_reinserion() {
-        let mut cache = FIFOReinsertion::new(10);
-        cache.put(&1, 1, 2).unwrap();
-        assert_eq!(cache.get(&1), Some(&1));
-        assert_eq!(cache.get(&2), None);
+    fn it_should_hand_back_evicted_entries() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Key(u32);
+
+        let mut cache = S3FIFO::new(10);
+        assert_eq!(cache.put_owned(Key(1), "one", 1).unwrap(), vec![]);
+
+        assert_eq!(
+            cache.put_owned(Key(2), "two", 1).unwrap(),
+            vec![EvictedEntry {
+                key: Key(1),
+                value: "one",
+                weight: 1,
+                segment: Segment::Small,
+            }]
+        );
+        assert!(matches!(
+            cache.put_owned(Key(3), "three", 2),
+            Err(S3FIFOError::BeyondCapacity { key: Key(3), .. })
+        ));
+        assert_eq!(cache.get(&Key(2)), Some(&"two"));
     }
 
     #[test]
-    fn ghost_fifo() {
-        let mut cache = GhostFIFO::new(10);
-        cache.put(&1, 2).unwrap();
-        assert!(cache.get(&1));
-        assert!(!cache.get(&2));
+    fn put_returns_keys_in_eviction_order() {
+        let mut cache = S3FIFO::new(20);
+        cache.put_with_hint(&100, 100, 9, Hint::Hot).unwrap();
+        cache.put_with_hint(&101, 101, 9, Hint::Hot).unwrap();
+        cache.put(&1, 1, 1).unwrap();
+        cache.get(&1);
+        cache.put(&2, 2, 1).unwrap();
+
+        let removed_keys = cache.put(&3, 3, 2).unwrap();
+
+        assert_eq!(removed_keys, Some(vec![100, 2]));
     }
 
+    #[cfg(feature = "memoize")]
     #[test]
-    fn s3fifo_works() {
-        let mut cache = S3FIFO::new(10);
-        cache.put(&1, 1, 1).unwrap();
-        assert_eq!(cache.get(&1), Some(&1));
-        assert_eq!(cache.get(&2), None);
+    fn it_should_memoize() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[kesh::memoize(capacity = 100)]
+        fn square(value: u64) -> u64 {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            value * value
+        }
+
+        assert_eq!(square(3), 9);
+        assert_eq!(square(3), 9);
+        assert_eq!(square(4), 16);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
     }
 
+    #[cfg(feature = "memoize")]
     #[test]
-    fn s3fifo_works_with_string() {
-        let mut cache = S3FIFO::new(10);
-        let key = String::from("1");
+    fn it_should_memoize_recursion() {
+        #[kesh::memoize]
+        fn fibonacci(n: u64) -> u64 {
+            if n < 2 {
+                n
+            } else {
+                fibonacci(n - 1) + fibonacci(n - 2)
+            }
+        }
 
-        cache.put(&key, 1, 1).unwrap();
-        assert_eq!(cache.get(&"1".to_string()), Some(&1));
-        assert_eq!(cache.get(&"2".to_string()), None);
+        assert_eq!(fibonacci(90), 2_880_067_194_370_816_120);
     }
 
+    #[cfg(feature = "memoize")]
     #[test]
-    fn s3fifo_works_with_string_2() {
-        let mut cache = S3FIFO::new(10);
-        let key = String::from("1");
-        let key2 = String::from("1");
+    fn it_should_memoize_with_weigher_and_ttl() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
 
-        cache.put(&key, 1, 1).unwrap();
-        assert_eq!(cache.get(&key2), Some(&1));
-        assert_eq!(cache.get(&key), Some(&1));
-        assert_eq!(cache.get(&"2".to_string()), None);
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[kesh::memoize(capacity = 1000, weigher = |value: &String| value.len(), ttl = Duration::ZERO)]
+        fn greet(name: String, mut times: usize) -> String {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            let mut greeting = String::new();
+            while times > 0 {
+                greeting.push_str(&name);
+                times -= 1;
+            }
+            greeting
+        }
+
+        assert_eq!(greet(String::from("hi"), 2), "hihi");
+        assert_eq!(greet(String::from("hi"), 2), "hihi");
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
     }
 
     #[test]
@@ -216,4 +2700,519 @@ mod tests {
         let mut cache = S3FIFO::new(10);
         cache.put(&1, 1, 2).unwrap();
     }
+
+    #[test]
+    fn hot_hint_skips_small_queue() {
+        let mut cache = S3FIFO::new(10);
+        cache.put_with_hint(&1, 1, 1, Hint::Hot).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn cold_hint_ignores_ghost() {
+        let mut cache = S3FIFO::new_with_ghost_sizing(10, GhostSizing::Main);
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+
+        let removed_keys = cache.put_with_hint(&1, 1, 1, Hint::Cold).unwrap();
+
+        assert_eq!(removed_keys, Some(vec![3]));
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&3), None);
+
+        cache.put(&2, 2, 1).unwrap();
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(cache.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn it_should_inspect_ghost() {
+        let mut cache = S3FIFO::new_with_ghost_sizing(10, GhostSizing::Main);
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+
+        assert!(cache.ghost_contains(&1));
+        assert!(!cache.ghost_contains(&3));
+        assert_eq!(cache.ghost_depth(&2), Some(0));
+        assert_eq!(cache.ghost_depth(&1), Some(1));
+        assert_eq!(cache.ghost_len(), 2);
+
+        cache.put(&1, 1, 1).unwrap();
+
+        assert!(!cache.ghost_contains(&1));
+        assert_eq!(cache.ghost_len(), 1);
+    }
+
+    #[test]
+    fn it_should_report_beyond_capacity_context() {
+        let mut cache = S3FIFO::new(10);
+
+        let error = cache.put(&1, 1, 2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "entry 1 with weight 2 is beyond capacity 1 of the small queue"
+        );
+
+        let error = cache.put_with_hint(&2, 2, 10, Hint::Hot).unwrap_err();
+        assert!(matches!(
+            error,
+            S3FIFOError::BeyondCapacity {
+                key: 2,
+                weight: 10,
+                capacity: 9,
+                segment: Segment::Main,
+            }
+        ));
+    }
+
+    #[test]
+    fn ghost_sized_to_main_remembers_older_departures() {
+        let mut cache = S3FIFO::new_with_ghost_sizing(10, GhostSizing::Main);
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+
+        let removed_keys = cache.put(&1, 1, 1).unwrap();
+
+        assert_eq!(removed_keys, None);
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn ghost_sized_to_small_forgets_older_departures() {
+        let mut cache = S3FIFO::new_with_ghost_sizing(10, GhostSizing::Small);
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+
+        let removed_keys = cache.put(&1, 1, 1).unwrap();
+
+        assert_eq!(removed_keys, Some(vec![3]));
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&3), None);
+    }
+
+    #[test]
+    fn it_should_not_evict_pinned_entries() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, Arc::new(1), 1).unwrap();
+        let entry = cache.pin(&1).unwrap();
+
+        let removed_keys = cache.put(&2, Arc::new(2), 1).unwrap();
+
+        assert_eq!(removed_keys, None);
+        assert_eq!(*entry, 1);
+        assert!(cache.small[0].contains_key(&2));
+
+        drop(entry);
+        let removed_keys = cache.put(&3, Arc::new(3), 1).unwrap();
+
+        assert_eq!(removed_keys, Some(vec![2]));
+        assert_eq!(cache.get(&1), Some(&Arc::new(1)));
+    }
+
+    #[test]
+    fn it_should_update_main_entries_in_place() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, 1, 1).unwrap();
+        cache.get(&1);
+        cache.put(&2, 2, 1).unwrap();
+
+        cache.put(&1, 100, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+        cache.put(&4, 4, 1).unwrap();
+
+        assert!(!cache.small[0].contains_key(&1));
+        assert_eq!(cache.get(&1), Some(&100));
+    }
+
+    #[test]
+    fn it_should_defer_writes_while_frozen() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, 1, 1).unwrap();
+
+        cache.freeze();
+        assert_eq!(cache.put(&1, 10, 1).unwrap(), None);
+        cache.put_with_hint(&2, 2, 1, Hint::Cold).unwrap();
+        cache.put(&3, 3, 5).unwrap();
+        cache.remove(&1);
+
+        assert!(cache.is_frozen());
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+
+        assert_eq!(cache.thaw(), vec![3]);
+        assert!(!cache.is_frozen());
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+    }
+
+    #[test]
+    fn it_should_compact_removed_entries() {
+        let mut cache = S3FIFO::new(100);
+        for key in 1..=3 {
+            cache.put(&key, key, 1).unwrap();
+        }
+        cache.remove(&1);
+
+        assert_eq!(cache.weight(), 2);
+        assert_eq!(cache.compact(), 1);
+        assert_eq!(cache.compact(), 0);
+
+        // Stale handles outnumbering the entries compact the queue.
+        cache.remove(&2);
+        cache.remove(&3);
+        assert_eq!(cache.compact(), 0);
+        assert_eq!(cache.small[0].used_capacity(), 0);
+    }
+
+    #[test]
+    fn it_should_cache_values_without_debug() {
+        #[derive(Clone)]
+        struct Secret(u32);
+
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, Secret(42), 1).unwrap();
+
+        assert!(cache.get(&1).is_some_and(|secret| secret.0 == 42));
+    }
+
+    #[test]
+    fn it_should_preload_hottest_entries_last() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&0, 0, 1).unwrap();
+        cache.remove(&0);
+
+        let skipped = cache.preload((1..13).chain([1]).map(|key| (key, key, 1)));
+
+        assert_eq!(skipped, vec![11, 12, 1]);
+        assert_eq!(cache.main.used_capacity(), 9);
+        assert!(cache.main.contains_key(&9));
+        assert!(cache.small[0].contains_key(&10));
+
+        cache.put(&11, 11, 1).unwrap();
+        let removed_keys = cache.put(&10, 10, 1).unwrap();
+
+        assert_eq!(removed_keys, Some(vec![9]));
+        assert!(cache.main.contains_key(&1));
+    }
+
+    #[test]
+    fn it_should_cache_trait_objects() {
+        trait Handler: Send + Sync {
+            fn handle(&self, request: u32) -> u32;
+        }
+
+        struct Double;
+
+        impl Handler for Double {
+            fn handle(&self, request: u32) -> u32 {
+                request * 2
+            }
+        }
+
+        let mut cache: S3FIFO<&str, Box<dyn Handler>> = S3FIFO::new(10);
+        cache
+            .put_with_weigher(&"double", Box::new(Double), |_, _| Some(1))
+            .unwrap();
+
+        assert_eq!(
+            cache.get(&"double").map(|handler| handler.handle(21)),
+            Some(42)
+        );
+    }
+
+    #[test]
+    fn it_should_rotate_out_unused_main_entries() {
+        let mut cache = S3FIFO::builder(10).generations(1).build();
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&1, 1, 1).unwrap();
+
+        assert_eq!(cache.rotate(), vec![1]);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+        assert!(S3FIFO::<i32, i32>::new(10).rotate().is_empty());
+    }
+
+    #[test]
+    fn it_should_isolate_small_queues() {
+        let mut cache = S3FIFO::builder(100)
+            .small_queues(2, |key: &u32| (*key % 2) as usize)
+            .build();
+        cache.put(&1, 1, 1).unwrap();
+
+        for key in (0..100).step_by(2) {
+            cache.put(&key, key, 1).unwrap();
+        }
+
+        assert!(cache.small[1].contains_key(&1));
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.small[0].capacity(), 5);
+    }
+
+    #[test]
+    fn it_should_version_entries() {
+        let mut cache = S3FIFO::builder(10).versioned().build();
+        cache.compare_and_put(&1, None, 1, 1).unwrap();
+        let version = cache.version(&1).unwrap();
+
+        assert!(matches!(
+            cache.compare_and_put(&1, None, 2, 1),
+            Err(S3FIFOError::VersionMismatch { actual: Some(v), .. }) if v == version
+        ));
+        cache.compare_and_put(&1, Some(version), 3, 1).unwrap();
+        assert_eq!(cache.get_versioned(&1), Some((&3, version + 1)));
+        assert!(cache.compare_and_put(&1, Some(version), 4, 1).is_err());
+
+        cache.remove(&1);
+        assert_eq!(cache.version(&1), None);
+        cache.put(&1, 5, 1).unwrap();
+        assert_eq!(cache.version(&1), Some(version + 2));
+        assert_eq!(S3FIFO::new(10).get_versioned(&1), None::<(&i32, u64)>);
+    }
+
+    #[test]
+    fn it_should_evict_own_entries_over_quota() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut cache = S3FIFO::builder(100)
+            .tenant_quota(3, |key: &u32| u64::from(*key / 100))
+            .eviction_listener_ref(move |key, _, _, cause| sender.send((*key, cause)).unwrap())
+            .build();
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&100, 100, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+
+        let removed_keys = cache.put(&4, 4, 1).unwrap();
+
+        assert_eq!(removed_keys, Some(vec![1]));
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![(1, RemovalCause::Size)]
+        );
+        assert_eq!(cache.get(&100), Some(&100));
+        assert_eq!(cache.get(&2), Some(&2));
+
+        cache.remove(&2);
+        assert_eq!(cache.put(&5, 5, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn it_should_evict_tenants_above_their_share() {
+        let mut cache = S3FIFO::builder(10)
+            .tenant_shares([(0, 4), (1, 1)], |key: &u32| u64::from(*key / 100))
+            .build();
+        for key in [0, 1, 100, 101, 102, 103, 104, 105, 106] {
+            cache.put_with_hint(&key, key, 1, Hint::Hot).unwrap();
+        }
+
+        let removed_keys = cache.put_with_hint(&2, 2, 1, Hint::Hot).unwrap();
+
+        assert_eq!(removed_keys, Some(vec![100]));
+        assert_eq!(cache.get(&0), Some(&0));
+        let stats = cache.tenant_stats();
+        assert_eq!((stats[0].entries, stats[0].hits), (3, 1));
+        assert_eq!((stats[1].entries, stats[1].lookups), (6, 0));
+    }
+
+    #[cfg(feature = "latency")]
+    #[test]
+    fn it_should_record_latencies() {
+        let mut cache = S3FIFO::new(10);
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.get(&2);
+        cache.get(&3);
+
+        let stats = cache.latency_stats();
+        assert_eq!(
+            (stats.get.count(), stats.put.count(), stats.evict.count()),
+            (2, 2, 1)
+        );
+        assert!(stats.put.percentile(99.0) <= stats.put.max());
+
+        cache.reset_latency_stats();
+        assert_eq!(cache.latency_stats().put.count(), 0);
+    }
+
+    #[test]
+    fn it_should_feed_changes_since_a_generation() {
+        let mut cache = S3FIFO::builder(10).change_feed(10).build();
+        cache.put(&1, 1, 1).unwrap();
+        let generation = cache.generation();
+
+        cache.put(&1, 10, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+        cache.remove(&2);
+        cache.remove(&3);
+
+        assert_eq!(generation, 1);
+        assert_eq!(
+            cache.changes_since(generation).unwrap().collect::<Vec<_>>(),
+            vec![
+                (2, &Change::Update(1)),
+                (3, &Change::Remove(1)),
+                (4, &Change::Insert(2)),
+                (5, &Change::Remove(2)),
+            ]
+        );
+        for key in 10..20 {
+            cache.put(&key, key, 1).unwrap();
+        }
+        assert!(cache.changes_since(generation).is_none());
+        assert!(S3FIFO::<i32, i32>::new(10).changes_since(0).is_none());
+    }
+
+    #[test]
+    fn it_should_overcommit_until_maintained() {
+        let mut cache = S3FIFO::builder(10).hard_capacity(20).build();
+        for key in 0..12 {
+            cache.put_with_hint(&key, key, 1, Hint::Hot).unwrap();
+        }
+        cache.put(&100, 100, 1).unwrap();
+        cache.put(&101, 101, 1).unwrap();
+
+        assert_eq!(cache.overcommitted(), 4);
+        assert_eq!(cache.maintain(), vec![100, 0, 1, 2]);
+        assert_eq!(cache.overcommitted(), 0);
+        assert_eq!(cache.get(&101), Some(&101));
+        assert_eq!(cache.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn it_should_expire_entries() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Mutex;
+
+        let start = Instant::now();
+        let elapsed = Arc::new(AtomicU64::new(0));
+        let clock = Arc::clone(&elapsed);
+        let expired = Arc::new(Mutex::new(vec![]));
+        let listened = Arc::clone(&expired);
+        let mut cache = S3FIFO::builder(100)
+            .clock(move || start + Duration::from_secs(clock.load(Ordering::Relaxed)))
+            .eviction_listener_ref(move |key, _, _, cause| {
+                if cause == RemovalCause::Expired {
+                    listened
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .push(*key);
+                }
+            })
+            .build();
+        cache
+            .put_with_ttl(&1, 1, 1, Duration::from_secs(10))
+            .unwrap();
+        cache
+            .put_with_hint(&2, 2, 1, Hint::Hot)
+            .and_then(|_| cache.put_with_ttl(&2, 2, 1, Duration::from_secs(20)))
+            .unwrap();
+        cache
+            .put_with_ttl(&3, 3, 1, Duration::from_secs(5))
+            .unwrap();
+        cache.put(&3, 3, 1).unwrap();
+
+        elapsed.store(10, Ordering::Relaxed);
+        assert_eq!(cache.time_to_live(&2), Some(Duration::from_secs(10)));
+        assert_eq!(cache.time_to_live(&3), None);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(cache.get(&3), Some(&3));
+
+        elapsed.store(30, Ordering::Relaxed);
+        assert_eq!(cache.expire_stale(), vec![2]);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(
+            *expired
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            vec![1, 2]
+        );
+        assert_eq!(cache.weight(), 1);
+    }
+
+    #[test]
+    fn it_should_introspect_without_touching_entries() {
+        let mut cache = S3FIFO::new(10);
+        assert!(cache.is_empty());
+        cache.put(&1, 1, 1).unwrap();
+        cache.put_with_hint(&2, 2, 3, Hint::Hot).unwrap();
+        cache.put_with_hint(&3, 3, 2, Hint::Hot).unwrap();
+        cache.remove(&3);
+
+        assert_eq!((cache.len(), cache.weight(), cache.capacity()), (2, 4, 10));
+        assert!(cache.contains_key(&1) && !cache.contains_key(&3));
+        assert_eq!(cache.peek(&2), Some(&2));
+        assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&1, &1), (&2, &2)]);
+
+        // Peeking didn't count a hit, so 1 is evicted rather than promoted.
+        assert_eq!(cache.put(&4, 4, 1).unwrap(), Some(vec![1]));
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(cache.get(&1), None);
+
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.hits, stats.misses, stats.insertions, stats.evictions),
+            (1, 1, 4, 1)
+        );
+        assert_eq!(
+            stats.main,
+            Occupancy {
+                len: 1,
+                weight: 3,
+                capacity: 9
+            }
+        );
+        assert_eq!((stats.small.len, stats.ghost.unwrap().len), (1, 1));
+    }
+
+    #[test]
+    fn it_should_get_or_insert_with() {
+        #[derive(Debug, PartialEq)]
+        enum LoadError {
+            Down,
+            Full,
+        }
+        impl From<S3FIFOError<u32>> for LoadError {
+            fn from(_: S3FIFOError<u32>) -> Self {
+                LoadError::Full
+            }
+        }
+
+        let mut cache = S3FIFO::<u32, u32>::new(10);
+        assert_eq!(cache.get_or_insert_with(&1, 1, || 1).unwrap(), &1);
+        cache.put(&2, 2, 1).unwrap();
+        assert!(cache.ghost_contains(&1));
+
+        assert_eq!(cache.get_or_insert_with(&1, 1, || 3).unwrap(), &3);
+        assert!(cache.main.contains_key(&1));
+        assert_eq!(cache.get_or_insert_with(&1, 1, || 4).unwrap(), &3);
+        assert!(matches!(
+            cache.get_or_insert_with(&5, 20, || 5),
+            Err(S3FIFOError::BeyondCapacity { .. })
+        ));
+
+        assert_eq!(
+            cache.try_get_or_insert_with(&6, 1, || Err(LoadError::Down)),
+            Err(LoadError::Down)
+        );
+        assert_eq!(
+            cache.try_get_or_insert_with(&6, 20, || Ok(6)),
+            Err(LoadError::Full)
+        );
+        assert!(!cache.contains_key(&6));
+
+        cache.freeze();
+        assert_eq!(cache.get_or_insert_with(&7, 1, || 7).unwrap(), &7);
+        assert_eq!(cache.get(&7), None);
+    }
 }
@@ -0,0 +1,198 @@
+use crate::{CloneKey, Segment};
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+/// How long evicted entries lived and how often they were hit, see
+/// [`crate::S3FIFO::lifetime_stats`].
+///
+/// Counts and means cover every eviction, percentiles the most recent ones
+/// kept by [`crate::S3FIFOBuilder::lifetimes`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LifetimeStats {
+    pub evicted: u64,
+    /// Evicted entries that were never hit.
+    pub never_hit: u64,
+    pub mean_lifetime: Duration,
+    pub p50_lifetime: Duration,
+    pub p90_lifetime: Duration,
+    pub p99_lifetime: Duration,
+    pub mean_hits: f64,
+    pub p50_hits: u64,
+    pub p90_hits: u64,
+    pub p99_hits: u64,
+}
+
+impl LifetimeStats {
+    /// Share of the evicted entries that were never hit.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn never_hit_ratio(&self) -> f64 {
+        if self.evicted == 0 {
+            return 0.0;
+        }
+        self.never_hit as f64 / self.evicted as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct Evictions {
+    evicted: u64,
+    never_hit: u64,
+    lifetime: Duration,
+    hits: u64,
+    /// Lifetime and hits of the most recent evictions, oldest first.
+    samples: VecDeque<(Duration, u64)>,
+}
+
+/// Insertion time and hits of every live entry, and what they were when the
+/// evicted entries left the cache.
+#[derive(Debug)]
+pub struct Lifetimes<K, S> {
+    entries: HashMap<K, (Instant, u64), S>,
+    small: Evictions,
+    main: Evictions,
+    samples: usize,
+    clone_key: CloneKey<K>,
+}
+
+impl<K, S> Lifetimes<K, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Keeps the last `samples` evictions of every segment for percentiles.
+    pub fn with_hasher(samples: usize, hasher: S, clone_key: CloneKey<K>) -> Self {
+        Lifetimes {
+            entries: HashMap::with_hasher(hasher),
+            small: Evictions::default(),
+            main: Evictions::default(),
+            samples: samples.max(1),
+            clone_key,
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Starts the lifetime of `key`, unless the put updated a live entry.
+    pub fn record_put(&mut self, key: &K) {
+        if !self.entries.contains_key(key) {
+            self.entries
+                .insert((self.clone_key)(key), (Instant::now(), 0));
+        }
+    }
+
+    pub fn record_get(&mut self, key: &K, hit: bool) {
+        if let (true, Some((_, hits))) = (hit, self.entries.get_mut(key)) {
+            *hits += 1;
+        }
+    }
+
+    pub fn record_evict(&mut self, key: &K, segment: Segment) {
+        let Some((inserted_at, hits)) = self.entries.remove(key) else {
+            return;
+        };
+
+        let lifetime = inserted_at.elapsed();
+        let evictions = match segment {
+            Segment::Small => &mut self.small,
+            Segment::Main => &mut self.main,
+        };
+        evictions.evicted += 1;
+        evictions.never_hit += u64::from(hits == 0);
+        evictions.lifetime += lifetime;
+        evictions.hits += hits;
+        if evictions.samples.len() == self.samples {
+            evictions.samples.pop_front();
+        }
+        evictions.samples.push_back((lifetime, hits));
+    }
+
+    /// Forgets `key` without counting an eviction.
+    pub fn remove(&mut self, key: &K) {
+        self.entries.remove(key);
+    }
+
+    /// Stats of the entries evicted from `segment`, or from both with `None`.
+    pub fn stats(&self, segment: Option<Segment>) -> LifetimeStats {
+        let evictions = match segment {
+            Some(Segment::Small) => vec![&self.small],
+            Some(Segment::Main) => vec![&self.main],
+            None => vec![&self.small, &self.main],
+        };
+        let evicted: u64 = evictions.iter().map(|evictions| evictions.evicted).sum();
+        if evicted == 0 {
+            return LifetimeStats::default();
+        }
+
+        let lifetime: Duration = evictions.iter().map(|evictions| evictions.lifetime).sum();
+        let hits: u64 = evictions.iter().map(|evictions| evictions.hits).sum();
+        let mut lifetimes = vec![];
+        let mut sampled_hits = vec![];
+        for (sampled_lifetime, sampled_hit) in evictions
+            .iter()
+            .flat_map(|evictions| evictions.samples.iter())
+        {
+            lifetimes.push(*sampled_lifetime);
+            sampled_hits.push(*sampled_hit);
+        }
+        lifetimes.sort_unstable();
+        sampled_hits.sort_unstable();
+
+        #[allow(clippy::cast_precision_loss)]
+        LifetimeStats {
+            evicted,
+            never_hit: evictions.iter().map(|evictions| evictions.never_hit).sum(),
+            mean_lifetime: lifetime.div_f64(evicted as f64),
+            p50_lifetime: percentile(&lifetimes, 50),
+            p90_lifetime: percentile(&lifetimes, 90),
+            p99_lifetime: percentile(&lifetimes, 99),
+            mean_hits: hits as f64 / evicted as f64,
+            p50_hits: percentile(&sampled_hits, 50),
+            p90_hits: percentile(&sampled_hits, 90),
+            p99_hits: percentile(&sampled_hits, 99),
+        }
+    }
+}
+
+/// Nearest-rank `percent` percentile of `sorted`, which isn't empty.
+fn percentile<T: Copy>(sorted: &[T], percent: usize) -> T {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultState;
+
+    #[test]
+    fn it_should_aggregate_evictions_per_segment() {
+        let mut lifetimes = Lifetimes::with_hasher(2, DefaultState::default(), Clone::clone);
+        for key in 0..4 {
+            lifetimes.record_put(&key);
+            for _ in 0..key {
+                lifetimes.record_get(&key, true);
+            }
+        }
+        lifetimes.record_get(&0, false);
+        lifetimes.remove(&3);
+        lifetimes.record_evict(&0, Segment::Small);
+        lifetimes.record_evict(&1, Segment::Main);
+        lifetimes.record_evict(&2, Segment::Main);
+        lifetimes.record_evict(&3, Segment::Main);
+
+        let small = lifetimes.stats(Some(Segment::Small));
+        assert_eq!((small.evicted, small.never_hit, small.p99_hits), (1, 1, 0));
+        let main = lifetimes.stats(Some(Segment::Main));
+        assert_eq!((main.evicted, main.p50_hits, main.p90_hits), (2, 1, 2));
+        let all = lifetimes.stats(None);
+        assert_eq!(all.evicted, 3);
+        assert!((all.mean_hits - 1.0).abs() < f64::EPSILON);
+        assert!((all.never_hit_ratio() - 1.0 / 3.0).abs() < f64::EPSILON);
+        assert!(all.p50_lifetime <= all.p99_lifetime);
+    }
+}
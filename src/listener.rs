@@ -0,0 +1,23 @@
+/// Why an entry left the cache, as told to eviction listeners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalCause {
+    /// The entry was evicted to make room for other entries.
+    Size,
+    /// The entry was removed explicitly.
+    Explicit,
+    /// The entry was overwritten by a put of the same key.
+    Replaced,
+    /// The entry went unused for too many generations of the main queue, see
+    /// [`crate::S3FIFOBuilder::generations`].
+    Rotated,
+    /// The entry outlived its time to live, see [`crate::S3FIFO::put_with_ttl`].
+    Expired,
+}
+
+/// Listener called with the key, value, weight and cause of every entry
+/// leaving the cache, before the value is dropped.
+pub type RefListener<K, V> = Box<dyn FnMut(&K, &V, usize, RemovalCause) + Send>;
+
+/// Listener handed the value of every entry leaving the cache, with its key,
+/// weight and cause, see [`crate::S3FIFOBuilder::eviction_listener`].
+pub type Listener<K, V> = Box<dyn FnMut(&K, V, usize, RemovalCause) + Send>;
@@ -0,0 +1,131 @@
+use crate::S3FIFO;
+
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Memoizes a function with an [`S3FIFO`] cache, without the `memoize`
+/// attribute macro.
+///
+/// ```
+/// let mut squares = kesh::MemoCache::new(100, |n: &u64| n * n);
+///
+/// assert_eq!(squares.get(&12), 144);
+/// ```
+///
+/// Results never expire unless a TTL is set with [`MemoCache::with_ttl`] or
+/// given per call to [`MemoCache::get_with_ttl`].
+pub struct MemoCache<K, V, F> {
+    cache: S3FIFO<K, (V, Option<Instant>)>,
+    compute: F,
+    ttl: Option<Duration>,
+}
+
+impl<K, V, F> MemoCache<K, V, F>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    F: FnMut(&K) -> V,
+{
+    /// Creates a wrapper caching up to `capacity` results of `compute`.
+    #[must_use]
+    pub fn new(capacity: usize, compute: F) -> Self {
+        MemoCache {
+            cache: S3FIFO::new(capacity),
+            compute,
+            ttl: None,
+        }
+    }
+
+    /// Expires results `ttl` after they were computed.
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the cached result for `key`, computing and caching it first if
+    /// it is missing or expired. Results that don't fit in the cache are
+    /// returned uncached.
+    pub fn get(&mut self, key: &K) -> V {
+        self.get_inner(key, self.ttl)
+    }
+
+    /// Like [`MemoCache::get`], but a result computed by this call expires
+    /// after `ttl` instead of the TTL of the wrapper.
+    pub fn get_with_ttl(&mut self, key: &K, ttl: Duration) -> V {
+        self.get_inner(key, Some(ttl))
+    }
+
+    fn get_inner(&mut self, key: &K, ttl: Option<Duration>) -> V {
+        if let Some((value, expires_at)) = self.cache.get_cloned(key) {
+            if expires_at.is_none_or(|expires_at| Instant::now() < expires_at) {
+                return value;
+            }
+        }
+
+        let value = (self.compute)(key);
+        let expires_at = ttl.and_then(|ttl| Instant::now().checked_add(ttl));
+        let _ = self.cache.put(key, (value.clone(), expires_at), 1);
+        value
+    }
+
+    /// Forgets the cached result for `key`, so the next `get` recomputes it.
+    pub fn invalidate(&mut self, key: &K) {
+        self.cache.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_compute_once() {
+        let mut calls = 0;
+        let mut memo = MemoCache::new(100, |key: &u32| {
+            calls += 1;
+            key * 2
+        });
+
+        assert_eq!(memo.get(&1), 2);
+        assert_eq!(memo.get(&1), 2);
+        assert_eq!(memo.get(&2), 4);
+        drop(memo);
+
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn it_should_recompute_after_invalidate() {
+        let mut calls = 0;
+        let mut memo = MemoCache::new(100, |key: &u32| {
+            calls += 1;
+            *key
+        });
+
+        memo.get(&1);
+        memo.invalidate(&1);
+        memo.get(&1);
+        drop(memo);
+
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn it_should_expire_results() {
+        let mut calls = 0;
+        let mut memo = MemoCache::new(100, |key: &u32| {
+            calls += 1;
+            *key
+        })
+        .with_ttl(Duration::ZERO);
+
+        memo.get(&1);
+        memo.get(&1);
+        memo.get_with_ttl(&2, Duration::from_secs(60));
+        memo.get(&2);
+        drop(memo);
+
+        assert_eq!(calls, 3);
+    }
+}
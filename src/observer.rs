@@ -0,0 +1,105 @@
+use crate::Segment;
+
+/// Hooks called by the cache on every operation, for custom metrics and
+/// tracing. Install one with [`crate::S3FIFOBuilder::observer`].
+///
+/// Every hook does nothing by default, so implementations only override the
+/// ones they need.
+pub trait CacheObserver<K>: Send {
+    /// A get found `key`.
+    fn on_hit(&mut self, _key: &K) {}
+
+    /// A get didn't find `key`.
+    fn on_miss(&mut self, _key: &K) {}
+
+    /// A put stored `key`, which wasn't in the cache.
+    fn on_insert(&mut self, _key: &K, _weight: usize) {}
+
+    /// A put overwrote the entry stored under `key`.
+    fn on_update(&mut self, _key: &K, _weight: usize) {}
+
+    /// `key` was evicted from `segment` to make room for other entries.
+    fn on_evict(&mut self, _key: &K, _segment: Segment) {}
+
+    /// A put found `key` in the ghost queue and admitted it straight into the
+    /// main queue.
+    fn on_ghost_hit(&mut self, _key: &K) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::S3FIFOBuilder;
+
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CacheObserver<i32> for Recorder {
+        fn on_hit(&mut self, key: &i32) {
+            self.calls.lock().unwrap().push(format!("hit {key}"));
+        }
+
+        fn on_miss(&mut self, key: &i32) {
+            self.calls.lock().unwrap().push(format!("miss {key}"));
+        }
+
+        fn on_insert(&mut self, key: &i32, weight: usize) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("insert {key} {weight}"));
+        }
+
+        fn on_update(&mut self, key: &i32, weight: usize) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("update {key} {weight}"));
+        }
+
+        fn on_evict(&mut self, key: &i32, segment: Segment) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("evict {key} {segment}"));
+        }
+
+        fn on_ghost_hit(&mut self, key: &i32) {
+            self.calls.lock().unwrap().push(format!("ghost hit {key}"));
+        }
+    }
+
+    #[test]
+    fn it_should_observe_operations() {
+        let recorder = Recorder::default();
+        let calls = recorder.calls.clone();
+        let mut cache = S3FIFOBuilder::new(10).observer(recorder).build();
+
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&1, 1, 1).unwrap();
+        cache.get(&1);
+        cache.get(&2);
+        cache.put(&2, 2, 1).unwrap();
+        cache.put(&3, 3, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "insert 1 1",
+                "update 1 1",
+                "hit 1",
+                "miss 2",
+                "insert 2 1",
+                "evict 2 small",
+                "insert 3 1",
+                "ghost hit 2",
+                "insert 2 1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt::{self, Debug};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Handle to a pinned cache entry, see [`crate::S3FIFO::pin`].
+///
+/// The entry is not evicted while a handle to it is alive. It can still be
+/// removed or overwritten, in which case the handle keeps the old value.
+#[derive(Clone)]
+pub struct EntryRef<T: ?Sized> {
+    value: Arc<T>,
+    _pin: Arc<()>,
+}
+
+impl<T: ?Sized> EntryRef<T> {
+    pub(crate) fn new(value: Arc<T>, pin: Arc<()>) -> Self {
+        EntryRef { value, _pin: pin }
+    }
+}
+
+impl<T: ?Sized> Deref for EntryRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Debug + ?Sized> Debug for EntryRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EntryRef").field(&self.value).finish()
+    }
+}
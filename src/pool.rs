@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug)]
+struct Member {
+    share: u64,
+    used: usize,
+}
+
+#[derive(Debug)]
+struct State {
+    budget: usize,
+    members: BTreeMap<u64, Member>,
+    next_id: u64,
+}
+
+impl State {
+    fn used(&self) -> usize {
+        self.members.values().map(|member| member.used).sum()
+    }
+
+    /// Weight of the budget owed to `id`, in proportion to its share.
+    #[allow(clippy::cast_possible_truncation)]
+    fn fair_share(&self, id: u64) -> usize {
+        let shares: u64 = self.members.values().map(|member| member.share).sum();
+        let share = self.members[&id].share;
+        (self.budget as u128 * u128::from(share) / u128::from(shares.max(1))) as usize
+    }
+}
+
+/// One weight budget shared by several independent caches, so an
+/// application with many typed caches stays within one global bound.
+///
+/// Join a cache with [`crate::S3FIFOBuilder::capacity_pool`]. Every member
+/// is owed a fair share of the budget, in proportion to its share. A member
+/// may use free budget beyond its fair share, but once the pool is full, a
+/// put into a member above its fair share first evicts that member's own
+/// entries to make room.
+///
+/// Members never evict each other's entries, so a put into a member below
+/// its fair share overcommits the pool until the members above theirs put
+/// again or call [`crate::S3FIFO::reclaim`].
+#[derive(Debug, Clone)]
+pub struct CapacityPool {
+    state: Arc<Mutex<State>>,
+}
+
+impl CapacityPool {
+    #[must_use]
+    pub fn new(budget: usize) -> Self {
+        CapacityPool {
+            state: Arc::new(Mutex::new(State {
+                budget,
+                members: BTreeMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    #[must_use]
+    pub fn budget(&self) -> usize {
+        self.lock().budget
+    }
+
+    /// Weight used by all the members. It exceeds the budget while
+    /// overcommitted.
+    #[must_use]
+    pub fn used(&self) -> usize {
+        self.lock().used()
+    }
+
+    /// Number of caches drawing from the pool.
+    #[must_use]
+    pub fn members(&self) -> usize {
+        self.lock().members.len()
+    }
+
+    pub(crate) fn join(&self, share: u64) -> PoolMember {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.members.insert(
+            id,
+            Member {
+                share: share.max(1),
+                used: 0,
+            },
+        );
+        PoolMember {
+            pool: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Membership of a cache in a [`CapacityPool`], left when dropped.
+#[derive(Debug)]
+pub(crate) struct PoolMember {
+    pool: CapacityPool,
+    id: u64,
+}
+
+impl PoolMember {
+    pub fn set_used(&self, used: usize) {
+        if let Some(member) = self.pool.lock().members.get_mut(&self.id) {
+            member.used = used;
+        }
+    }
+
+    /// Weight the member has to evict before growing by `growth`: as much
+    /// as takes the pool back within budget, but no more than takes the
+    /// member down to its fair share.
+    pub fn excess(&self, growth: usize) -> usize {
+        let state = self.pool.lock();
+        let used = state.members[&self.id].used;
+        let over_budget = (state.used() + growth).saturating_sub(state.budget);
+        let over_share = (used + growth).saturating_sub(state.fair_share(self.id));
+        over_budget.min(over_share).min(used)
+    }
+}
+
+impl Drop for PoolMember {
+    fn drop(&mut self) {
+        self.pool.lock().members.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Hint, S3FIFOBuilder};
+
+    #[test]
+    fn it_should_reclaim_from_members_above_their_share() {
+        let pool = CapacityPool::new(10);
+        let mut first = S3FIFOBuilder::new(20).capacity_pool(&pool, 1).build();
+        let mut second = S3FIFOBuilder::new(20).capacity_pool(&pool, 1).build();
+        for key in 0..10 {
+            first.put_with_hint(&key, key, 1, Hint::Hot).unwrap();
+        }
+
+        for key in 100..105 {
+            assert_eq!(second.put_with_hint(&key, key, 1, Hint::Hot).unwrap(), None);
+        }
+        assert_eq!(pool.used(), 15);
+
+        let evicted = first.put_with_hint(&10, 10, 1, Hint::Hot).unwrap().unwrap();
+        assert_eq!(evicted.len(), 6);
+        assert_eq!(pool.used(), 10);
+        assert!(second.reclaim().is_empty());
+
+        drop(second);
+        assert_eq!((pool.members(), pool.used()), (1, 5));
+    }
+
+    #[test]
+    fn it_should_split_the_budget_by_share() {
+        let pool = CapacityPool::new(10);
+        let first = pool.join(1);
+        let second = pool.join(4);
+        first.set_used(6);
+        second.set_used(4);
+
+        assert_eq!(first.excess(1), 1);
+        assert_eq!(second.excess(1), 0);
+        assert_eq!(second.excess(5), 1);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::CloneKey;
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+/// Derives the tenant of a key, see [`crate::S3FIFOBuilder::tenant_quota`].
+pub type TenantOf<K> = Arc<dyn Fn(&K) -> u64 + Send + Sync>;
+
+#[derive(Debug)]
+struct Entry {
+    tenant: u64,
+    weight: usize,
+    seq: u64,
+}
+
+#[derive(Debug)]
+struct Tenant<K> {
+    weight: usize,
+    entries: usize,
+    /// Keys in insertion order, with the sequence number of the insertion.
+    /// A key is stale once it left the cache or was inserted again.
+    order: VecDeque<(K, u64)>,
+}
+
+/// Occupancy and lookups of a tenant, see [`crate::S3FIFO::tenant_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenantStats {
+    pub tenant: u64,
+    pub entries: usize,
+    pub weight: usize,
+    pub lookups: u64,
+    pub hits: u64,
+}
+
+impl TenantStats {
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_ratio(&self) -> f64 {
+        if self.lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.lookups as f64
+    }
+}
+
+/// Weight used by every tenant of the cache, the insertion order of their
+/// entries and their lookups.
+pub struct Quotas<K, S> {
+    tenant_of: TenantOf<K>,
+    quota: Option<usize>,
+    shares: Option<HashMap<u64, u64>>,
+    entries: HashMap<K, Entry, S>,
+    tenants: HashMap<u64, Tenant<K>>,
+    lookups: HashMap<u64, (u64, u64)>,
+    seq: u64,
+    clone_key: CloneKey<K>,
+}
+
+impl<K, S> Quotas<K, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn with_hasher(
+        tenant_of: TenantOf<K>,
+        quota: Option<usize>,
+        shares: Option<HashMap<u64, u64>>,
+        hasher: S,
+        clone_key: CloneKey<K>,
+    ) -> Self {
+        Quotas {
+            tenant_of,
+            quota,
+            shares,
+            entries: HashMap::with_hasher(hasher),
+            tenants: HashMap::new(),
+            lookups: HashMap::new(),
+            seq: 0,
+            clone_key,
+        }
+    }
+
+    /// Tenant function, quota and shares the quotas were built with.
+    pub fn config(&self) -> (TenantOf<K>, Option<usize>, Option<HashMap<u64, u64>>) {
+        (Arc::clone(&self.tenant_of), self.quota, self.shares.clone())
+    }
+
+    pub fn record_get(&mut self, key: &K, hit: bool) {
+        let (lookups, hits) = self.lookups.entry((self.tenant_of)(key)).or_default();
+        *lookups += 1;
+        *hits += u64::from(hit);
+    }
+
+    pub fn weight(&self, key: &K) -> usize {
+        self.entries.get(key).map_or(0, |entry| entry.weight)
+    }
+
+    pub fn record_put(&mut self, key: &K, weight: usize) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            let tenant = self.tenants.get_mut(&entry.tenant).unwrap();
+            tenant.weight = tenant.weight - entry.weight + weight;
+            entry.weight = weight;
+            return;
+        }
+
+        self.seq += 1;
+        let tenant_id = (self.tenant_of)(key);
+        self.entries.insert(
+            (self.clone_key)(key),
+            Entry {
+                tenant: tenant_id,
+                weight,
+                seq: self.seq,
+            },
+        );
+        let tenant = self.tenants.entry(tenant_id).or_insert_with(|| Tenant {
+            weight: 0,
+            entries: 0,
+            order: VecDeque::new(),
+        });
+        tenant.weight += weight;
+        tenant.entries += 1;
+        tenant.order.push_back(((self.clone_key)(key), self.seq));
+
+        // Drop the stale keys once they outnumber the live ones.
+        if tenant.order.len() > 2 * tenant.entries {
+            let entries = &self.entries;
+            tenant
+                .order
+                .retain(|(key, seq)| entries.get(key).is_some_and(|entry| entry.seq == *seq));
+        }
+    }
+
+    pub fn record_remove(&mut self, key: &K) {
+        let Some(entry) = self.entries.remove(key) else {
+            return;
+        };
+        let tenant = self.tenants.get_mut(&entry.tenant).unwrap();
+        tenant.weight -= entry.weight;
+        tenant.entries -= 1;
+        if tenant.entries == 0 {
+            self.tenants.remove(&entry.tenant);
+        }
+    }
+
+    /// The oldest entries of the tenant of `key` to evict so a put of `key`
+    /// with `weight` keeps the tenant within its quota. `key` is never one of
+    /// them, so a single entry heavier than the quota still fits.
+    pub fn victims(&self, key: &K, weight: usize) -> Vec<K> {
+        let tenant_id = (self.tenant_of)(key);
+        let (Some(quota), Some(tenant)) = (self.quota, self.tenants.get(&tenant_id)) else {
+            return vec![];
+        };
+        let excess = (tenant.weight - self.weight(key) + weight).saturating_sub(quota);
+
+        self.oldest(tenant_id, key, excess, |_| true)
+    }
+
+    /// The oldest entries of the tenant furthest above its share of
+    /// `capacity` to evict to free `excess` weight, among the entries
+    /// `evictable` accepts. Shares are proportional to the share of every
+    /// tenant with entries in the cache, 1 unless configured otherwise.
+    pub fn share_victims<F>(&self, key: &K, excess: usize, capacity: usize, evictable: F) -> Vec<K>
+    where
+        F: Fn(&K) -> bool,
+    {
+        let Some(shares) = &self.shares else {
+            return vec![];
+        };
+        let share = |tenant: &u64| u128::from(shares.get(tenant).copied().unwrap_or(1));
+        let tenant_id = (self.tenant_of)(key);
+        let mut total: u128 = self.tenants.keys().map(share).sum();
+        if !self.tenants.contains_key(&tenant_id) {
+            total += share(&tenant_id);
+        }
+
+        // Above its share when weight / capacity > share / total.
+        let over_share = |(tenant, state): (&u64, &Tenant<K>)| {
+            let used = state.weight as u128 * total;
+            let fair = capacity as u128 * share(tenant);
+            (used > fair).then(|| (used - fair, *tenant))
+        };
+        let Some((_, victim_tenant)) = self.tenants.iter().filter_map(over_share).max() else {
+            return vec![];
+        };
+
+        self.oldest(victim_tenant, key, excess, evictable)
+    }
+
+    /// The oldest live entries of `tenant` other than `key` accepted by
+    /// `evictable`, until they weigh `excess`.
+    fn oldest<F>(&self, tenant: u64, key: &K, mut excess: usize, evictable: F) -> Vec<K>
+    where
+        F: Fn(&K) -> bool,
+    {
+        let mut victims = vec![];
+        let Some(tenant) = self.tenants.get(&tenant) else {
+            return victims;
+        };
+        for (victim, seq) in &tenant.order {
+            if excess == 0 {
+                break;
+            }
+            match self.entries.get(victim) {
+                Some(entry) if entry.seq == *seq && victim != key && evictable(victim) => {
+                    excess = excess.saturating_sub(entry.weight);
+                    victims.push((self.clone_key)(victim));
+                }
+                _ => {}
+            }
+        }
+
+        victims
+    }
+
+    /// Stats of every tenant with entries or lookups, by tenant.
+    pub fn stats(&self) -> Vec<TenantStats> {
+        let mut stats: HashMap<u64, TenantStats> = HashMap::new();
+        for (tenant, state) in &self.tenants {
+            let stats = stats.entry(*tenant).or_default();
+            stats.entries = state.entries;
+            stats.weight = state.weight;
+        }
+        for (tenant, (lookups, hits)) in &self.lookups {
+            let stats = stats.entry(*tenant).or_default();
+            stats.lookups = *lookups;
+            stats.hits = *hits;
+        }
+
+        let mut stats: Vec<_> = stats
+            .into_iter()
+            .map(|(tenant, stats)| TenantStats { tenant, ..stats })
+            .collect();
+        stats.sort_by_key(|stats| stats.tenant);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::DefaultState;
+
+    #[test]
+    fn it_should_pick_the_oldest_entries_of_the_tenant() {
+        let mut quotas = Quotas::with_hasher(
+            Arc::new(|key: &u32| u64::from(*key / 10)),
+            Some(3),
+            None,
+            DefaultState::default(),
+            Clone::clone,
+        );
+        quotas.record_put(&1, 1);
+        quotas.record_put(&2, 1);
+        quotas.record_put(&11, 3);
+        quotas.record_put(&3, 1);
+        quotas.record_remove(&2);
+        quotas.record_put(&2, 1);
+
+        assert_eq!(quotas.victims(&4, 1), vec![1]);
+        assert_eq!(quotas.victims(&4, 3), vec![1, 3, 2]);
+        assert_eq!(quotas.victims(&1, 2), vec![3]);
+        assert_eq!(quotas.victims(&12, 1), vec![11]);
+        assert!(quotas.victims(&21, 3).is_empty());
+    }
+
+    #[test]
+    fn it_should_pick_the_tenant_furthest_above_its_share() {
+        let shares = HashMap::from([(0, 3), (1, 1)]);
+        let mut quotas = Quotas::with_hasher(
+            Arc::new(|key: &u32| u64::from(*key / 10)),
+            None,
+            Some(shares),
+            DefaultState::default(),
+            Clone::clone,
+        );
+        for key in [1, 2, 3, 11, 12] {
+            quotas.record_put(&key, 1);
+        }
+        quotas.record_get(&1, true);
+        quotas.record_get(&13, false);
+
+        assert_eq!(quotas.share_victims(&4, 1, 4, |_| true), vec![11]);
+        assert_eq!(quotas.share_victims(&4, 1, 4, |key| *key != 11), vec![12]);
+        assert_eq!(quotas.share_victims(&4, 1, 8, |_| true), Vec::<u32>::new());
+        assert_eq!(
+            quotas.stats()[1],
+            TenantStats {
+                tenant: 1,
+                entries: 2,
+                weight: 2,
+                lookups: 1,
+                hits: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,82 @@
+/// A mutation of a cache, as told to the replication sink set with
+/// [`crate::S3FIFOBuilder::replication`] and replayed by
+/// [`crate::S3FIFO::apply`].
+///
+/// The sink gets `Mutation<&K, &V>` borrowing from the cache, and serializes
+/// or clones what it needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutation<K, V> {
+    /// A put stored the entry.
+    Put { key: K, value: V, weight: usize },
+    /// The key was removed explicitly, or split off into another cache.
+    Remove(K),
+    /// The key was evicted to make room for other entries, or rotated out.
+    Evict(K),
+}
+
+impl<K, V> Mutation<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Mutation::Put { key, .. } | Mutation::Remove(key) | Mutation::Evict(key) => key,
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Mutation<&K, &V> {
+    /// Clones the key and value out of the cache.
+    #[must_use]
+    pub fn cloned(&self) -> Mutation<K, V> {
+        match *self {
+            Mutation::Put { key, value, weight } => Mutation::Put {
+                key: key.clone(),
+                value: value.clone(),
+                weight,
+            },
+            Mutation::Remove(key) => Mutation::Remove(key.clone()),
+            Mutation::Evict(key) => Mutation::Evict(key.clone()),
+        }
+    }
+}
+
+/// Sink called with every mutation of the cache, in order.
+pub type Replicator<K, V> = Box<dyn FnMut(Mutation<&K, &V>) + Send>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{S3FIFOBuilder, S3FIFO};
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn it_should_mirror_a_cache() {
+        let (sender, receiver) = mpsc::channel();
+        let mut primary = S3FIFOBuilder::new(10)
+            .replication(move |mutation: Mutation<&u32, &String>| {
+                sender.send(mutation.cloned()).unwrap();
+            })
+            .build();
+        primary.put(&1, String::from("1"), 1).unwrap();
+        primary.put(&2, String::from("2"), 1).unwrap();
+        primary.put(&3, String::from("3"), 1).unwrap();
+        primary.remove(&3);
+        primary.put(&4, String::from("4"), 1).unwrap();
+
+        let mutations: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(
+            mutations.iter().map(Mutation::key).collect::<Vec<_>>(),
+            [&1, &1, &2, &2, &3, &3, &4]
+        );
+        assert_eq!(mutations[1], Mutation::Evict(1));
+        assert_eq!(mutations[5], Mutation::Remove(3));
+
+        let mut replica = S3FIFO::new(100);
+        for mutation in mutations {
+            replica.apply(mutation).unwrap();
+        }
+        assert_eq!(replica.get(&1), None);
+        assert_eq!(replica.get(&2), None);
+        assert_eq!(replica.get(&3), None);
+        assert_eq!(replica.get(&4), Some(&String::from("4")));
+    }
+}
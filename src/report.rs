@@ -0,0 +1,108 @@
+use crate::Segment;
+
+/// An entry that left the cache, with the segment it was evicted from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evicted<K> {
+    pub key: K,
+    pub segment: Segment,
+}
+
+/// An entry that left the cache, handed back with its value and weight, see
+/// [`crate::S3FIFO::put_owned`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvictedEntry<K, V> {
+    pub key: K,
+    pub value: V,
+    pub weight: usize,
+    pub segment: Segment,
+}
+
+impl<K, V> From<EvictedEntry<K, V>> for Evicted<K> {
+    fn from(entry: EvictedEntry<K, V>) -> Self {
+        Evicted {
+            key: entry.key,
+            segment: entry.segment,
+        }
+    }
+}
+
+/// Entries that left the cache during a put, in eviction order. See
+/// [`crate::S3FIFO::put_with_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvictionReport<K> {
+    pub evicted: Vec<Evicted<K>>,
+}
+
+impl<K> Default for EvictionReport<K> {
+    fn default() -> Self {
+        EvictionReport {
+            evicted: Vec::new(),
+        }
+    }
+}
+
+impl<K> EvictionReport<K> {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.evicted.is_empty()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.evicted.len()
+    }
+
+    /// Keys of all victims, in eviction order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.evicted.iter().map(|evicted| &evicted.key)
+    }
+
+    /// Keys of the victims evicted from `segment`, in eviction order.
+    pub fn keys_from(&self, segment: Segment) -> impl Iterator<Item = &K> {
+        self.evicted
+            .iter()
+            .filter(move |evicted| evicted.segment == segment)
+            .map(|evicted| &evicted.key)
+    }
+
+    #[must_use]
+    pub fn into_keys(self) -> Vec<K> {
+        self.evicted
+            .into_iter()
+            .map(|evicted| evicted.key)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_filter_by_segment() {
+        let report = EvictionReport {
+            evicted: vec![
+                Evicted {
+                    key: 1,
+                    segment: Segment::Main,
+                },
+                Evicted {
+                    key: 2,
+                    segment: Segment::Small,
+                },
+                Evicted {
+                    key: 3,
+                    segment: Segment::Main,
+                },
+            ],
+        };
+
+        assert_eq!(report.len(), 3);
+        assert_eq!(
+            report.keys_from(Segment::Main).collect::<Vec<_>>(),
+            [&1, &3]
+        );
+        assert_eq!(report.keys_from(Segment::Small).collect::<Vec<_>>(), [&2]);
+        assert_eq!(report.into_keys(), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{DefaultState, S3FIFO};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+/// Consistent-hash ring mapping keys to one of several cache instances,
+/// local [`S3FIFO`] shards or remote tiers, identified by `usize` nodes.
+///
+/// Every node gets `vnodes` points on the ring, and a key goes to the node
+/// of the first point at or after its hash. Adding or removing a node only
+/// moves the keys of the points it gains or loses.
+///
+/// Processes routing to shared instances must place the points alike, so
+/// give them a hasher without a random seed, such as [`DefaultState`] with
+/// the `deterministic-hash` feature.
+#[derive(Debug, Clone)]
+pub struct Router<K, S = DefaultState> {
+    ring: BTreeMap<u64, usize>,
+    nodes: BTreeSet<usize>,
+    vnodes: usize,
+    hasher: S,
+    key: PhantomData<fn(&K)>,
+}
+
+impl<K> Router<K> {
+    /// Creates a router without nodes, giving `vnodes` points to every node
+    /// added, at least 1. More points spread the keys more evenly.
+    #[must_use]
+    pub fn new(vnodes: usize) -> Self {
+        Self::with_hasher(vnodes, DefaultState::default())
+    }
+}
+
+impl<K, S> Router<K, S>
+where
+    S: BuildHasher,
+{
+    #[must_use]
+    pub fn with_hasher(vnodes: usize, hasher: S) -> Self {
+        Router {
+            ring: BTreeMap::new(),
+            nodes: BTreeSet::new(),
+            vnodes: vnodes.max(1),
+            hasher,
+            key: PhantomData,
+        }
+    }
+
+    /// Adds `node` to the ring. Returns `false` if it was already there.
+    pub fn add(&mut self, node: usize) -> bool {
+        if !self.nodes.insert(node) {
+            return false;
+        }
+        for vnode in 0..self.vnodes {
+            self.ring.insert(self.hasher.hash_one((node, vnode)), node);
+        }
+        true
+    }
+
+    /// Removes `node` from the ring. Returns `false` if it wasn't there.
+    pub fn remove(&mut self, node: usize) -> bool {
+        if !self.nodes.remove(&node) {
+            return false;
+        }
+        self.ring.retain(|_, owner| *owner != node);
+        true
+    }
+
+    /// Nodes on the ring, in ascending order.
+    pub fn nodes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes.iter().copied()
+    }
+
+    /// Node owning `key`, `None` while the ring is empty.
+    pub fn route(&self, key: &K) -> Option<usize>
+    where
+        K: Hash,
+    {
+        let hash = self.hasher.hash_one(key);
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| *node)
+    }
+
+    /// Moves the entries of `caches`, indexed by node, to the cache of the
+    /// node owning them, after nodes were added or removed. Caches of nodes
+    /// no longer on the ring are emptied, unless the ring is empty. Entries
+    /// owned by a node without a cache stay where they are.
+    ///
+    /// Moved entries keep their segment, frequency and queue order, see
+    /// [`S3FIFO::merge`]. Returns the keys that didn't fit in their new
+    /// cache, or were evicted from it to make room.
+    pub fn rebalance<V, T>(&self, caches: &mut [S3FIFO<K, V, T>]) -> Vec<K>
+    where
+        K: Eq + Hash,
+        T: BuildHasher,
+    {
+        let caches_len = caches.len();
+        let mut dropped = vec![];
+        for from in 0..caches_len {
+            let moved = caches[from].extract_if(|key, _| {
+                self.route(key)
+                    .is_some_and(|to| to != from && to < caches_len)
+            });
+            let moved = moved.partition(caches_len, |key| self.route(key).unwrap_or(from));
+            for (to, incoming) in moved.into_iter().enumerate() {
+                dropped.extend(caches[to].absorb_extracted(incoming));
+            }
+        }
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hint;
+
+    #[test]
+    fn it_should_only_move_keys_to_an_added_node() {
+        let mut router = Router::new(64);
+        assert_eq!(router.route(&1), None);
+        router.add(0);
+        router.add(1);
+
+        let before: Vec<_> = (0..1000).map(|key| router.route(&key).unwrap()).collect();
+        assert!(router.add(2));
+        assert!(!router.add(2));
+        let after: Vec<_> = (0..1000).map(|key| router.route(&key).unwrap()).collect();
+
+        let moved = before.iter().zip(&after).filter(|(b, a)| b != a).count();
+        assert!(before.iter().zip(&after).all(|(b, a)| b == a || *a == 2));
+        assert!(moved > 200 && moved < 500);
+
+        assert!(router.remove(2));
+        assert_eq!(
+            (0..1000)
+                .map(|key| router.route(&key).unwrap())
+                .collect::<Vec<_>>(),
+            before
+        );
+    }
+
+    #[test]
+    fn it_should_rebalance_caches() {
+        let mut router = Router::new(16);
+        router.add(0);
+        let mut caches = vec![S3FIFO::new(100), S3FIFO::new(100)];
+        for key in 0..20 {
+            caches[0].put_with_hint(&key, key, 1, Hint::Hot).unwrap();
+        }
+
+        router.add(1);
+        let dropped = router.rebalance(&mut caches);
+
+        assert_eq!(dropped, vec![]);
+        for key in 0..20 {
+            let node = router.route(&key).unwrap();
+            assert_eq!(caches[node].get(&key), Some(&key));
+            assert_eq!(caches[1 - node].get(&key), None);
+        }
+    }
+    #[test]
+    fn it_should_rebalance_caches_with_custom_ratios() {
+        let mut router = Router::new(16);
+        router.add(0);
+        let mut caches = vec![
+            S3FIFO::builder(40).ratios(50, 50).build(),
+            S3FIFO::builder(40).ratios(50, 50).build(),
+        ];
+        for key in 0..20 {
+            caches[0].put(&key, key, 1).unwrap();
+        }
+        let before = caches[0].len();
+
+        router.add(1);
+        let dropped = router.rebalance(&mut caches);
+
+        assert_eq!(before, caches[0].len() + caches[1].len() + dropped.len());
+        for key in (0..20).filter(|key| !dropped.contains(key)) {
+            let node = router.route(&key).unwrap();
+            assert_eq!(caches[node].get(&key), Some(&key));
+            assert_eq!(caches[1 - node].get(&key), None);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::CloneKey;
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+/// Hit ratios of the cache and of the shadow LRU, measured on the same
+/// sample of keys. See [`crate::S3FIFOBuilder::shadow_lru`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShadowStats {
+    /// Lookups of sampled keys.
+    pub lookups: u64,
+    /// Sampled lookups the cache served.
+    pub hits: u64,
+    /// Sampled lookups the shadow LRU would have served.
+    pub lru_hits: u64,
+}
+
+impl ShadowStats {
+    #[must_use]
+    pub fn hit_ratio(&self) -> f64 {
+        ratio(self.hits, self.lookups)
+    }
+
+    #[must_use]
+    pub fn lru_hit_ratio(&self) -> f64 {
+        ratio(self.lru_hits, self.lookups)
+    }
+
+    /// How much higher the hit ratio of the cache is than the one of an LRU
+    /// of the same size. Negative when LRU would do better.
+    #[must_use]
+    pub fn hit_ratio_delta(&self) -> f64 {
+        self.hit_ratio() - self.lru_hit_ratio()
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+/// Metadata-only LRU over a sample of the keys, holding the same share of
+/// the capacity as the share of keys it samples.
+#[derive(Debug)]
+pub struct ShadowLru<K, S> {
+    entries: HashMap<K, (usize, u64), S>,
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+    used_capacity: usize,
+    capacity: usize,
+    one_in: u64,
+    stats: ShadowStats,
+    clone_key: CloneKey<K>,
+}
+
+impl<K, S> ShadowLru<K, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Samples one key in `one_in`, with `capacity` being the capacity of
+    /// the whole cache.
+    pub fn with_hasher(capacity: usize, one_in: u64, hasher: S, clone_key: CloneKey<K>) -> Self {
+        let one_in = one_in.max(1);
+        ShadowLru {
+            entries: HashMap::with_hasher(hasher),
+            recency: BTreeMap::new(),
+            tick: 0,
+            used_capacity: 0,
+            capacity: (capacity as u64 / one_in) as usize,
+            one_in,
+            stats: ShadowStats::default(),
+            clone_key,
+        }
+    }
+
+    fn sampled(&self, key: &K) -> bool {
+        self.entries
+            .hasher()
+            .hash_one(key)
+            .is_multiple_of(self.one_in)
+    }
+
+    pub fn record_get(&mut self, key: &K, hit: bool) {
+        if !self.sampled(key) {
+            return;
+        }
+
+        self.stats.lookups += 1;
+        if hit {
+            self.stats.hits += 1;
+        }
+        if let Some(weight) = self.entries.get(key).map(|(weight, _)| *weight) {
+            self.stats.lru_hits += 1;
+            self.touch(key, weight);
+        }
+    }
+
+    pub fn record_put(&mut self, key: &K, weight: usize) {
+        if !self.sampled(key) || weight > self.capacity {
+            return;
+        }
+
+        self.remove(key);
+        self.used_capacity += weight;
+        self.touch(key, weight);
+        while self.used_capacity > self.capacity {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            let (weight, _) = self.entries.remove(&oldest).unwrap();
+            self.used_capacity -= weight;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some((weight, tick)) = self.entries.remove(key) {
+            self.recency.remove(&tick);
+            self.used_capacity -= weight;
+        }
+    }
+
+    fn touch(&mut self, key: &K, weight: usize) {
+        self.tick += 1;
+        if let Some((_, tick)) = self
+            .entries
+            .insert((self.clone_key)(key), (weight, self.tick))
+        {
+            self.recency.remove(&tick);
+        }
+        self.recency.insert(self.tick, (self.clone_key)(key));
+    }
+
+    pub fn stats(&self) -> ShadowStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultState;
+
+    #[test]
+    fn it_should_count_lru_hits() {
+        let mut shadow = ShadowLru::with_hasher(2, 1, DefaultState::default(), Clone::clone);
+        shadow.record_put(&1, 1);
+        shadow.record_put(&2, 1);
+        shadow.record_get(&1, true);
+        shadow.record_put(&3, 1);
+
+        shadow.record_get(&1, false);
+        shadow.record_get(&2, true);
+
+        assert_eq!(
+            shadow.stats(),
+            ShadowStats {
+                lookups: 3,
+                hits: 2,
+                lru_hits: 2,
+            }
+        );
+        assert!((shadow.stats().hit_ratio_delta()).abs() < f64::EPSILON);
+    }
+}
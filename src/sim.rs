@@ -0,0 +1,160 @@
+//! Trace replay for evaluating the cache against recorded and synthetic
+//! workloads.
+
+mod trace;
+mod workload;
+
+pub use trace::{CsvReader, OracleGeneralReader, TraceFormat, TwitterReader};
+pub use workload::{HotspotWorkload, ScanWorkload, ZipfWorkload};
+
+use crate::S3FIFO;
+
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Get,
+    Set,
+    Delete,
+}
+
+/// A single request of a trace. Keys that are not numeric in the trace are
+/// hashed to `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    pub key: u64,
+    pub size: usize,
+    pub op: Op,
+}
+
+impl Request {
+    #[must_use]
+    pub fn get(key: u64, size: usize) -> Self {
+        Request {
+            key,
+            size,
+            op: Op::Get,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub requests: u64,
+    pub hits: u64,
+    pub bytes_requested: u64,
+    pub bytes_hit: u64,
+}
+
+impl ReplayStats {
+    #[must_use]
+    pub fn misses(&self) -> u64 {
+        self.requests - self.hits
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_ratio(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.requests as f64
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn byte_hit_ratio(&self) -> f64 {
+        if self.bytes_requested == 0 {
+            return 0.0;
+        }
+        self.bytes_hit as f64 / self.bytes_requested as f64
+    }
+}
+
+/// Runs `requests` through `cache` the way a look-aside cache would: a `Get`
+/// miss inserts the object, `Set` inserts or updates it and `Delete` removes
+/// it. Only `Get` requests count towards the hit ratios. Objects are weighed
+/// by their size, with empty objects weighing 1.
+///
+/// # Errors
+///
+/// Returns the first error of the trace reader.
+pub fn replay<I>(cache: &mut S3FIFO<u64, ()>, requests: I) -> io::Result<ReplayStats>
+where
+    I: IntoIterator<Item = io::Result<Request>>,
+{
+    let mut stats = ReplayStats::default();
+    for request in requests {
+        let request = request?;
+        let weight = request.size.max(1);
+        match request.op {
+            Op::Get => {
+                stats.requests += 1;
+                stats.bytes_requested += request.size as u64;
+                if cache.get(&request.key).is_some() {
+                    stats.hits += 1;
+                    stats.bytes_hit += request.size as u64;
+                } else {
+                    let _ = cache.put(&request.key, (), weight);
+                }
+            }
+            Op::Set => {
+                let _ = cache.put(&request.key, (), weight);
+            }
+            Op::Delete => cache.remove(&request.key),
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_replay() {
+        let mut cache = S3FIFO::new(100);
+        let requests = [
+            Request::get(1, 10),
+            Request::get(1, 10),
+            Request {
+                key: 2,
+                size: 5,
+                op: Op::Set,
+            },
+            Request::get(2, 5),
+            Request {
+                key: 2,
+                size: 5,
+                op: Op::Delete,
+            },
+            Request::get(2, 5),
+        ];
+
+        let stats = replay(&mut cache, requests.into_iter().map(Ok)).unwrap();
+
+        assert_eq!(
+            stats,
+            ReplayStats {
+                requests: 4,
+                hits: 2,
+                bytes_requested: 30,
+                bytes_hit: 15,
+            }
+        );
+        assert_eq!(stats.misses(), 2);
+        assert!((stats.hit_ratio() - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn it_should_stop_on_reader_error() {
+        let mut cache = S3FIFO::new(100);
+        let requests = vec![
+            Ok(Request::get(1, 1)),
+            Err(io::Error::new(io::ErrorKind::InvalidData, "broken")),
+        ];
+
+        assert!(replay(&mut cache, requests).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+use super::{Op, Request};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::FromStr;
+
+/// Trace formats understood by the readers of this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// libCacheSim's binary `oracleGeneral` format.
+    OracleGeneral,
+    /// Twitter's production cache cluster traces.
+    Twitter,
+    /// One `key[,size]` request per line.
+    Csv,
+}
+
+impl TraceFormat {
+    /// Opens a reader for this format.
+    pub fn reader<R>(self, reader: R) -> Box<dyn Iterator<Item = io::Result<Request>>>
+    where
+        R: Read + 'static,
+    {
+        match self {
+            TraceFormat::OracleGeneral => {
+                Box::new(OracleGeneralReader::new(BufReader::new(reader)))
+            }
+            TraceFormat::Twitter => Box::new(TwitterReader::new(BufReader::new(reader))),
+            TraceFormat::Csv => Box::new(CsvReader::new(BufReader::new(reader))),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oracleGeneral" | "oracle-general" => Ok(TraceFormat::OracleGeneral),
+            "twitter" => Ok(TraceFormat::Twitter),
+            "csv" => Ok(TraceFormat::Csv),
+            _ => Err(format!("unknown trace format {s:?}")),
+        }
+    }
+}
+
+/// Reads libCacheSim `oracleGeneral` traces: little-endian records of
+/// `u32` timestamp, `u64` object id, `u32` object size and `i64` next access
+/// time. Every record is a `Get`.
+#[derive(Debug)]
+pub struct OracleGeneralReader<R> {
+    reader: R,
+}
+
+const ORACLE_GENERAL_RECORD: usize = 24;
+
+impl<R: Read> OracleGeneralReader<R> {
+    pub fn new(reader: R) -> Self {
+        OracleGeneralReader { reader }
+    }
+}
+
+impl<R: Read> Iterator for OracleGeneralReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = [0; ORACLE_GENERAL_RECORD];
+        let mut read = 0;
+        while read < ORACLE_GENERAL_RECORD {
+            match self.reader.read(&mut record[read..]) {
+                Ok(0) if read == 0 => return None,
+                Ok(0) => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "truncated oracleGeneral record",
+                    )))
+                }
+                Ok(n) => read += n,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Some(Err(error)),
+            }
+        }
+
+        let key = u64::from_le_bytes(record[4..12].try_into().unwrap());
+        let size = u32::from_le_bytes(record[12..16].try_into().unwrap());
+        Some(Ok(Request::get(key, size as usize)))
+    }
+}
+
+/// Reads Twitter cache cluster traces, lines of
+/// `timestamp,key,key size,value size,client id,operation,TTL`.
+///
+/// Keys are hashed, and a request weighs its key size plus its value size.
+/// `get`/`gets` are `Get`, `delete` is `Delete`, and every other operation
+/// writes the key and becomes a `Set`.
+#[derive(Debug)]
+pub struct TwitterReader<R> {
+    lines: io::Lines<R>,
+}
+
+impl<R: BufRead> TwitterReader<R> {
+    pub fn new(reader: R) -> Self {
+        TwitterReader {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TwitterReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(parse_twitter(&line));
+        }
+    }
+}
+
+fn parse_twitter(line: &str) -> io::Result<Request> {
+    // Anonymized keys may contain commas, so the key is whatever sits between
+    // the timestamp and the last five fields.
+    let (_timestamp, rest) = line
+        .split_once(',')
+        .ok_or_else(|| invalid_line("twitter", line))?;
+    let mut fields = rest.rsplitn(6, ',');
+    let _ttl = fields.next();
+    let op = fields.next().ok_or_else(|| invalid_line("twitter", line))?;
+    let _client = fields.next();
+    let value_size = parse_size(fields.next(), "twitter", line)?;
+    let key_size = parse_size(fields.next(), "twitter", line)?;
+    let key = fields.next().ok_or_else(|| invalid_line("twitter", line))?;
+
+    let op = match op {
+        "get" | "gets" => Op::Get,
+        "delete" => Op::Delete,
+        _ => Op::Set,
+    };
+
+    Ok(Request {
+        key: hash_key(key),
+        size: key_size + value_size,
+        op,
+    })
+}
+
+/// Reads plain CSV traces with one `key[,size]` `Get` per line. Numeric keys
+/// are used as is and other keys are hashed. The size defaults to 1. Empty
+/// lines, lines starting with `#` and a leading `key,size` header are skipped.
+#[derive(Debug)]
+pub struct CsvReader<R> {
+    lines: io::Lines<R>,
+    first: bool,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    pub fn new(reader: R) -> Self {
+        CsvReader {
+            lines: reader.lines(),
+            first: true,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
+            let first = std::mem::replace(&mut self.first, false);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (first && line == "key,size") {
+                continue;
+            }
+            return Some(parse_csv(line));
+        }
+    }
+}
+
+fn parse_csv(line: &str) -> io::Result<Request> {
+    let (key, size) = match line.split_once(',') {
+        Some((key, size)) => (key.trim(), parse_size(Some(size), "csv", line)?),
+        None => (line, 1),
+    };
+    let key = key.parse().unwrap_or_else(|_| hash_key(key));
+
+    Ok(Request::get(key, size))
+}
+
+fn parse_size(field: Option<&str>, format: &str, line: &str) -> io::Result<usize> {
+    field
+        .and_then(|field| field.trim().parse().ok())
+        .ok_or_else(|| invalid_line(format, line))
+}
+
+fn invalid_line(format: &str, line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid {format} trace line {line:?}"),
+    )
+}
+
+fn hash_key(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oracle_general_record(key: u64, size: u32) -> Vec<u8> {
+        let mut record = vec![];
+        record.extend(7u32.to_le_bytes());
+        record.extend(key.to_le_bytes());
+        record.extend(size.to_le_bytes());
+        record.extend((-1i64).to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn it_reads_oracle_general() {
+        let mut trace = oracle_general_record(42, 100);
+        trace.extend(oracle_general_record(43, 7));
+
+        let requests = OracleGeneralReader::new(trace.as_slice())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(requests, vec![Request::get(42, 100), Request::get(43, 7)]);
+    }
+
+    #[test]
+    fn it_rejects_truncated_oracle_general() {
+        let trace = oracle_general_record(42, 100);
+
+        let mut reader = OracleGeneralReader::new(&trace[..20]);
+
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn it_reads_twitter() {
+        let trace =
+            "0,q:q:1:8WTf,14,19,3,get,0\n\n1,a,b,4,10,3,set,3600\n2,q:q:1:8WTf,14,0,3,delete,0\n";
+
+        let requests = TwitterReader::new(trace.as_bytes())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(
+            requests,
+            vec![
+                Request::get(hash_key("q:q:1:8WTf"), 33),
+                Request {
+                    key: hash_key("a,b"),
+                    size: 14,
+                    op: Op::Set,
+                },
+                Request {
+                    key: hash_key("q:q:1:8WTf"),
+                    size: 14,
+                    op: Op::Delete,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reads_csv() {
+        let trace = "key,size\n1,10\n# comment\nuser:1\n2, 5\n";
+
+        let requests = TraceFormat::Csv
+            .reader(trace.as_bytes())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(
+            requests,
+            vec![
+                Request::get(1, 10),
+                Request::get(hash_key("user:1"), 1),
+                Request::get(2, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_csv() {
+        let mut reader = CsvReader::new("1,big\n".as_bytes());
+
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn it_parses_format_names() {
+        assert_eq!("oracleGeneral".parse(), Ok(TraceFormat::OracleGeneral));
+        assert_eq!("twitter".parse(), Ok(TraceFormat::Twitter));
+        assert!("parquet".parse::<TraceFormat>().is_err());
+    }
+}
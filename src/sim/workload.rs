@@ -0,0 +1,307 @@
+use super::Request;
+
+/// Xorshift64 generator, good enough to shape synthetic workloads and
+/// reproducible from its seed.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero.
+        Rng(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in `0..n`, `n` must not be zero.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Uniform in `[0, 1)`.
+    #[allow(clippy::cast_precision_loss)]
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Endless `Get` workload interleaving uniform lookups of a hot set with
+/// one-pass scans of keys that are never requested again, to check that
+/// scans don't flush the hot set.
+///
+/// ```
+/// use kesh::sim::{replay, ScanWorkload};
+///
+/// let mut cache = kesh::S3FIFO::new(1_000);
+/// let workload = ScanWorkload::new(500, 2_000).take(100_000);
+///
+/// let stats = replay(&mut cache, workload.map(Ok)).unwrap();
+/// assert!(stats.hit_ratio() > 0.3);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScanWorkload {
+    rng: Rng,
+    hot_keys: u64,
+    scan_len: u64,
+    hot_requests: u64,
+    size: usize,
+    /// Requests left in the current hot phase or scan.
+    remaining: u64,
+    scanning: bool,
+    next_scan_key: u64,
+}
+
+impl ScanWorkload {
+    /// Hot keys are `0..hot_keys`, and every scan requests `scan_len` new
+    /// keys. Hot phases last `4 * hot_keys` requests unless set with
+    /// [`ScanWorkload::hot_requests`].
+    #[must_use]
+    pub fn new(hot_keys: u64, scan_len: u64) -> Self {
+        ScanWorkload {
+            rng: Rng::new(DEFAULT_SEED),
+            hot_keys: hot_keys.max(1),
+            scan_len,
+            hot_requests: hot_keys * 4,
+            size: 1,
+            remaining: hot_keys * 4,
+            scanning: false,
+            next_scan_key: hot_keys,
+        }
+    }
+
+    /// Number of hot set lookups between two scans.
+    #[must_use]
+    pub fn hot_requests(mut self, hot_requests: u64) -> Self {
+        self.hot_requests = hot_requests;
+        self.remaining = hot_requests;
+        self
+    }
+
+    /// Size of every requested object, 1 by default.
+    #[must_use]
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+}
+
+impl Iterator for ScanWorkload {
+    type Item = Request;
+
+    fn next(&mut self) -> Option<Request> {
+        while self.remaining == 0 {
+            self.scanning = !self.scanning;
+            self.remaining = if self.scanning {
+                self.scan_len
+            } else {
+                self.hot_requests
+            };
+            if self.scan_len == 0 && self.hot_requests == 0 {
+                return None;
+            }
+        }
+        self.remaining -= 1;
+
+        let key = if self.scanning {
+            self.next_scan_key += 1;
+            self.next_scan_key - 1
+        } else {
+            self.rng.below(self.hot_keys)
+        };
+        Some(Request::get(key, self.size))
+    }
+}
+
+/// Endless `Get` workload over keys `0..keys` whose popularity follows a
+/// Zipf distribution: key `k` is requested in proportion to
+/// `1 / (k + 1)^exponent`. Exponents around 0.6 to 1.0 match most web and
+/// key-value cache traces.
+///
+/// Sampling is a binary search over a precomputed table of `keys` floats.
+#[derive(Debug, Clone)]
+pub struct ZipfWorkload {
+    rng: Rng,
+    cdf: Vec<f64>,
+    size: usize,
+}
+
+impl ZipfWorkload {
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(keys: u64, exponent: f64) -> Self {
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (0..keys.max(1))
+            .map(|key| {
+                total += 1.0 / ((key + 1) as f64).powf(exponent);
+                total
+            })
+            .collect();
+        for probability in &mut cdf {
+            *probability /= total;
+        }
+
+        ZipfWorkload {
+            rng: Rng::new(DEFAULT_SEED),
+            cdf,
+            size: 1,
+        }
+    }
+
+    /// Size of every requested object, 1 by default.
+    #[must_use]
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+}
+
+impl Iterator for ZipfWorkload {
+    type Item = Request;
+
+    fn next(&mut self) -> Option<Request> {
+        let unit = self.rng.unit();
+        let key = self
+            .cdf
+            .partition_point(|probability| *probability <= unit)
+            .min(self.cdf.len() - 1);
+        Some(Request::get(key as u64, self.size))
+    }
+}
+
+/// Endless `Get` workload where a hot fraction of the keys `0..keys`
+/// receives a fixed share of the requests, uniformly, and the other keys
+/// share the rest. `HotspotWorkload::new(keys, 0.2, 0.8)` is the classic
+/// 80/20 workload.
+#[derive(Debug, Clone)]
+pub struct HotspotWorkload {
+    rng: Rng,
+    keys: u64,
+    hot_keys: u64,
+    hot_probability: f64,
+    size: usize,
+}
+
+impl HotspotWorkload {
+    /// The first `hot_fraction` of the keys receive `hot_probability` of the
+    /// requests. Both are clamped to `[0, 1]`.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn new(keys: u64, hot_fraction: f64, hot_probability: f64) -> Self {
+        let keys = keys.max(1);
+        let hot_keys = (keys as f64 * hot_fraction.clamp(0.0, 1.0)).round() as u64;
+        HotspotWorkload {
+            rng: Rng::new(DEFAULT_SEED),
+            keys,
+            hot_keys: hot_keys.clamp(1, keys),
+            hot_probability: hot_probability.clamp(0.0, 1.0),
+            size: 1,
+        }
+    }
+
+    /// Size of every requested object, 1 by default.
+    #[must_use]
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+}
+
+impl Iterator for HotspotWorkload {
+    type Item = Request;
+
+    fn next(&mut self) -> Option<Request> {
+        let cold_keys = self.keys - self.hot_keys;
+        let key = if cold_keys == 0 || self.rng.unit() < self.hot_probability {
+            self.rng.below(self.hot_keys)
+        } else {
+            self.hot_keys + self.rng.below(cold_keys)
+        };
+        Some(Request::get(key, self.size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sim::replay;
+    use crate::S3FIFO;
+
+    #[test]
+    fn it_should_alternate_hot_phases_and_scans() {
+        let requests: Vec<_> = ScanWorkload::new(2, 3)
+            .hot_requests(2)
+            .take(7)
+            .map(|request| request.key)
+            .collect();
+
+        assert!(requests[..2].iter().all(|key| *key < 2));
+        assert_eq!(requests[2..5], [2, 3, 4]);
+        assert!(requests[5..].iter().all(|key| *key < 2));
+    }
+
+    #[test]
+    fn it_should_keep_the_hot_set_through_scans() {
+        let mut cache = S3FIFO::new(1_000);
+        let workload = ScanWorkload::new(500, 5_000)
+            .hot_requests(5_000)
+            .take(200_000);
+
+        let stats = replay(&mut cache, workload.map(Ok)).unwrap();
+
+        // Half of the requests go to the hot set, which always hits once warm.
+        assert!(stats.hit_ratio() > 0.45, "{stats:?}");
+    }
+
+    #[test]
+    fn it_should_favor_low_zipf_keys() {
+        let keys: Vec<_> = ZipfWorkload::new(1_000, 1.0)
+            .take(10_000)
+            .map(|request| request.key)
+            .collect();
+
+        let first = keys.iter().filter(|key| **key == 0).count();
+        let tenth = keys.iter().filter(|key| **key == 9).count();
+        assert!(keys.iter().all(|key| *key < 1_000));
+        assert!(first > 5 * tenth, "{first} {tenth}");
+    }
+
+    #[test]
+    fn it_should_send_most_requests_to_the_hotspot() {
+        let hot = HotspotWorkload::new(1_000, 0.2, 0.8)
+            .take(10_000)
+            .filter(|request| request.key < 200)
+            .count();
+
+        assert!((7_500..8_500).contains(&hot), "{hot}");
+    }
+}
@@ -0,0 +1,254 @@
+use crate::hash::BuildIdentityHasher;
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+
+/// Address of an entry in a [`Slab`]. A handle outlives its entry: once the
+/// entry is removed, the handle stops resolving, even after the slot is
+/// given to another entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle {
+    index: usize,
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    generation: u64,
+    entry: Option<T>,
+}
+
+/// Arena of entries, reusing the slots of removed entries.
+///
+/// The queues keep their order as handles into a slab, so moving an entry
+/// within or out of a queue doesn't hash its key, and a removed entry only
+/// leaves a stale handle behind.
+#[derive(Debug)]
+pub struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    vacant: Vec<usize>,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Slab {
+            slots: Vec::new(),
+            vacant: Vec::new(),
+        }
+    }
+}
+
+impl<T> Slab<T> {
+    pub fn insert(&mut self, entry: T) -> Handle {
+        if let Some(index) = self.vacant.pop() {
+            let slot = &mut self.slots[index];
+            slot.entry = Some(entry);
+            return Handle {
+                index,
+                generation: slot.generation,
+            };
+        }
+
+        self.slots.push(Slot {
+            generation: 0,
+            entry: Some(entry),
+        });
+        Handle {
+            index: self.slots.len() - 1,
+            generation: 0,
+        }
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        self.slots
+            .get(handle.index)
+            .filter(|slot| slot.generation == handle.generation)?
+            .entry
+            .as_ref()
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index)
+            .filter(|slot| slot.generation == handle.generation)?
+            .entry
+            .as_mut()
+    }
+
+    /// Removes the entry of `handle`, if it is still there, and frees its
+    /// slot.
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(handle.index)
+            .filter(|slot| slot.generation == handle.generation)?;
+        let entry = slot.entry.take()?;
+        slot.generation += 1;
+        self.vacant.push(handle.index);
+        Some(entry)
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.vacant.clear();
+    }
+}
+
+/// Entry of a [`Table`], chained to the next entry whose key has the same
+/// hash.
+#[derive(Debug)]
+struct Entry<K, T> {
+    key: K,
+    value: T,
+    hash: u64,
+    next: Option<Handle>,
+}
+
+/// Slab of keyed entries, storing every key once.
+///
+/// The index maps the hash of a key to the handle of its entry, and entries
+/// whose keys share a hash are chained from there, so a lookup compares the
+/// key with the one in the slab instead of a copy kept in a map.
+#[derive(Debug)]
+pub struct Table<K, T, S> {
+    index: HashMap<u64, Handle, BuildIdentityHasher>,
+    entries: Slab<Entry<K, T>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K, T, S> Table<K, T, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        Table {
+            index: HashMap::default(),
+            entries: Slab::default(),
+            len: 0,
+            hasher,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Handle of the entry of `key`.
+    pub fn find(&self, key: &K) -> Option<Handle> {
+        let mut next = self.index.get(&self.hasher.hash_one(key)).copied();
+        while let Some(handle) = next {
+            let entry = self.entries.get(handle)?;
+            if entry.key == *key {
+                return Some(handle);
+            }
+            next = entry.next;
+        }
+        None
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<(&K, &T)> {
+        self.entries
+            .get(handle)
+            .map(|entry| (&entry.key, &entry.value))
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        self.entries.get_mut(handle).map(|entry| &mut entry.value)
+    }
+
+    /// Inserts an entry for `key`, which must not have one yet.
+    pub fn insert(&mut self, key: K, value: T) -> Handle {
+        debug_assert!(self.find(&key).is_none());
+        let hash = self.hasher.hash_one(&key);
+        let next = self.index.get(&hash).copied();
+        let handle = self.entries.insert(Entry {
+            key,
+            value,
+            hash,
+            next,
+        });
+        self.index.insert(hash, handle);
+        self.len += 1;
+        handle
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<(K, T)> {
+        let entry = self.entries.remove(handle)?;
+        let head = self.index[&entry.hash];
+        if head == handle {
+            match entry.next {
+                Some(next) => self.index.insert(entry.hash, next),
+                None => self.index.remove(&entry.hash),
+            };
+        } else {
+            let mut previous = head;
+            while let Some(chained) = self.entries.get_mut(previous) {
+                if chained.next == Some(handle) {
+                    chained.next = entry.next;
+                    break;
+                }
+                previous = chained.next.unwrap();
+            }
+        }
+        self.len -= 1;
+        Some((entry.key, entry.value))
+    }
+
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.entries.clear();
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    #[test]
+    fn it_should_not_resolve_handles_of_removed_entries() {
+        let mut slab = Slab::default();
+        let first = slab.insert("first");
+        assert_eq!(slab.remove(first), Some("first"));
+
+        let second = slab.insert("second");
+        assert_eq!(second.index, first.index);
+        assert_eq!(slab.get(first), None);
+        assert_eq!(slab.remove(first), None);
+        assert_eq!(slab.get(second), Some(&"second"));
+        *slab.get_mut(second).unwrap() = "updated";
+        assert_eq!(slab.remove(second), Some("updated"));
+    }
+
+    #[test]
+    fn it_should_chain_keys_sharing_a_hash() {
+        #[derive(Default)]
+        struct Colliding;
+        impl Hasher for Colliding {
+            fn finish(&self) -> u64 {
+                0
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut table = Table::with_hasher(BuildHasherDefault::<Colliding>::default());
+        let handles: Vec<_> = (0..3).map(|key| table.insert(key, key * 10)).collect();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.find(&2), Some(handles[2]));
+
+        assert_eq!(table.remove(handles[1]), Some((1, 10)));
+        assert_eq!(table.find(&1), None);
+        assert_eq!(table.get(handles[0]), Some((&0, &0)));
+        assert_eq!(table.remove(handles[2]), Some((2, 20)));
+        assert_eq!(table.find(&0), Some(handles[0]));
+        assert_eq!(table.len(), 1);
+    }
+}
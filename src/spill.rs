@@ -0,0 +1,297 @@
+use crate::{DefaultState, RemovalCause, S3FIFOError, S3FIFO};
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+type Spilled<K, V> = Arc<Mutex<Vec<(K, V, usize)>>>;
+
+/// Lookups and movements of the entries of a [`SpillCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpillStats {
+    pub primary_hits: u64,
+    pub secondary_hits: u64,
+    pub misses: u64,
+    /// Entries evicted from the primary cache and put into the secondary one.
+    pub spilled: u64,
+    /// Secondary hits moved back to the primary cache.
+    pub promoted: u64,
+}
+
+impl SpillStats {
+    /// Share of the lookups served by either cache.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_ratio(&self) -> f64 {
+        let hits = self.primary_hits + self.secondary_hits;
+        let lookups = hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        hits as f64 / lookups as f64
+    }
+}
+
+/// Two [`S3FIFO`] caches forming an in-process hierarchy, for example a
+/// small cache of hot values in front of a larger one of compressed values.
+///
+/// Entries evicted from the primary cache, for size or rotation, are offered
+/// to the secondary cache, and secondary hits move back to the primary one.
+/// An entry lives in at most one of the caches, and keeps its time to live
+/// when it moves.
+pub struct SpillCache<K, V, S = DefaultState> {
+    primary: S3FIFO<K, V, S>,
+    secondary: S3FIFO<K, V, S>,
+    spilled: Spilled<K, V>,
+    /// Keys dropped while promoting secondary hits, reported by the next put.
+    dropped: Vec<K>,
+    stats: SpillStats,
+}
+
+impl<K, V, S> SpillCache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Spills the victims of `primary` into `secondary`. The victims stay in
+    /// the hierarchy, so the owning eviction listener of `primary` is only
+    /// handed the entries leaving it otherwise. Its listener by reference
+    /// keeps seeing every entry.
+    #[must_use]
+    pub fn new(mut primary: S3FIFO<K, V, S>, secondary: S3FIFO<K, V, S>) -> Self {
+        let spilled: Spilled<K, V> = Arc::default();
+        primary.keep_evicted_deadlines();
+        let mut listener = primary.owning_listener.take();
+        let sink = Arc::clone(&spilled);
+        primary.owning_listener = Some(Box::new(move |key, value, weight, cause| {
+            if matches!(cause, RemovalCause::Size | RemovalCause::Rotated) {
+                let mut spilled = sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                spilled.push((key.clone(), value, weight));
+            } else if let Some(listener) = &mut listener {
+                listener(key, value, weight, cause);
+            }
+        }));
+
+        SpillCache {
+            primary,
+            secondary,
+            spilled,
+            dropped: vec![],
+            stats: SpillStats::default(),
+        }
+    }
+
+    /// Looks `key` up in the primary cache, then in the secondary one. A
+    /// secondary hit moves to the primary cache, unless it's too heavy for it.
+    /// Expired entries are missed in both caches.
+    ///
+    /// The primary victims of a move spill as usual, and the keys they push
+    /// out of the hierarchy are reported by the next [`SpillCache::put`].
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        if self.primary.contains_key(key) {
+            self.stats.primary_hits += 1;
+            return self.primary.get(key);
+        }
+        // Counts the miss in the primary cache.
+        let _ = self.primary.get(key);
+
+        let weight = self.secondary.peek_entry(key).map(|(_, weight)| weight);
+        let Some(weight) = weight.filter(|_| self.secondary.contains_key(key)) else {
+            self.stats.misses += 1;
+            let _ = self.secondary.get(key);
+            return None;
+        };
+
+        self.stats.secondary_hits += 1;
+        if !self.primary.admits(key, weight) {
+            return self.secondary.get(key);
+        }
+        // Moves the entry without telling the secondary listeners, it stays
+        // in the hierarchy.
+        let Some((value, weight, deadline)) = self.secondary.take(key) else {
+            return self.secondary.get(key);
+        };
+        let _ = self.primary.put_with_deadline(key, value, weight, deadline);
+        self.stats.promoted += 1;
+        let dropped = self.spill();
+        self.dropped.extend(dropped);
+        self.primary.peek_entry(key).map(|(value, _)| value)
+    }
+
+    /// Puts an entry into the primary cache, spilling its victims. Returns
+    /// the keys that left the hierarchy since the last successful put:
+    /// secondary victims and spilled entries too heavy for the secondary
+    /// cache, those of this put last.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry doesn't fit in the
+    /// primary cache.
+    pub fn put(
+        &mut self,
+        key: &K,
+        value: V,
+        weight: usize,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>> {
+        self.primary.put(key, value, weight)?;
+        self.secondary.remove(key);
+        let mut dropped = mem::take(&mut self.dropped);
+        dropped.extend(self.spill());
+        Ok((!dropped.is_empty()).then_some(dropped))
+    }
+
+    /// Removes `key` from both caches.
+    pub fn remove(&mut self, key: &K) {
+        self.primary.remove(key);
+        self.secondary.remove(key);
+    }
+
+    fn spill(&mut self) -> Vec<K> {
+        let spilled = {
+            let mut spilled = self
+                .spilled
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            mem::take(&mut *spilled)
+        };
+        let mut deadlines: HashMap<_, _> =
+            self.primary.take_evicted_deadlines().into_iter().collect();
+
+        let mut dropped = vec![];
+        for (key, value, weight) in spilled {
+            self.stats.spilled += 1;
+            let deadline = deadlines.remove(&key);
+            match self
+                .secondary
+                .put_with_deadline(&key, value, weight, deadline)
+            {
+                Ok(evicted) => dropped.extend(evicted.into_iter().flatten()),
+                Err(_) => dropped.push(key),
+            }
+        }
+
+        dropped
+    }
+
+    pub fn stats(&self) -> SpillStats {
+        self.stats
+    }
+
+    pub fn primary(&self) -> &S3FIFO<K, V, S> {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S3FIFO<K, V, S> {
+        &self.secondary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hint;
+
+    use std::time::Duration;
+
+    #[test]
+    fn it_should_spill_victims_and_promote_secondary_hits() {
+        let mut cache = SpillCache::new(S3FIFO::new(10), S3FIFO::new(100));
+        cache.put(&1, 1, 1).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+
+        assert!(cache.secondary().contains_live(&1));
+        assert_eq!(cache.get(&1), Some(&1));
+        assert!(cache.primary().contains_live(&1));
+        assert!(!cache.secondary().contains_live(&1));
+        assert_eq!(cache.get(&3), None);
+
+        assert_eq!(
+            cache.stats(),
+            SpillStats {
+                primary_hits: 0,
+                secondary_hits: 1,
+                misses: 1,
+                spilled: 1,
+                promoted: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_report_keys_leaving_the_hierarchy() {
+        let mut cache = SpillCache::new(S3FIFO::new(10), S3FIFO::new(10));
+        let mut dropped = vec![];
+        for key in 0..4 {
+            dropped.extend(cache.put(&key, key, 1).unwrap().into_iter().flatten());
+        }
+
+        assert_eq!(dropped, vec![0, 1]);
+        assert!(cache.secondary().contains_live(&2));
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(cache.stats().primary_hits, 0);
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(cache.stats().primary_hits, 1);
+    }
+
+    #[test]
+    fn it_should_report_keys_dropped_by_promotions() {
+        let mut secondary = S3FIFO::new(10);
+        secondary.put_with_hint(&100, 100, 1, Hint::Hot).unwrap();
+        secondary.put(&101, 101, 1).unwrap();
+        let mut cache = SpillCache::new(S3FIFO::new(10), secondary);
+        cache.put(&1, 1, 1).unwrap();
+
+        // Promoting 100 spills 1 into the full secondary small queue.
+        assert_eq!(cache.get(&100), Some(&100));
+        assert!(cache.secondary().contains_live(&1));
+        assert!(!cache.secondary().contains_live(&101));
+
+        assert_eq!(cache.put(&2, 2, 1).unwrap(), Some(vec![101, 1]));
+        assert_eq!(cache.put(&3, 3, 1).unwrap(), Some(vec![100]));
+    }
+
+    #[test]
+    fn it_should_miss_expired_entries_in_both_caches() {
+        let mut primary = S3FIFO::new(10);
+        primary.put_with_ttl(&2, 2, 1, Duration::ZERO).unwrap();
+        let mut secondary = S3FIFO::new(10);
+        secondary.put_with_ttl(&1, 1, 1, Duration::ZERO).unwrap();
+        let mut cache = SpillCache::new(primary, secondary);
+
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), None);
+        assert!(!cache.primary().contains_live(&1));
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn it_should_carry_time_to_live_across_the_caches() {
+        let hour = Duration::from_secs(3600);
+        let mut cache = SpillCache::new(S3FIFO::new(10), S3FIFO::new(100));
+        cache.primary.put_with_ttl(&1, 1, 1, hour).unwrap();
+        cache.put(&2, 2, 1).unwrap();
+
+        assert!(cache.secondary().time_to_live(&1).is_some());
+        assert_eq!(cache.get(&1), Some(&1));
+        assert!(cache.primary().time_to_live(&1).is_some());
+        assert_eq!(cache.secondary().time_to_live(&1), None);
+    }
+
+    #[test]
+    fn it_should_promote_without_telling_the_secondary_listeners() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut secondary = S3FIFO::builder(10)
+            .eviction_listener_ref(move |key: &i32, _: &i32, _, cause| {
+                sender.send((*key, cause)).unwrap();
+            })
+            .build();
+        secondary.put(&1, 1, 1).unwrap();
+        let mut cache = SpillCache::new(S3FIFO::new(10), secondary);
+
+        assert_eq!(cache.get(&1), Some(&1));
+        assert!(!cache.secondary().contains_live(&1));
+        assert_eq!(receiver.try_iter().count(), 0);
+    }
+}
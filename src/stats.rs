@@ -0,0 +1,52 @@
+/// Weight and entries held by one segment of a cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Occupancy {
+    /// Live entries, or keys for the ghost queue.
+    pub len: usize,
+    /// Weight held by the live entries, or keys for the ghost queue.
+    pub weight: usize,
+    pub capacity: usize,
+}
+
+/// Counters and occupancy of a cache, see [`crate::S3FIFO::stats`].
+///
+/// The counters only grow, so they can be exported as they are to
+/// monitoring systems expecting monotonic counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups that found a live entry, pinning included.
+    pub hits: u64,
+    pub misses: u64,
+    /// Successful puts, updates of live entries included.
+    pub insertions: u64,
+    /// Entries that left the cache to make room, or were rotated out.
+    pub evictions: u64,
+    /// Entries removed because their time to live ran out.
+    pub expirations: u64,
+    /// All the small queues together.
+    pub small: Occupancy,
+    pub main: Occupancy,
+    /// `None` without ghost queue.
+    pub ghost: Option<Occupancy>,
+}
+
+impl CacheStats {
+    /// Share of the lookups that were hits, 0 without lookups.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+
+    pub(crate) fn record_get(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
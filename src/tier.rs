@@ -0,0 +1,143 @@
+use crate::{DefaultState, S3FIFOError, S3FIFO};
+
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+
+/// A slower, usually remote, second cache tier, such as Redis or memcached.
+///
+/// Implementations own the connection and its error handling: a failed
+/// lookup is a miss, and a failed write is dropped.
+pub trait RemoteTier<K, V> {
+    /// Returns the value of `key` and its weight in the local cache.
+    fn get(&self, key: &K) -> impl Future<Output = Option<(V, usize)>>;
+
+    fn put(&self, key: &K, value: &V, weight: usize) -> impl Future<Output = ()>;
+
+    fn remove(&self, key: &K) -> impl Future<Output = ()>;
+}
+
+/// An [`S3FIFO`] cache in front of a [`RemoteTier`].
+///
+/// Lookups check the local cache first and fall back to the remote tier,
+/// whose hits are admitted locally. Writes go through to both tiers.
+pub struct TieredCache<K, V, R, S = DefaultState> {
+    local: S3FIFO<K, V, S>,
+    remote: R,
+}
+
+impl<K, V, R, S> TieredCache<K, V, R, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    R: RemoteTier<K, V>,
+    S: BuildHasher,
+{
+    #[must_use]
+    pub fn new(local: S3FIFO<K, V, S>, remote: R) -> Self {
+        TieredCache { local, remote }
+    }
+
+    /// Returns the value of `key` from the local cache, or from the remote
+    /// tier, caching it locally. Remote values too heavy for the local
+    /// cache are returned uncached.
+    pub async fn get(&mut self, key: &K) -> Option<V> {
+        if let Some(value) = self.local.get_cloned(key) {
+            return Some(value);
+        }
+
+        let (value, weight) = self.remote.get(key).await?;
+        let _ = self.local.put(key, value.clone(), weight);
+        Some(value)
+    }
+
+    /// Writes the entry to the remote tier, then to the local cache. Returns
+    /// the keys evicted from the local cache, they stay in the remote tier.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry doesn't fit in the
+    /// local cache. It is written to the remote tier anyway.
+    pub async fn put(
+        &mut self,
+        key: &K,
+        value: V,
+        weight: usize,
+    ) -> Result<Option<Vec<K>>, S3FIFOError<K>> {
+        self.remote.put(key, &value, weight).await;
+        self.local.put(key, value, weight)
+    }
+
+    /// Removes `key` from both tiers.
+    pub async fn remove(&mut self, key: &K) {
+        self.local.remove(key);
+        self.remote.remove(key).await;
+    }
+
+    pub fn local(&mut self) -> &mut S3FIFO<K, V, S> {
+        &mut self.local
+    }
+
+    pub fn remote(&self) -> &R {
+        &self.remote
+    }
+
+    #[must_use]
+    pub fn into_parts(self) -> (S3FIFO<K, V, S>, R) {
+        (self.local, self.remote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::executor::block_on;
+
+    use std::collections::HashMap;
+    use std::future;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Remote {
+        entries: Mutex<HashMap<u32, (u32, usize)>>,
+    }
+
+    impl RemoteTier<u32, u32> for Remote {
+        fn get(&self, key: &u32) -> impl Future<Output = Option<(u32, usize)>> {
+            future::ready(self.entries.lock().unwrap().get(key).copied())
+        }
+
+        fn put(&self, key: &u32, value: &u32, weight: usize) -> impl Future<Output = ()> {
+            self.entries.lock().unwrap().insert(*key, (*value, weight));
+            future::ready(())
+        }
+
+        fn remove(&self, key: &u32) -> impl Future<Output = ()> {
+            self.entries.lock().unwrap().remove(key);
+            future::ready(())
+        }
+    }
+
+    #[test]
+    fn it_should_readmit_remote_hits() {
+        let remote = Remote::default();
+        remote.entries.lock().unwrap().insert(1, (10, 1));
+        let mut cache = TieredCache::new(S3FIFO::new(10), remote);
+
+        assert_eq!(block_on(cache.get(&1)), Some(10));
+        assert_eq!(cache.local().get(&1), Some(&10));
+        assert_eq!(block_on(cache.get(&2)), None);
+    }
+
+    #[test]
+    fn it_should_write_through() {
+        let mut cache = TieredCache::new(S3FIFO::new(10), Remote::default());
+
+        block_on(cache.put(&1, 10, 1)).unwrap();
+        assert_eq!(cache.remote().entries.lock().unwrap()[&1], (10, 1));
+
+        block_on(cache.remove(&1));
+        assert!(cache.remote().entries.lock().unwrap().is_empty());
+        assert_eq!(cache.local().get(&1), None);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::CloneKey;
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+
+/// Version of every live entry, see [`crate::S3FIFOBuilder::versioned`].
+#[derive(Debug)]
+pub struct Versions<K, S> {
+    versions: HashMap<K, u64, S>,
+    last: u64,
+    clone_key: CloneKey<K>,
+}
+
+impl<K, S> Versions<K, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn with_hasher(hasher: S, clone_key: CloneKey<K>) -> Self {
+        Versions {
+            versions: HashMap::with_hasher(hasher),
+            last: 0,
+            clone_key,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<u64> {
+        self.versions.get(key).copied()
+    }
+
+    /// Gives `key` a version above every version given so far, so a key
+    /// removed and put again never gets an old version back.
+    pub fn bump(&mut self, key: &K) -> u64 {
+        self.last += 1;
+        match self.versions.get_mut(key) {
+            Some(version) => *version = self.last,
+            None => {
+                self.versions.insert((self.clone_key)(key), self.last);
+            }
+        }
+        self.last
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.versions.remove(key);
+    }
+}
@@ -0,0 +1,213 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem::size_of;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Values that know their own weight, see [`crate::S3FIFO::put_weighted`].
+///
+/// Byte and string types weigh their logical length, regardless of who owns
+/// the data: a `Cow::Borrowed(&'static str)` weighs the same as the
+/// equivalent `Cow::Owned(String)`, without the cache copying it.
+pub trait Weighted {
+    fn weight(&self) -> usize;
+}
+
+impl Weighted for str {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Weighted for [u8] {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Weighted for String {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Weighted for Vec<u8> {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: Weighted + ?Sized> Weighted for &T {
+    fn weight(&self) -> usize {
+        (**self).weight()
+    }
+}
+
+impl<T: Weighted + ?Sized> Weighted for Box<T> {
+    fn weight(&self) -> usize {
+        (**self).weight()
+    }
+}
+
+impl<T: Weighted + ?Sized> Weighted for Rc<T> {
+    fn weight(&self) -> usize {
+        (**self).weight()
+    }
+}
+
+impl<T: Weighted + ?Sized> Weighted for Arc<T> {
+    fn weight(&self) -> usize {
+        (**self).weight()
+    }
+}
+
+impl<B> Weighted for Cow<'_, B>
+where
+    B: Weighted + ToOwned + ?Sized,
+{
+    fn weight(&self) -> usize {
+        (**self).weight()
+    }
+}
+
+/// Approximate number of bytes a value owns on the heap, see
+/// [`shallow_size`].
+///
+/// Collections count their allocated capacity, not just their length.
+/// Hash maps and sets ignore the bookkeeping of the table itself.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+/// Approximate size of `value` in bytes: `size_of::<V>()` plus the heap
+/// memory it owns. Use it as the weight to bound a cache by bytes, see
+/// [`crate::S3FIFO::put_sized`].
+pub fn shallow_size<V: HeapSize>(value: &V) -> usize {
+    size_of::<V>() + value.heap_size()
+}
+
+macro_rules! impl_heap_size_for_inline {
+    ($($ty:ty),*) => {
+        $(impl HeapSize for $ty {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
+}
+
+impl_heap_size_for_inline!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    &'static str
+);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        shallow_size(&**self)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for VecDeque<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize, S> HeapSize for HashMap<K, V, S> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<(K, V)>()
+            + self
+                .iter()
+                .map(|(key, value)| key.heap_size() + value.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl<T: HeapSize, S> HeapSize for HashSet<T, S> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize, C: HeapSize> HeapSize for (A, B, C) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size() + self.2.heap_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_weighs_logical_length() {
+        let borrowed: Cow<'static, str> = Cow::Borrowed("value");
+        let owned: Cow<'static, str> = Cow::Owned(String::from("value"));
+        let bytes: Cow<'static, [u8]> = Cow::Borrowed(b"bytes!");
+
+        assert_eq!(borrowed.weight(), 5);
+        assert_eq!(owned.weight(), 5);
+        assert_eq!(bytes.weight(), 6);
+        assert_eq!(Arc::<str>::from("shared").weight(), 6);
+    }
+
+    #[test]
+    fn it_measures_shallow_size() {
+        let mut names = Vec::with_capacity(2);
+        names.push(String::with_capacity(10));
+
+        assert_eq!(shallow_size(&7u64), 8);
+        assert_eq!(
+            shallow_size(&String::with_capacity(10)),
+            size_of::<String>() + 10
+        );
+        assert_eq!(
+            shallow_size(&names),
+            size_of::<Vec<String>>() + 2 * size_of::<String>() + 10
+        );
+        assert_eq!(
+            shallow_size(&Some(Box::new(1u32))),
+            size_of::<Option<Box<u32>>>() + 4
+        );
+    }
+}
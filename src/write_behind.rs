@@ -0,0 +1,268 @@
+use crate::RemovalCause;
+
+use std::collections::VecDeque;
+use std::future::{self, Future};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+
+/// A durable store receiving the entries evicted from a cache, see
+/// [`WriteBehind`].
+///
+/// Implementations own the connection and its error handling: a failed
+/// write is dropped.
+pub trait EvictionSink<K, V> {
+    /// Writes a batch of evicted entries, oldest first.
+    fn write(&mut self, batch: Vec<(K, V)>) -> impl Future<Output = ()>;
+}
+
+struct State<K, V> {
+    queue: VecDeque<(K, V)>,
+    listeners: usize,
+    waker: Option<Waker>,
+    dropped: usize,
+}
+
+struct Shared<K, V> {
+    state: Mutex<State<K, V>>,
+    room: Condvar,
+    buffer: usize,
+    /// Whether a full queue drops evicted entries instead of blocking.
+    lossy: bool,
+}
+
+impl<K, V> Shared<K, V> {
+    fn lock(&self) -> MutexGuard<'_, State<K, V>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Bounded queue between the eviction listener of a cache and an async
+/// [`EvictionSink`], turning the cache into the front of a durable pipeline.
+///
+/// Install [`WriteBehind::listener`] with
+/// [`crate::S3FIFOBuilder::eviction_listener`], then drive
+/// [`WriteBehind::run`] on any executor. Entries evicted for size or rotation
+/// are moved into the queue; removed and replaced entries are not written.
+///
+/// Once `buffer` entries are waiting, the evicting put blocks until the sink
+/// catches up. `run` must therefore make progress on another thread than
+/// the one writing to the cache. Behind a [`crate::ConcurrentS3FIFO`], the
+/// put blocks with its shard locked, stalling every call on that shard, gets
+/// included. Build the queue with [`WriteBehind::lossy`] there to drop the
+/// entries it has no room for instead.
+pub struct WriteBehind<K, V> {
+    shared: Arc<Shared<K, V>>,
+    batch: usize,
+}
+
+impl<K, V> WriteBehind<K, V> {
+    /// Queues up to `buffer` entries and writes them in batches of up to
+    /// `batch` entries. Both are at least 1.
+    #[must_use]
+    pub fn new(buffer: usize, batch: usize) -> Self {
+        Self::with_mode(buffer, batch, false)
+    }
+
+    /// Like [`WriteBehind::new`], but evictions never wait for room in the
+    /// queue: the entries evicted while it's full are dropped and counted by
+    /// [`WriteBehind::dropped`].
+    #[must_use]
+    pub fn lossy(buffer: usize, batch: usize) -> Self {
+        Self::with_mode(buffer, batch, true)
+    }
+
+    fn with_mode(buffer: usize, batch: usize, lossy: bool) -> Self {
+        WriteBehind {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    listeners: 0,
+                    waker: None,
+                    dropped: 0,
+                }),
+                room: Condvar::new(),
+                buffer: buffer.max(1),
+                lossy,
+            }),
+            batch: batch.max(1),
+        }
+    }
+
+    /// Eviction listener feeding the queue. [`WriteBehind::run`] returns once
+    /// every listener is dropped and the queue is written.
+    pub fn listener(&self) -> impl FnMut(&K, V, usize, RemovalCause) + Send + 'static
+    where
+        K: Clone + Send + 'static,
+        V: Send + 'static,
+    {
+        self.shared.lock().listeners += 1;
+        let listener = Listener(Arc::clone(&self.shared));
+        move |key, value, _, cause| {
+            if matches!(cause, RemovalCause::Size | RemovalCause::Rotated) {
+                listener.push(key.clone(), value);
+            }
+        }
+    }
+
+    /// Number of entries waiting to be written.
+    pub fn pending(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    /// Number of evicted entries a [`WriteBehind::lossy`] queue had no room
+    /// for, never written.
+    pub fn dropped(&self) -> usize {
+        self.shared.lock().dropped
+    }
+
+    /// Writes the queued entries to `sink` as they arrive, handing it every
+    /// entry waiting when it's ready for a batch, up to the batch size.
+    pub async fn run<T>(self, mut sink: T)
+    where
+        T: EvictionSink<K, V>,
+    {
+        while let Some(batch) = self.next_batch().await {
+            sink.write(batch).await;
+        }
+    }
+
+    fn next_batch(&self) -> impl Future<Output = Option<Vec<(K, V)>>> + '_ {
+        future::poll_fn(|context| {
+            let mut state = self.shared.lock();
+            if state.queue.is_empty() {
+                if state.listeners == 0 {
+                    return Poll::Ready(None);
+                }
+                state.waker = Some(context.waker().clone());
+                return Poll::Pending;
+            }
+
+            let len = state.queue.len().min(self.batch);
+            let batch = state.queue.drain(..len).collect();
+            self.shared.room.notify_all();
+            Poll::Ready(Some(batch))
+        })
+    }
+}
+
+struct Listener<K, V>(Arc<Shared<K, V>>);
+
+impl<K, V> Listener<K, V> {
+    fn push(&self, key: K, value: V) {
+        let mut state = self.0.lock();
+        if self.0.lossy && state.queue.len() >= self.0.buffer {
+            state.dropped += 1;
+            return;
+        }
+        while state.queue.len() >= self.0.buffer {
+            state = self
+                .0
+                .room
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        state.queue.push_back((key, value));
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<K, V> Drop for Listener<K, V> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.listeners -= 1;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::executor::block_on;
+    use crate::S3FIFO;
+
+    use std::thread;
+
+    type Batches = Arc<Mutex<Vec<Vec<(u32, u32)>>>>;
+
+    #[derive(Default)]
+    struct Sink {
+        batches: Batches,
+    }
+
+    impl EvictionSink<u32, u32> for Sink {
+        fn write(&mut self, batch: Vec<(u32, u32)>) -> impl Future<Output = ()> {
+            self.batches.lock().unwrap().push(batch);
+            future::ready(())
+        }
+    }
+
+    #[test]
+    fn it_should_write_evicted_entries_in_batches() {
+        let write_behind = WriteBehind::new(10, 2);
+        let mut cache = S3FIFO::builder(10)
+            .eviction_listener(write_behind.listener())
+            .build();
+        for key in 0..4 {
+            cache.put(&key, key * 10, 1).unwrap();
+        }
+        cache.remove(&3);
+        drop(cache);
+
+        assert_eq!(write_behind.pending(), 3);
+        let sink = Sink::default();
+        let batches = Arc::clone(&sink.batches);
+        block_on(write_behind.run(sink));
+
+        assert_eq!(
+            *batches.lock().unwrap(),
+            vec![vec![(0, 0), (1, 10)], vec![(2, 20)]]
+        );
+    }
+
+    #[test]
+    fn it_should_block_evictions_until_the_sink_catches_up() {
+        let write_behind = WriteBehind::new(1, 1);
+        let listener = write_behind.listener();
+        let writer = thread::spawn(move || {
+            let mut cache = S3FIFO::builder(10).eviction_listener(listener).build();
+            for key in 0..100 {
+                cache.put(&key, key, 1).unwrap();
+            }
+        });
+
+        let sink = Sink::default();
+        let batches = Arc::clone(&sink.batches);
+        block_on(write_behind.run(sink));
+        writer.join().unwrap();
+
+        let batches = batches.lock().unwrap();
+        assert_eq!(batches.len(), 99);
+        assert!(batches.iter().all(|batch| batch.len() == 1));
+    }
+    #[test]
+    fn it_should_drop_evictions_when_a_lossy_queue_is_full() {
+        let write_behind = WriteBehind::lossy(2, 1);
+        let mut cache = S3FIFO::builder(10)
+            .eviction_listener(write_behind.listener())
+            .build();
+        for key in 0..20 {
+            cache.put(&key, key, 1).unwrap();
+        }
+        drop(cache);
+
+        assert_eq!(write_behind.pending(), 2);
+        assert_eq!(write_behind.dropped(), 17);
+        let sink = Sink::default();
+        let batches = Arc::clone(&sink.batches);
+        block_on(write_behind.run(sink));
+
+        assert_eq!(*batches.lock().unwrap(), vec![vec![(0, 0)], vec![(1, 1)]]);
+    }
+}